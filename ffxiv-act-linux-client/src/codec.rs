@@ -0,0 +1,68 @@
+use std::io::{self, Read};
+
+use flate2::read::DeflateDecoder;
+
+/// Compression the host applies to the entries of each tick frame, identified by the codec id in
+/// the high nibble of the frame flags.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Codec {
+    None,
+    Deflate,
+    Lz4,
+    Zstd,
+}
+
+/// Every codec this client can decode, offered to the host in the handshake.
+pub const SUPPORTED_CODECS: [Codec; 4] = [Codec::Lz4, Codec::Zstd, Codec::Deflate, Codec::None];
+
+impl Codec {
+    pub fn id(self) -> u8 {
+        match self {
+            Codec::None => 0,
+            Codec::Deflate => 1,
+            Codec::Lz4 => 2,
+            Codec::Zstd => 3,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Codec> {
+        match id {
+            0 => Some(Codec::None),
+            1 => Some(Codec::Deflate),
+            2 => Some(Codec::Lz4),
+            3 => Some(Codec::Zstd),
+            _ => None,
+        }
+    }
+
+    pub fn decompress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Codec::None => Ok(data.to_vec()),
+            Codec::Deflate => {
+                let mut inflated = Vec::new();
+                DeflateDecoder::new(data).read_to_end(&mut inflated)?;
+                Ok(inflated)
+            },
+            Codec::Lz4 => lz4_flex::decompress_size_prepended(data)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+            Codec::Zstd => zstd::decode_all(data),
+        }
+    }
+}
+
+#[cfg(test)]
+mod codec_tests {
+    use crate::codec::Codec;
+
+    #[test]
+    fn decompress_frames() {
+        let deflated = [0x63u8, 0x60, 0x64, 0x62, 0x06, 0x00];
+        assert_eq!(Codec::Deflate.decompress(&deflated).unwrap(), vec![0, 1, 2, 3]);
+        let lz4 = [4u8, 0, 0, 0, 0x40, 0, 1, 2, 3];
+        assert_eq!(Codec::Lz4.decompress(&lz4).unwrap(), vec![0, 1, 2, 3]);
+        let zstd = zstd::encode_all(&[0u8, 1, 2, 3][..], 1).unwrap();
+        assert_eq!(Codec::Zstd.decompress(&zstd).unwrap(), vec![0, 1, 2, 3]);
+        assert_eq!(Codec::None.decompress(&[9, 8]).unwrap(), vec![9, 8]);
+        assert!(Codec::Lz4.decompress(&[9, 8]).is_err());
+    }
+}
@@ -0,0 +1,93 @@
+use std::fs::File;
+use std::net::ToSocketAddrs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use ffxiv_act_linux_common::logging::LogConfig;
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Where the host's memory-sync and network-passthrough are. Either may be left out to find
+    /// the host on the LAN instead.
+    #[serde(default)]
+    pub mem_address: Option<String>,
+    #[serde(default)]
+    pub net_address: Option<String>,
+    /// The host's memory-sync port, which host discovery is broadcast to.
+    #[serde(default = "default_discovery_port")]
+    pub discovery_port: u16,
+    #[serde(default)]
+    pub log: LogConfig,
+    /// Where to serve the client's status as JSON, if anywhere, e.g. `127.0.0.1:9263`.
+    #[serde(default)]
+    pub status_address: Option<String>,
+    /// How often to log a summary of what the client has received. 0 turns it off.
+    #[serde(default = "default_summary_interval_s")]
+    pub summary_interval_s: u64,
+}
+
+fn default_discovery_port() -> u16 {
+    7262
+}
+
+fn default_summary_interval_s() -> u64 {
+    60
+}
+
+/// Reads the config file and checks that the addresses in it can be used, returning every problem
+/// found.
+pub fn read_config(path: &Path) -> Result<Config, Vec<String>> {
+    let file = File::open(path).map_err(|e| vec![format!("Couldn't open {}: {}", path.display(), e)])?;
+    let config: Config = serde_json::from_reader(file).map_err(|e| vec![e.to_string()])?;
+    let problems = validate(&config);
+    if problems.is_empty() {
+        Ok(config)
+    } else {
+        Err(problems)
+    }
+}
+
+fn validate(config: &Config) -> Vec<String> {
+    let mut problems = Vec::new();
+    for (name, address) in &[("mem_address", &config.mem_address), ("net_address", &config.net_address), ("status_address", &config.status_address)] {
+        if let Some(address) = address {
+            match address.to_socket_addrs() {
+                Ok(mut addresses) => {
+                    if addresses.next().is_none() {
+                        problems.push(format!("{} \"{}\" resolves to nothing", name, address));
+                    }
+                },
+                Err(e) => problems.push(format!("{} \"{}\" isn't a host and port, e.g. 192.168.122.1:7262: {}", name, address, e)),
+            }
+        }
+    }
+    if config.discovery_port == 0 {
+        problems.push("discovery_port must be the host's memory-sync port, 7262 unless changed".to_string());
+    }
+    problems
+}
+
+#[cfg(test)]
+mod config_tests {
+    use crate::config::*;
+
+    #[test]
+    fn report_every_problem() {
+        let config: Config = serde_json::from_str(r#"{"mem_address": "192.168.122.1", "net_address": "127.0.0.1:54992", "discovery_port": 0, "status_address": "9263"}"#).unwrap();
+        let problems = validate(&config);
+        assert_eq!(problems.len(), 3);
+        assert!(problems[0].starts_with("mem_address \"192.168.122.1\" isn't a host and port"));
+        assert!(problems[1].starts_with("status_address \"9263\" isn't a host and port"));
+        assert!(problems[2].starts_with("discovery_port"));
+
+        let typo = serde_json::from_str::<Config>(r#"{"mem_adress": "192.168.122.1:7262"}"#).unwrap_err();
+        assert!(typo.to_string().starts_with("unknown field `mem_adress`"));
+
+        let config: Config = serde_json::from_str("{}").unwrap();
+        assert_eq!(config, Config { mem_address: None, net_address: None, discovery_port: 7262, log: LogConfig::default(),
+            status_address: None, summary_interval_s: 60 });
+        assert!(validate(&config).is_empty());
+    }
+}
@@ -0,0 +1,83 @@
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+/// Broadcast to find hosts on the LAN.
+const DISCOVERY_MAGIC: [u8; 8] = [68, 73, 83, 67, 55, 50, 54, 50];
+const ANNOUNCEMENT_PACKET_ID: u8 = 0x13;
+
+/// A host that answered a discovery broadcast.
+#[derive(Debug, PartialEq)]
+pub struct DiscoveredHost {
+    pub mem_address: SocketAddr,
+    pub net_address: SocketAddr,
+    pub version: u8,
+}
+
+/// Broadcasts a discovery request to `port` on the LAN and collects the hosts that answer within
+/// `wait`.
+pub fn discover_hosts(port: u16, wait: Duration) -> io::Result<Vec<DiscoveredHost>> {
+    let udp = UdpSocket::bind("0.0.0.0:0")?;
+    udp.set_broadcast(true)?;
+    udp.send_to(&DISCOVERY_MAGIC, ("255.255.255.255", port))?;
+
+    let mut hosts: Vec<DiscoveredHost> = Vec::new();
+    let mut buffer = [0u8; 64];
+    let deadline = Instant::now() + wait;
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+        udp.set_read_timeout(Some(deadline - now))?;
+        match udp.recv_from(&mut buffer) {
+            Ok((num, from)) => {
+                if let Some(host) = parse_announcement(from, &buffer[..num]) {
+                    if hosts.iter().all(|known| known.mem_address != host.mem_address) {
+                        hosts.push(host);
+                    }
+                }
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(hosts)
+}
+
+/// Reads a host's answer, `[0x13][version: u8][memory-sync port: u16][network-passthrough port: u16]`,
+/// sent from the host's address.
+fn parse_announcement(from: SocketAddr, data: &[u8]) -> Option<DiscoveredHost> {
+    let mut cursor = data;
+    if cursor.read_u8().ok()? != ANNOUNCEMENT_PACKET_ID {
+        return None;
+    }
+    let version = cursor.read_u8().ok()?;
+    let mem_port = cursor.read_u16::<LittleEndian>().ok()?;
+    let net_port = cursor.read_u16::<LittleEndian>().ok()?;
+    Some(DiscoveredHost {
+        mem_address: SocketAddr::new(from.ip(), mem_port),
+        net_address: SocketAddr::new(from.ip(), net_port),
+        version,
+    })
+}
+
+#[cfg(test)]
+mod discovery_tests {
+    use crate::discovery::*;
+
+    #[test]
+    fn read_announcement() {
+        let from: SocketAddr = "192.168.122.1:7262".parse().unwrap();
+        let host = parse_announcement(from, &[0x13, 1, 0x5e, 0x1c, 0xd0, 0xd6]).unwrap();
+        assert_eq!(host, DiscoveredHost {
+            mem_address: "192.168.122.1:7262".parse().unwrap(),
+            net_address: "192.168.122.1:54992".parse().unwrap(),
+            version: 1,
+        });
+        assert_eq!(parse_announcement(from, &[0x12, 1, 0x5e, 0x1c, 0xd0, 0xd6]), None);
+        assert_eq!(parse_announcement(from, &[0x13, 1, 0x5e]), None);
+    }
+}
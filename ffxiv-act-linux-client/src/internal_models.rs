@@ -7,9 +7,6 @@ use serde::{Deserialize, Serialize};
 
 use bincode;
 
-use flate2::{GzBuilder, Compression};
-use flate2::read::GzDecoder;
-
 #[derive(Serialize, Deserialize)]
 pub struct Combatant {
     pub name: [u8; 30],
@@ -170,22 +167,8 @@ impl Combatant {
             }).expect("Unable to read combatant")
     }
 
-    #[allow(dead_code)]
-    pub fn binary_serialize_compressed(&self) -> Vec<u8> {
-        let mut ret = Vec::new();
-        let cursor = Cursor::new(&mut ret);
-
-        let mut gz = GzBuilder::new()
-            .write(cursor, Compression::default());
-        bincode::serialize_into(&mut gz, &self).expect("Unable to serialize combatant");
-        gz.finish().expect("Unable to compress combatant");
-        ret
-    }
-
-    pub fn deserialize_binary_compressed(data: Vec<u8>) -> Combatant {
-        let cursor = Cursor::new(data);
-        let mut gz = GzDecoder::new(cursor);
-        bincode::deserialize_from(&mut gz).expect("Unable to deserialize combatant")
+    pub fn deserialize_binary(data: Vec<u8>) -> Combatant {
+        bincode::deserialize(&data).expect("Unable to deserialize combatant")
     }
 
     pub fn as_ffxiv_array(&self) -> [u8; 11520] {
@@ -298,17 +281,15 @@ impl Target {
 #[cfg(test)]
 mod models_tests {
     use crate::internal_models::Combatant;
-    use std::io::{Cursor, Write};
 
     #[test]
     fn combatant_serialize() {
-        let a = Combatant::from_ffxiv_slice(&[0u8; 7308]);
-        let mut compress_vec = Vec::new();
-        let mut cursor = Cursor::new(&mut compress_vec);
-        let mut g = flate2::write::GzEncoder::new(cursor, flate2::Compression::default());
-        g.write_all(bincode::serialize(&a).unwrap().as_ref()).unwrap();
-        g.finish().unwrap();
-        assert_eq!(compress_vec.as_slice(), a.binary_serialize_compressed().as_slice());
+        let mut a = Combatant::from_ffxiv_slice(&[0u8; 7308]);
+        a.id = 0x1040_2A3B;
+        a.current_hp = 90_000;
+        let b = Combatant::deserialize_binary(bincode::serialize(&a).unwrap());
+        assert_eq!((b.id, b.current_hp), (0x1040_2A3B, 90_000));
+        assert_eq!(b.as_ffxiv_array().as_ref(), a.as_ffxiv_array().as_ref());
     }
 
 }
\ No newline at end of file
@@ -1,112 +1,116 @@
 extern crate bincode;
 extern crate flate2;
 extern crate byteorder;
+#[macro_use]
+extern crate log;
+extern crate ffxiv_act_linux_common;
 
 use std::sync::{mpsc, Arc};
 use std::net::{TcpStream, UdpSocket};
-use std::fs::File;
+use std::path::Path;
 use std::io::prelude::*;
 use std::thread;
 
-use serde_json::from_reader;
-use serde::Deserialize;
 
-use byteorder::{ByteOrder, LittleEndian, ReadBytesExt};
+use byteorder::{LittleEndian, ReadBytesExt};
 
 use std::net::ToSocketAddrs;
 
 mod models;
 mod internal_models;
+mod reassembly;
+mod codec;
+mod discovery;
+mod config;
+mod stats;
+mod mirror;
 
 use models::*;
-use std::collections::HashMap;
-use crate::internal_models::Combatant;
+use crate::reassembly::{Reassembler, TickAssembler};
+use crate::codec::{Codec, SUPPORTED_CODECS};
+use crate::stats::Stats;
+use crate::mirror::Mirror;
+use ffxiv_act_linux_common::logging;
 use std::io::Cursor;
-use std::fmt::Display;
 use std::time::{Duration, Instant};
 use std::error::Error;
+use std::borrow::Cow;
 
 
 static mut ALL_MEMORY: AllMemory = AllMemory::create();
 
-fn handle_zone_packet<R: Read>(tcp: &mut R) {
-    let mut zone_buffer = [0u8; 4];
-    tcp.read_exact(&mut zone_buffer).expect("malformed zone packet");
-    let zone = LittleEndian::read_u32(&zone_buffer);
-    unsafe { set_zone(zone); }
+/// The host re-scanned its signatures, so whatever it read before may have come from the wrong
+/// place. Drops every mob until the host sends them again.
+fn handle_signatures_reloaded_packet<R: ReadBytesExt>(data: &mut R, mirror: &mut Mirror) -> std::io::Result<()> {
+    let count = data.read_u8()?;
+    let names = (0..count)
+        .map(|_| {
+            let len = data.read_u8()?;
+            let mut name = vec![0u8; len as usize];
+            data.read_exact(name.as_mut_slice())?;
+            Ok(String::from_utf8_lossy(&name).into_owned())
+        })
+        .collect::<std::io::Result<Vec<_>>>()?;
+    info!(target: "mem", "Host reloaded its memory signatures: {}", names.join(", "));
+    mirror.clear_mobs();
+    Ok(())
 }
 
-fn handle_mob_null_packet<R: ReadBytesExt>(tcp: &mut R, mob_array_heap: &mut HashMap<u16, (u64, Box<[u8; 11520]>)>) {
-    let index = tcp.read_u16::<LittleEndian>().expect("malformed mob delete packet");
-    if index >= 421 {
-        panic!("malformed mob delete packet: oob");
-    }
-    unsafe {
-        ALL_MEMORY.mob_array.data[index as usize] = 0u64;
-    }
-    mob_array_heap.remove(&index);
+/// Reads a fragment datagram into the reassembler, returning the original packet once every
+/// fragment of it has arrived.
+fn handle_fragment(datagram: &[u8], reassembler: &mut Reassembler) -> std::io::Result<Option<Vec<u8>>> {
+    let mut cursor = Cursor::new(&datagram[1..]);
+    let seq = cursor.read_u64::<LittleEndian>()?;
+    let index = cursor.read_u16::<LittleEndian>()?;
+    let count = cursor.read_u16::<LittleEndian>()?;
+    let chunk = &datagram[FRAGMENT_HEADER_SIZE..];
+    Ok(reassembler.push(seq, index, count, chunk))
 }
 
-fn handle_mob_packet<R: ReadBytesExt>(tcp: &mut R, mob_array_heap: &mut HashMap<u16, (u64, Box<[u8; 11520]>)>) {
-    let index = tcp.read_u16::<LittleEndian>().expect("malformed mob packet");
-
-    let pointer = tcp.read_u64::<LittleEndian>().expect("malformed mob packet");
-    let len = tcp.read_u64::<LittleEndian>().expect("malformed mob packet");
-    let mut data = vec![0u8; len as usize];
-    tcp.read_exact(data.as_mut_slice()).unwrap();
-    let combatant = Combatant::deserialize_binary_compressed(data);
-    if index == 0 {
-//        println!("{:x?}", combatant.id);
-    }
-    if mob_array_heap.contains_key(&index) {
-        let (heap_ptr, mob) = mob_array_heap.get_mut(&index).unwrap();
-        if *heap_ptr != pointer {
-            mob_array_heap.remove(&index);
-            let new_mob = Box::new(combatant.as_ffxiv_array());
-            unsafe {
-                ALL_MEMORY.mob_array.data[index as usize] = (new_mob.as_ref() as *const [u8; 11520]) as u64
-            }
-            mob_array_heap.insert(index, (pointer, new_mob));
-        } else {
-            let mut cursor = Cursor::new(mob.as_mut().as_mut());
-            cursor.write_all(combatant.as_ffxiv_array().as_ref()).unwrap();
-        }
-    } else {
-        let new_mob = Box::new(combatant.as_ffxiv_array());
-        unsafe {
-            ALL_MEMORY.mob_array.data[index as usize] = (new_mob.as_ref() as *const [u8; 11520]) as u64;
-        }
-        mob_array_heap.insert(index, (pointer, new_mob));
-    }
+/// Reads a tick frame into the tick assembler, returning the entries of the whole tick once its
+/// last frame has arrived.
+fn handle_frame(frame: &[u8], ticks: &mut TickAssembler) -> std::io::Result<Option<Vec<Vec<u8>>>> {
+    let mut cursor = Cursor::new(&frame[9..]);
+    let tick = cursor.read_u64::<LittleEndian>()?;
+    let index = cursor.read_u16::<LittleEndian>()?;
+    let flags = cursor.read_u8()?;
+    let codec = Codec::from_id(flags >> 4)
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "unknown frame codec"))?;
+    let entries = codec.decompress(&frame[FRAME_HEADER_SIZE..])?;
+    ticks.push(tick, index, flags & FRAME_LAST_IN_TICK != 0, &entries)
 }
 
-fn handle_target_packet<R: ReadBytesExt>(data: &mut R, mob_array_heap: &mut HashMap<u16, (u64, Box<[u8; 11520]>)>) {
-
-    let host_target = data.read_u64::<LittleEndian>().unwrap();
-    let host_hover_target = data.read_u64::<LittleEndian>().unwrap();
-    let host_focus_target = data.read_u64::<LittleEndian>().unwrap();
-    let target = get_client_mob_pointer_from_host(host_target, mob_array_heap);
-    let hover_target = get_client_mob_pointer_from_host(host_hover_target, mob_array_heap);
-    let focus_target = get_client_mob_pointer_from_host(host_focus_target, mob_array_heap);
-    unsafe {
-        ALL_MEMORY.target.target_data.target = target;
-        ALL_MEMORY.target.target_data.hovertarget = hover_target;
-        ALL_MEMORY.target.target_data.focustarget = focus_target;
-    }
-
+/// Builds the handshake sent to the host: the memory-sync magic followed by
+/// `[version: u8][count: u8][codec ids...]`.
+fn encode_hello() -> Vec<u8> {
+    let mut hello = MEMORY_MAGIC.to_vec();
+    hello.push(PROTOCOL_VERSION);
+    hello.push(SUPPORTED_CODECS.len() as u8);
+    hello.extend(SUPPORTED_CODECS.iter().map(|codec| codec.id()));
+    hello
 }
 
-fn handle_server_time_packet<R: ReadBytesExt>(data: &mut R) {
-    let time = data.read_u64::<LittleEndian>().unwrap();
-    unsafe { set_server_time(time) }
+/// Stages a sync packet in the mirror. Nothing reaches ACT until the mirror is committed.
+fn handle_sync_packet<R: ReadBytesExt>(packet_id: u8, data: &mut R, mirror: &mut Mirror, stats: &Stats) {
+    stats.packet(packet_id);
+    match packet_id {
+        0x01 => mirror.zone(data),
+        0x02 => mirror.mob(data),
+        0x03 => mirror.mob_null(data),
+        0x04 => mirror.target(data),
+        0x05 => stats.server_time(mirror.server_time(data)),
+        0x06 => mirror.party_list(data),
+        0x07 => if let Err(e) = handle_signatures_reloaded_packet(data, mirror) {
+            stats.decode_error();
+            warn!(target: "mem", "Ignoring a malformed signatures reloaded packet: {}", e);
+        },
+        _ => panic!("Unknown packet type"),
+    }
+    stats.mobs(mirror.mobs());
 }
 
-fn get_client_mob_pointer_from_host(host_pointer: u64, mob_array_heap: &mut HashMap<u16, (u64, Box<[u8; 11520]>)>) -> u64 {
-    if host_pointer != 0 {
-        let (_, mob) = mob_array_heap.values().find(|(ptr, _)| *ptr == host_pointer).unwrap();
-        let mob_ref = mob.as_ref();
-        (mob_ref as *const [u8; 11520]) as u64
-    } else { 0 }
+fn commit(mirror: &mut Mirror) {
+    unsafe { mirror.commit(&mut *std::ptr::addr_of_mut!(ALL_MEMORY)) }
 }
 
 enum ThreadControlMsg {
@@ -114,6 +118,7 @@ enum ThreadControlMsg {
     Error(ThreadType, Box<dyn Error + Send>),
     UnableToConnect(ThreadType),
     ReadTimeOut(ThreadType),
+    IncompatibleHost(ThreadType, u8),
 }
 
 enum ThreadType {
@@ -121,64 +126,82 @@ enum ThreadType {
     Mem,
 }
 
-impl Display for ThreadType {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+impl ThreadType {
+    /// The log target for messages about the thread.
+    fn target(&self) -> &'static str {
         match self {
-            ThreadType::Mem => write!(f, "[MEM]"),
-            ThreadType::FFXIV => write!(f, "[NET]"),
+            ThreadType::Mem => "mem",
+            ThreadType::FFXIV => "net",
         }
     }
 }
 
 fn main() {
+    let config = config::read_config(Path::new("config.json")).unwrap_or_else(|problems| {
+        eprintln!("Problems with config file config.json:");
+        for problem in problems {
+            eprintln!("  - {}", problem);
+        }
+        std::process::exit(1);
+    });
+    if let Err(e) = logging::init(&config.log, 0) {
+        eprintln!("Unable to start logging: {}", e);
+        std::process::exit(1);
+    }
+
+    let stats = Arc::new(Stats::default());
+    if let Some(status_address) = config.status_address.as_ref() {
+        if let Err(e) = stats::start(stats.clone(), status_address) {
+            error!(target: "stats", "Unable to serve status on {}: {}", status_address, e);
+            std::process::exit(1);
+        }
+    }
+    if config.summary_interval_s > 0 {
+        stats::log_summaries(stats.clone(), Duration::from_secs(config.summary_interval_s));
+    }
+
     'outer: loop {
         unsafe { setup_memory(); }
 
-        let config: Config = {
-            let file_res = File::open("config.json");
-            if let Ok(file) = file_res {
-                match from_reader(file) {
-                    Ok(conf) => conf,
-                    Err(e) => {
-                        eprintln!("Unable to read / parse config file: {}", e.description());
-                        std::process::exit(1);
-                    }
-                }
-            } else {
-                eprintln!("Unable to open config file.");
-                std::process::exit(1);
-            }
+        let (mem_address, net_address) = match (config.mem_address.clone(), config.net_address.clone()) {
+            (Some(mem_address), Some(net_address)) => (mem_address, net_address),
+            (mem_address, net_address) => {
+                let host = find_host(config.discovery_port);
+                (mem_address.unwrap_or_else(|| host.mem_address.to_string()), net_address.unwrap_or_else(|| host.net_address.to_string()))
+            },
         };
 
-
         let (thread_ctl_tx, thread_ctl_rx) = mpsc::channel();
 
-        start_ffxiv_client(config.net_address, thread_ctl_tx.clone());
-        start_mem_sync_client(config.mem_address, thread_ctl_tx.clone());
+        start_ffxiv_client(net_address, thread_ctl_tx.clone());
+        start_mem_sync_client(mem_address, thread_ctl_tx.clone(), stats.clone());
 
         for msg in thread_ctl_rx {
             match msg {
                 ThreadControlMsg::Ending(t) => {
-                    println!("{} Channel disconnected.", t);
+                    info!(target: t.target(), "Channel disconnected.");
                     break 'outer;
                 },
                 ThreadControlMsg::UnableToConnect(t) => {
-                    println!("{} Unable to connect to host.", t);
+                    error!(target: t.target(), "Unable to connect to host.");
                     break 'outer;
                 },
                 ThreadControlMsg::ReadTimeOut(t) => {
-                    println!("{} Read timed out.", t);
+                    error!(target: t.target(), "Read timed out.");
+                    break 'outer;
+                },
+                ThreadControlMsg::IncompatibleHost(t, version) => {
+                    error!(target: t.target(), "Host uses protocol version {}, but this client uses {}. Update both to the same release.", version, PROTOCOL_VERSION);
                     break 'outer;
                 },
                 ThreadControlMsg::Error(t, err) => {
-                    println!("{} Errored!", t);
-                    eprintln!("{:?}", err);
+                    error!(target: t.target(), "Errored! {:?}", err);
                     break 'outer;
                 }
             }
         }
         unsafe {
-            println!("Memory sync bank ptr: {:p}", &ALL_MEMORY as *const AllMemory)
+            info!(target: "client", "Memory sync bank ptr: {:p}", &ALL_MEMORY as *const AllMemory)
         }
     }
 
@@ -190,7 +213,7 @@ fn start_ffxiv_client(addr: String, thread_ctl: mpsc::Sender<ThreadControlMsg>)
     thread::spawn(move || {
         let addr = addr.to_socket_addrs().unwrap().next().unwrap();
         if let Ok(mut tcp_ffxiv) = TcpStream::connect(addr) {
-            println!("[NET] Connected FFXIV-passthrough client.");
+            info!(target: "net", "Connected FFXIV-passthrough client.");
             let mut byte_buffer_ffxiv = [0u8; 32768];
             loop {
                 let read = tcp_ffxiv.read(&mut byte_buffer_ffxiv).unwrap();
@@ -207,10 +230,21 @@ fn start_ffxiv_client(addr: String, thread_ctl: mpsc::Sender<ThreadControlMsg>)
 
 const MEMORY_MAGIC:[u8; 8] = [7,2,6,2,2,5,4,4];
 const KEEP_ALIVE_MAGIC:[u8; 8] = [123,157,225,223,116,254,178,126];
+const FRAGMENT_PACKET_ID: u8 = 0x10;
+const FRAGMENT_HEADER_SIZE: usize = 13;
+const FRAME_PACKET_ID: u8 = 0x11;
+const FRAME_HEADER_SIZE: usize = 20;
+const FRAME_LAST_IN_TICK: u8 = 0x01;
+const HANDSHAKE_PACKET_ID: u8 = 0x12;
 
-fn start_mem_sync_client(addr: String, thread_ctl: mpsc::Sender<ThreadControlMsg>) {
+/// Bumped whenever the memory-sync wire format changes incompatibly.
+const PROTOCOL_VERSION: u8 = 2;
+
+fn start_mem_sync_client(addr: String, thread_ctl: mpsc::Sender<ThreadControlMsg>, stats: Arc<Stats>) {
     thread::spawn(move || {
-        let mut mob_array_heap: HashMap<u16, (u64, Box<[u8; 11520]>)> = HashMap::new();
+        let mut mirror = Mirror::new();
+        let mut reassembler = Reassembler::new();
+        let mut ticks = TickAssembler::new();
         let mut buffer = [0u8; 12000];
         let addr = {
             if let Ok(mut addrs) = addr.to_socket_addrs() {
@@ -229,8 +263,8 @@ fn start_mem_sync_client(addr: String, thread_ctl: mpsc::Sender<ThreadControlMsg
         let udp_client = Arc::new(UdpSocket::bind("0.0.0.0:0").unwrap());
         udp_client.set_nonblocking(true).unwrap();
         udp_client.connect(addr).unwrap();
-        udp_client.send(&MEMORY_MAGIC).unwrap();
-        println!("[MEM] Opened UDP memory-sync socket and attempting to connect to host...");
+        udp_client.send(&encode_hello()).unwrap();
+        info!(target: "mem", "Opened UDP memory-sync socket and attempting to connect to host...");
         let mut has_recv = false;
         let udp2 = udp_client.clone();
         thread::spawn(move || {
@@ -248,26 +282,73 @@ fn start_mem_sync_client(addr: String, thread_ctl: mpsc::Sender<ThreadControlMsg
                 Ok(num) => {
                     if num > 0 {
                         if !has_recv {
-                            println!("[MEM] UDP memory-sync connection validated.");
+                            info!(target: "mem", "UDP memory-sync connection validated.");
                             has_recv = true;
+                            stats.connected(true);
                         }
                         last_recv = Some(Instant::now());
-                        let mut cursor = Cursor::new(&buffer[1..]);
+                        let datagram = &buffer[..num];
+                        if datagram[0] == HANDSHAKE_PACKET_ID {
+                            if num < 11 {
+                                stats.decode_error();
+                                warn!(target: "mem", "Ignoring a {} byte handshake reply, which is too short to read.", num);
+                                continue 'mem;
+                            }
+                            let version = datagram[9];
+                            if version != PROTOCOL_VERSION {
+                                thread_ctl.send(ThreadControlMsg::IncompatibleHost(ThreadType::Mem, version)).unwrap();
+                                break 'mem;
+                            }
+                            match Codec::from_id(datagram[10]) {
+                                Some(codec) => info!(target: "mem", "Host accepted handshake, using {:?} compression.", codec),
+                                None => info!(target: "mem", "Host accepted handshake with an unknown codec."),
+                            }
+                            continue 'mem;
+                        }
+                        let packet = if datagram[0] == FRAGMENT_PACKET_ID {
+                            match handle_fragment(datagram, &mut reassembler) {
+                                Ok(Some(packet)) => Cow::Owned(packet),
+                                Ok(None) => continue 'mem,
+                                Err(err) => {
+                                    stats.decode_error();
+                                    thread_ctl.send(ThreadControlMsg::Error(ThreadType::Mem, Box::new(err))).unwrap();
+                                    break 'mem;
+                                }
+                            }
+                        } else {
+                            Cow::Borrowed(datagram)
+                        };
+                        let mut cursor = Cursor::new(&packet[1..]);
                         let seq = cursor.read_u64::<LittleEndian>();
                         if seq.is_err() {
+                            stats.decode_error();
                             thread_ctl.send(ThreadControlMsg::Error(ThreadType::Mem, Box::new(seq.unwrap_err()))).unwrap();
                             break 'mem;
                         }
                         let seq = seq.unwrap();
                         if seq > last_seq {
+                            stats.frame(seq, last_seq);
                             last_seq = seq;
-                            match buffer[0] {
-                                0x01 => handle_zone_packet(&mut cursor),
-                                0x02 => handle_mob_packet(&mut cursor, &mut mob_array_heap),
-                                0x03 => handle_mob_null_packet(&mut cursor, &mut mob_array_heap),
-                                0x04 => handle_target_packet(&mut cursor, &mut mob_array_heap),
-                                0x05 => handle_server_time_packet(&mut cursor),
-                                _ => panic!("Unknown packet type"),
+                            reassembler.discard_through(seq);
+                            if packet[0] == FRAME_PACKET_ID {
+                                match handle_frame(&packet, &mut ticks) {
+                                    Ok(Some(entries)) => {
+                                        for entry in entries {
+                                            let mut entry_cursor = Cursor::new(&entry[1..]);
+                                            handle_sync_packet(entry[0], &mut entry_cursor, &mut mirror, &stats);
+                                        }
+                                        commit(&mut mirror);
+                                    },
+                                    Ok(None) => {},
+                                    Err(err) => {
+                                        stats.decode_error();
+                                        thread_ctl.send(ThreadControlMsg::Error(ThreadType::Mem, Box::new(err))).unwrap();
+                                        break 'mem;
+                                    }
+                                }
+                            } else {
+                                handle_sync_packet(packet[0], &mut cursor, &mut mirror, &stats);
+                                commit(&mut mirror);
                             }
                         }
                     } else {
@@ -290,14 +371,31 @@ fn start_mem_sync_client(addr: String, thread_ctl: mpsc::Sender<ThreadControlMsg
                 }
             }
         }
+        stats.connected(false);
         thread_ctl.send(ThreadControlMsg::Ending(ThreadType::Mem)).unwrap();
     });
 }
 
-#[derive(Deserialize)]
-struct Config {
-    pub mem_address: String,
-    pub net_address: String,
+/// Broadcasts for hosts until one that speaks our protocol version answers.
+fn find_host(port: u16) -> discovery::DiscoveredHost {
+    info!(target: "mem", "No host address configured, looking for a host on port {}...", port);
+    loop {
+        match discovery::discover_hosts(port, Duration::from_secs(2)) {
+            Ok(hosts) => {
+                let (compatible, incompatible): (Vec<_>, Vec<_>) = hosts.into_iter()
+                    .partition(|host| host.version == PROTOCOL_VERSION);
+                for host in incompatible {
+                    warn!(target: "mem", "Ignoring host at {} using protocol version {} (client uses {})", host.mem_address, host.version, PROTOCOL_VERSION);
+                }
+                if let Some(host) = compatible.into_iter().next() {
+                    info!(target: "mem", "Found host at {}", host.mem_address.ip());
+                    return host;
+                }
+            },
+            Err(e) => warn!(target: "mem", "Unable to broadcast for hosts: {}", e),
+        }
+        thread::sleep(Duration::from_secs(3));
+    }
 }
 
 unsafe fn setup_memory() {
@@ -306,10 +404,16 @@ unsafe fn setup_memory() {
     ALL_MEMORY.server_time.ptr = (&SERVER_1) as *const ServerTimePart1 as u64;
 }
 
-unsafe fn set_zone(zone: u32) {
-    ALL_MEMORY.zone_id.data = zone;
+#[cfg(test)]
+mod main_tests {
+    use crate::*;
+
+    #[test]
+    fn count_malformed_signatures_reloaded_packets() {
+        let (mut mirror, stats) = (Mirror::new(), Stats::default());
+        handle_sync_packet(0x07, &mut Cursor::new([1u8, 9, b'z']), &mut mirror, &stats);
+        assert_eq!(stats.snapshot().decode_errors, 1);
+        handle_sync_packet(0x07, &mut Cursor::new([1u8, 7, b'z', b'o', b'n', b'e', b'_', b'i', b'd']), &mut mirror, &stats);
+        assert_eq!(stats.snapshot().decode_errors, 1);
+    }
 }
-
-unsafe fn set_server_time(server_time: u64) {
-    SERVER_3.data = server_time
-}
\ No newline at end of file
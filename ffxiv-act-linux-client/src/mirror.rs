@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+use std::io::Read;
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use crate::internal_models::Combatant;
+use crate::models::{AllMemory, SERVER_3};
+
+const MOB_SIZE: usize = 11520;
+const MOB_SLOTS: usize = 421;
+const PARTY_LIST_SIZE: usize = 25600;
+
+type Mob = Box<[u8; MOB_SIZE]>;
+
+/// One slot of the mob array. The mob is kept twice: updates are written to the copy ACT isn't
+/// pointed at, and the slot is pointed at that copy when the tick is committed.
+struct MobSlot {
+    /// Where the host read the mob from, which targets are given as.
+    host_pointer: u64,
+    copies: [Mob; 2],
+    current: usize,
+    /// Whether the other copy has been written since the last commit.
+    written: bool,
+}
+
+impl MobSlot {
+    fn pointer(&self) -> u64 {
+        (self.copies[self.current].as_ref() as *const [u8; MOB_SIZE]) as u64
+    }
+}
+
+/// What the host has sent, staged a tick at a time. ACT reads `ALL_MEMORY` while the client writes
+/// it, so a tick's entries are all applied here and only then committed together, rather than
+/// leaving ACT to find a mob array that is half one tick and half the next.
+pub struct Mirror {
+    mobs: HashMap<u16, MobSlot>,
+    /// Slots dropped since the last commit. ACT may still be reading them, so they are kept until
+    /// the commit after the one that drops them from the mob array.
+    removed: Vec<MobSlot>,
+    retired: Vec<MobSlot>,
+    /// The host's target, hover target and focus target, pointed at our own copies on each commit.
+    targets: [u64; 3],
+    zone: Option<u32>,
+    party_list: Option<Vec<u8>>,
+    server_time: Option<u64>,
+}
+
+impl Mirror {
+    pub fn new() -> Mirror {
+        Mirror {
+            mobs: HashMap::new(),
+            removed: Vec::new(),
+            retired: Vec::new(),
+            targets: [0; 3],
+            zone: None,
+            party_list: None,
+            server_time: None,
+        }
+    }
+
+    pub fn mobs(&self) -> usize {
+        self.mobs.len()
+    }
+
+    pub fn zone<R: Read>(&mut self, data: &mut R) {
+        self.zone = Some(data.read_u32::<LittleEndian>().expect("malformed zone packet"));
+    }
+
+    pub fn mob<R: Read>(&mut self, data: &mut R) {
+        let index = data.read_u16::<LittleEndian>().expect("malformed mob packet");
+        if index as usize >= MOB_SLOTS {
+            panic!("malformed mob packet: oob");
+        }
+        let pointer = data.read_u64::<LittleEndian>().expect("malformed mob packet");
+        let len = data.read_u64::<LittleEndian>().expect("malformed mob packet");
+        let mut mob = vec![0u8; len as usize];
+        data.read_exact(mob.as_mut_slice()).expect("malformed mob packet");
+        let mob = Combatant::deserialize_binary(mob).as_ffxiv_array();
+
+        match self.mobs.get_mut(&index) {
+            Some(slot) => {
+                slot.host_pointer = pointer;
+                *slot.copies[1 - slot.current] = mob;
+                slot.written = true;
+            },
+            None => {
+                // A new slot isn't in the mob array until the commit, so it can start out current.
+                let slot = MobSlot { host_pointer: pointer, copies: [Box::new(mob), Box::new([0u8; MOB_SIZE])], current: 0, written: false };
+                self.mobs.insert(index, slot);
+            },
+        }
+    }
+
+    pub fn mob_null<R: Read>(&mut self, data: &mut R) {
+        let index = data.read_u16::<LittleEndian>().expect("malformed mob delete packet");
+        if index as usize >= MOB_SLOTS {
+            panic!("malformed mob delete packet: oob");
+        }
+        self.removed.extend(self.mobs.remove(&index));
+    }
+
+    pub fn target<R: Read>(&mut self, data: &mut R) {
+        for target in self.targets.iter_mut() {
+            *target = data.read_u64::<LittleEndian>().expect("malformed target packet");
+        }
+    }
+
+    pub fn server_time<R: Read>(&mut self, data: &mut R) -> u64 {
+        let server_time = data.read_u64::<LittleEndian>().expect("malformed server time packet");
+        self.server_time = Some(server_time);
+        server_time
+    }
+
+    pub fn party_list<R: Read>(&mut self, data: &mut R) {
+        let len = data.read_u64::<LittleEndian>().expect("malformed party list packet");
+        let mut party_list = vec![0u8; len as usize];
+        data.read_exact(party_list.as_mut_slice()).expect("malformed party list packet");
+        self.party_list = Some(party_list);
+    }
+
+    /// Drops every mob, as whatever the host read before may have come from the wrong place.
+    pub fn clear_mobs(&mut self) {
+        self.removed.extend(self.mobs.drain().map(|(_, slot)| slot));
+    }
+
+    /// Writes everything staged since the last commit to `memory`.
+    pub fn commit(&mut self, memory: &mut AllMemory) {
+        for slot in self.mobs.values_mut().filter(|slot| slot.written) {
+            slot.current = 1 - slot.current;
+            slot.written = false;
+        }
+        let mut pointers = [0u64; MOB_SLOTS];
+        for (index, slot) in &self.mobs {
+            pointers[*index as usize] = slot.pointer();
+        }
+        // Targets and mobs are read at different rates, so the host may name a mob this client
+        // hasn't received yet. It'll be pointed at properly once it has.
+        let [target, hover_target, focus_target] = self.targets.map(|host_pointer| {
+            self.mobs.values()
+                .find(|slot| host_pointer != 0 && slot.host_pointer == host_pointer)
+                .map(MobSlot::pointer)
+                .unwrap_or(0)
+        });
+
+        memory.mob_array.data = pointers;
+        memory.target.target_data.target = target;
+        memory.target.target_data.hovertarget = hover_target;
+        memory.target.target_data.focustarget = focus_target;
+        if let Some(zone) = self.zone.take() {
+            memory.zone_id.data = zone;
+        }
+        if let Some(party_list) = self.party_list.take() {
+            let len = party_list.len().min(PARTY_LIST_SIZE);
+            memory.party_list.data[..len].copy_from_slice(&party_list[..len]);
+        }
+        if let Some(server_time) = self.server_time.take() {
+            unsafe { SERVER_3.data = server_time }
+        }
+        // Those dropped at the last commit have been out of the mob array for a whole tick.
+        self.retired.clear();
+        self.retired.append(&mut self.removed);
+    }
+}
+
+#[cfg(test)]
+mod mirror_tests {
+    use crate::mirror::*;
+    use byteorder::WriteBytesExt;
+    use std::io::Cursor;
+
+    fn mob_packet(index: u16, host_pointer: u64, hp: u32) -> Vec<u8> {
+        let mut mob = Combatant::from_ffxiv_slice(&[0u8; 7308]);
+        mob.current_hp = hp;
+        let mob = bincode::serialize(&mob).unwrap();
+        let mut packet = Vec::new();
+        packet.write_u16::<LittleEndian>(index).unwrap();
+        packet.write_u64::<LittleEndian>(host_pointer).unwrap();
+        packet.write_u64::<LittleEndian>(mob.len() as u64).unwrap();
+        packet.extend(mob);
+        packet
+    }
+
+    fn hp(memory: &AllMemory, index: usize) -> u32 {
+        let mob = unsafe { &*(memory.mob_array.data[index] as *const [u8; MOB_SIZE]) };
+        Combatant::from_ffxiv_slice(mob).current_hp
+    }
+
+    #[test]
+    fn commit_whole_ticks() {
+        let mut memory = Box::new(AllMemory::create());
+        let mut mirror = Mirror::new();
+        mirror.mob(&mut Cursor::new(mob_packet(0, 0x1000, 100)));
+        mirror.mob(&mut Cursor::new(mob_packet(1, 0x2000, 200)));
+        mirror.target(&mut Cursor::new([0x2000u64.to_le_bytes(), [0; 8], [0; 8]].concat()));
+        assert_eq!({ memory.mob_array.data }[0], 0);
+        mirror.commit(&mut memory);
+        assert_eq!((hp(&memory, 0), hp(&memory, 1)), (100, 200));
+        assert_eq!({ memory.target.target_data.target }, { memory.mob_array.data }[1]);
+
+        // Nothing ACT can see changes until the whole tick is committed.
+        let before = { memory.mob_array.data };
+        mirror.mob(&mut Cursor::new(mob_packet(0, 0x1000, 90)));
+        mirror.mob_null(&mut Cursor::new(1u16.to_le_bytes()));
+        assert_eq!({ memory.mob_array.data }, before);
+        assert_eq!(hp(&memory, 0), 100);
+        mirror.commit(&mut memory);
+        assert_eq!(hp(&memory, 0), 90);
+        assert_ne!({ memory.mob_array.data }[0], before[0]);
+        assert_eq!(({ memory.mob_array.data }[1], { memory.target.target_data.target }), (0, 0));
+        assert_eq!(mirror.mobs(), 1);
+    }
+}
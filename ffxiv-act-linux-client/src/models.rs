@@ -77,7 +77,7 @@ pub struct MobArray {
 pub struct PartyList {
     signature: [u8; 21],
     offset: [u8; 4],
-    data: [u8; 25600],
+    pub data: [u8; 25600],
 }
 
 #[repr(C, packed)]
@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+/// Fragmented sync packets are discarded once this many newer packets have been handled without
+/// the missing fragments arriving.
+const MAX_PENDING_PACKETS: usize = 32;
+
+struct PartialPacket {
+    chunks: Vec<Option<Vec<u8>>>,
+    received: usize,
+}
+
+/// Collects the fragments of sync packets too large for a single datagram and hands back the
+/// original packet once every fragment of it has arrived.
+pub struct Reassembler {
+    pending: HashMap<u64, PartialPacket>,
+}
+
+impl Reassembler {
+    pub fn new() -> Reassembler {
+        Reassembler { pending: HashMap::new() }
+    }
+
+    /// Adds fragment `index` of `count` for the packet with sequence `seq`. Returns the complete
+    /// encoded packet when this was the last missing fragment.
+    pub fn push(&mut self, seq: u64, index: u16, count: u16, chunk: &[u8]) -> Option<Vec<u8>> {
+        if count == 0 || index >= count {
+            return None;
+        }
+        let partial = self.pending.entry(seq).or_insert_with(|| PartialPacket {
+            chunks: vec![None; count as usize],
+            received: 0,
+        });
+        if partial.chunks.len() != count as usize {
+            return None;
+        }
+        let slot = &mut partial.chunks[index as usize];
+        if slot.is_none() {
+            *slot = Some(chunk.to_vec());
+            partial.received += 1;
+        }
+
+        if partial.received == partial.chunks.len() {
+            let partial = self.pending.remove(&seq).unwrap();
+            Some(partial.chunks.into_iter().flat_map(|chunk| chunk.unwrap()).collect())
+        } else {
+            self.evict_stale(seq);
+            None
+        }
+    }
+
+    /// Drops any partially received packet that is older than `seq`, since the client would
+    /// ignore it as out of date once complete anyway.
+    pub fn discard_through(&mut self, seq: u64) {
+        self.pending.retain(|pending_seq, _| *pending_seq > seq);
+    }
+
+    fn evict_stale(&mut self, newest: u64) {
+        if self.pending.len() > MAX_PENDING_PACKETS {
+            let cutoff = newest.saturating_sub(MAX_PENDING_PACKETS as u64);
+            self.pending.retain(|pending_seq, _| *pending_seq > cutoff);
+        }
+    }
+}
+
+/// Buffers the frames of one reader tick until the frame marked as the last of the tick arrives,
+/// so the whole tick can be applied at once.
+pub struct TickAssembler {
+    tick: Option<u64>,
+    frames_received: u16,
+    entries: Vec<Vec<u8>>,
+}
+
+impl TickAssembler {
+    pub fn new() -> TickAssembler {
+        TickAssembler { tick: None, frames_received: 0, entries: Vec::new() }
+    }
+
+    /// Adds frame `index` of `tick`, whose entries are `[len: u16][packet id][packet body...]`.
+    /// Returns every entry of the tick once its last frame has arrived. If any frame of the tick
+    /// went missing the tick is dropped instead; the next one will carry a complete snapshot.
+    pub fn push(&mut self, tick: u64, index: u16, last: bool, mut entries: &[u8]) -> std::io::Result<Option<Vec<Vec<u8>>>> {
+        if self.tick != Some(tick) {
+            self.tick = Some(tick);
+            self.frames_received = 0;
+            self.entries.clear();
+        }
+        while !entries.is_empty() {
+            let len = entries.read_u16::<LittleEndian>()? as usize;
+            if len == 0 || len > entries.len() {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "malformed frame entry"));
+            }
+            self.entries.push(entries[..len].to_vec());
+            entries = &entries[len..];
+        }
+        self.frames_received += 1;
+
+        if last {
+            let complete = self.frames_received == index + 1;
+            self.tick = None;
+            let entries = std::mem::take(&mut self.entries);
+            Ok(if complete { Some(entries) } else { None })
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod reassembly_tests {
+    use crate::reassembly::{Reassembler, TickAssembler};
+
+    #[test]
+    fn reassemble_out_of_order() {
+        let mut reassembler = Reassembler::new();
+        assert_eq!(reassembler.push(9, 2, 3, &[5, 6]), None);
+        assert_eq!(reassembler.push(9, 0, 3, &[1, 2]), None);
+        assert_eq!(reassembler.push(9, 0, 3, &[1, 2]), None);
+        assert_eq!(reassembler.push(9, 1, 3, &[3, 4]), Some(vec![1, 2, 3, 4, 5, 6]));
+    }
+
+    #[test]
+    fn discard_old_fragments() {
+        let mut reassembler = Reassembler::new();
+        assert_eq!(reassembler.push(4, 0, 2, &[1]), None);
+        reassembler.discard_through(4);
+        assert_eq!(reassembler.push(4, 1, 2, &[2]), None);
+        assert_eq!(reassembler.push(4, 0, 2, &[1]), Some(vec![1, 2]));
+    }
+
+    #[test]
+    fn assemble_tick() {
+        let mut ticks = TickAssembler::new();
+        assert_eq!(ticks.push(7, 0, false, &[2, 0, 3, 0xe8, 1, 0, 9]).unwrap(), None);
+        let tick = ticks.push(7, 1, true, &[1, 0, 4]).unwrap();
+        assert_eq!(tick, Some(vec![vec![3, 0xe8], vec![9], vec![4]]));
+    }
+
+    #[test]
+    fn drop_incomplete_tick() {
+        let mut ticks = TickAssembler::new();
+        assert_eq!(ticks.push(7, 0, false, &[1, 0, 9]).unwrap(), None);
+        assert_eq!(ticks.push(8, 1, true, &[1, 0, 4]).unwrap(), None);
+        assert_eq!(ticks.push(9, 0, true, &[1, 0, 5]).unwrap(), Some(vec![vec![5]]));
+        assert!(ticks.push(10, 0, true, &[4, 0, 5]).is_err());
+    }
+}
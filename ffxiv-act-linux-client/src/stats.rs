@@ -0,0 +1,180 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use ffxiv_act_linux_common::http;
+use serde::Serialize;
+
+/// What the memory-sync connection has received, for telling whether ACT is seeing fresh data.
+/// Kept across reconnects.
+#[derive(Default)]
+pub struct Stats {
+    counts: Mutex<Counts>,
+}
+
+#[derive(Default)]
+struct Counts {
+    connected: bool,
+    packets: BTreeMap<&'static str, u64>,
+    sequence_gaps: u64,
+    decode_errors: u64,
+    mobs: usize,
+    last_update: Option<Instant>,
+    /// The last server time received, and when.
+    server_time: Option<(u64, Instant)>,
+}
+
+/// The stats at one moment, as served by the status endpoint.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Snapshot {
+    pub connected: bool,
+    /// Sync packets handled, by type.
+    pub packets: BTreeMap<&'static str, u64>,
+    /// Frames the host sent that never arrived.
+    pub sequence_gaps: u64,
+    pub decode_errors: u64,
+    /// Mobs currently in the mob array.
+    pub mobs: usize,
+    /// How long ago the last frame arrived.
+    pub last_update_ms: Option<u64>,
+    /// How far the game's server time, as last received, is behind this machine's clock. The game
+    /// counts it in whole seconds, so this is only accurate to a second.
+    pub server_time_lag_s: Option<i64>,
+}
+
+/// The name of each sync packet type.
+fn packet_name(packet_id: u8) -> &'static str {
+    match packet_id {
+        0x01 => "zone",
+        0x02 => "mob",
+        0x03 => "mob_null",
+        0x04 => "target",
+        0x05 => "server_time",
+        0x06 => "party_list",
+        0x07 => "signatures_reloaded",
+        _ => "unknown",
+    }
+}
+
+impl Stats {
+    pub fn connected(&self, connected: bool) {
+        self.counts.lock().unwrap().connected = connected;
+    }
+
+    pub fn packet(&self, packet_id: u8) {
+        *self.counts.lock().unwrap().packets.entry(packet_name(packet_id)).or_insert(0) += 1;
+    }
+
+    /// Records a frame numbered `seq` arriving after the one numbered `last_seq`. The host numbers
+    /// frames consecutively, so any numbers skipped over were lost.
+    pub fn frame(&self, seq: u64, last_seq: u64) {
+        let mut counts = self.counts.lock().unwrap();
+        if last_seq != 0 && seq > last_seq + 1 {
+            counts.sequence_gaps += seq - last_seq - 1;
+        }
+        counts.last_update = Some(Instant::now());
+    }
+
+    pub fn decode_error(&self) {
+        self.counts.lock().unwrap().decode_errors += 1;
+    }
+
+    pub fn mobs(&self, mobs: usize) {
+        self.counts.lock().unwrap().mobs = mobs;
+    }
+
+    pub fn server_time(&self, server_time: u64) {
+        self.counts.lock().unwrap().server_time = Some((server_time, Instant::now()));
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        let unix_now = SystemTime::now().duration_since(UNIX_EPOCH).map(|now| now.as_secs()).unwrap_or(0);
+        self.snapshot_at(Instant::now(), unix_now)
+    }
+
+    fn snapshot_at(&self, now: Instant, unix_now: u64) -> Snapshot {
+        let counts = self.counts.lock().unwrap();
+        Snapshot {
+            connected: counts.connected,
+            packets: counts.packets.clone(),
+            sequence_gaps: counts.sequence_gaps,
+            decode_errors: counts.decode_errors,
+            mobs: counts.mobs,
+            last_update_ms: counts.last_update.map(|last| now.saturating_duration_since(last).as_millis() as u64),
+            // The server time was as old as it is now when it was received.
+            server_time_lag_s: counts.server_time.map(|(server_time, received)| {
+                unix_now as i64 - now.saturating_duration_since(received).as_secs() as i64 - server_time as i64
+            }),
+        }
+    }
+}
+
+impl Snapshot {
+    /// A one line summary for the log.
+    pub fn summary(&self) -> String {
+        let total = self.packets.values().sum::<u64>();
+        let by_type = self.packets.iter().map(|(name, count)| format!("{} {}", name, count)).collect::<Vec<_>>();
+        let ms = |value: Option<u64>| value.map(|value| format!("{} ms ago", value)).unwrap_or_else(|| "never".to_string());
+        let lag = self.server_time_lag_s.map(|lag| format!("{} s", lag)).unwrap_or_else(|| "unknown".to_string());
+        format!("{}, {} packets ({}), {} lost, {} decode errors, {} mobs, last update {}, server time lag {}",
+            if self.connected { "connected" } else { "disconnected" }, total, by_type.join(", "),
+            self.sequence_gaps, self.decode_errors, self.mobs, ms(self.last_update_ms), lag)
+    }
+}
+
+/// Logs a summary of `stats` every `interval`.
+pub fn log_summaries(stats: Arc<Stats>, interval: Duration) {
+    thread::spawn(move || {
+        loop {
+            thread::sleep(interval);
+            info!(target: "stats", "{}", stats.snapshot().summary());
+        }
+    });
+}
+
+/// Serves `stats` as JSON over HTTP on `address`, at `/status`.
+pub fn start(stats: Arc<Stats>, address: &str) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    info!(target: "stats", "Serving status on http://{}/status", address);
+    let page = http::Page { path: "/status", content_type: "application/json", target: "stats" };
+    thread::spawn(move || http::serve(listener, page, move || Ok(serde_json::to_string(&stats.snapshot())?)));
+    Ok(())
+}
+
+#[cfg(test)]
+mod stats_tests {
+    use crate::stats::*;
+
+    #[test]
+    fn count_packets_and_gaps() {
+        let stats = Stats::default();
+        stats.connected(true);
+        stats.frame(1, 0);
+        stats.frame(2, 1);
+        stats.frame(6, 2);
+        for packet_id in &[0x02, 0x02, 0x03, 0x05] {
+            stats.packet(*packet_id);
+        }
+        stats.decode_error();
+        stats.mobs(12);
+        stats.server_time(1_700_000_000);
+
+        let snapshot = stats.snapshot_at(Instant::now() + Duration::from_millis(1500), 1_700_000_003);
+        assert_eq!(snapshot.sequence_gaps, 3);
+        assert_eq!(snapshot.decode_errors, 1);
+        assert_eq!(snapshot.mobs, 12);
+        assert!(snapshot.last_update_ms.unwrap() >= 1500);
+        assert_eq!(snapshot.server_time_lag_s, Some(2));
+        assert_eq!(snapshot.summary(), format!("connected, 4 packets (mob 2, mob_null 1, server_time 1), 3 lost, 1 decode errors, \
+12 mobs, last update {} ms ago, server time lag 2 s", snapshot.last_update_ms.unwrap()));
+
+        assert_eq!(Stats::default().snapshot().summary(),
+            "disconnected, 0 packets (), 0 lost, 0 decode errors, 0 mobs, last update never, server time lag unknown");
+        let stats = Stats::default();
+        stats.packet(0x01);
+        assert_eq!(serde_json::to_string(&stats.snapshot()).unwrap(), r#"{"connected":false,"packets":{"zone":1},"sequence_gaps":0,"decode_errors":0,"mobs":0,"last_update_ms":null,"server_time_lag_s":null}"#);
+    }
+}
@@ -0,0 +1,75 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+/// A single page served over HTTP, such as the host's metrics or the client's status.
+pub struct Page {
+    pub path: &'static str,
+    pub content_type: &'static str,
+    /// The log target problems answering requests are logged to.
+    pub target: &'static str,
+}
+
+/// Answers requests on `listener` for as long as it's open, with `page` rendered by `render`.
+pub fn serve<F: Fn() -> io::Result<String>>(listener: TcpListener, page: Page, render: F) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                if let Err(e) = respond(stream, &page, &render) {
+                    log::debug!(target: page.target, "Unable to answer a request for {}: {}", page.path, e);
+                }
+            },
+            Err(e) => log::warn!(target: page.target, "Unable to accept a connection for {}: {}", page.path, e),
+        }
+    }
+}
+
+/// Answers a single request and closes the connection, which is all a scraper needs.
+fn respond<F: Fn() -> io::Result<String>>(stream: TcpStream, page: &Page, render: &F) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // The headers aren't needed, but are read so the client doesn't see a reset.
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some(path)) if path == page.path => ("200 OK", page.content_type, render()?),
+        (Some("GET"), _) => ("404 Not Found", "text/plain", format!("Only {} is served here\n", page.path)),
+        _ => ("405 Method Not Allowed", "text/plain", String::new()),
+    };
+    let mut stream = reader.into_inner();
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, content_type, body.len(), body)?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod http_tests {
+    use crate::http::*;
+    use std::io::Read;
+    use std::thread;
+
+    #[test]
+    fn serve_page() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let page = Page { path: "/status", content_type: "application/json", target: "test" };
+        thread::spawn(move || serve(listener, page, || Ok("{}".to_string())));
+
+        let request = |request: &str| {
+            let mut stream = TcpStream::connect(address).unwrap();
+            write!(stream, "{}\r\nHost: localhost\r\n\r\n", request).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        assert_eq!(request("GET /status HTTP/1.1"),
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{}");
+        assert!(request("GET /metrics HTTP/1.1").ends_with("\r\n\r\nOnly /status is served here\n"));
+        assert!(request("POST /status HTTP/1.1").starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+    }
+}
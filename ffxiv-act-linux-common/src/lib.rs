@@ -0,0 +1,4 @@
+//! What the host and the client share.
+
+pub mod http;
+pub mod logging;
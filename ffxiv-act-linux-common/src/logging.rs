@@ -0,0 +1,202 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde::Deserialize;
+
+/// Where log lines go and which are kept.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// The level to log at, optionally followed by per-target levels, e.g.
+    /// `info,reader=debug,net=warn`. The targets the host and the client log to are listed in the
+    /// README.
+    pub filter: String,
+    /// A file to also write log lines to.
+    pub file: Option<PathBuf>,
+    /// The log file is rotated once it grows past this size, keeping `keep` old files as
+    /// `file.1` (the newest) to `file.<keep>`.
+    pub max_size_mb: u64,
+    pub keep: usize,
+}
+
+impl Default for LogConfig {
+    fn default() -> LogConfig {
+        LogConfig { filter: "info".to_string(), file: None, max_size_mb: 10, keep: 5 }
+    }
+}
+
+/// Which levels to log for which targets.
+#[derive(Debug, PartialEq)]
+struct Filter {
+    default: LevelFilter,
+    /// Per-target levels, longest target first so the most specific one matches.
+    targets: Vec<(String, LevelFilter)>,
+}
+
+impl Filter {
+    fn parse(filter: &str) -> Result<Filter, String> {
+        let mut parsed = Filter { default: LevelFilter::Info, targets: Vec::new() };
+        for directive in filter.split(',').map(str::trim).filter(|directive| !directive.is_empty()) {
+            let mut parts = directive.splitn(2, '=');
+            let first = parts.next().unwrap_or_default();
+            let level = |level: &str| level.parse::<LevelFilter>().map_err(|_| format!("\"{}\" isn't a log level", level));
+            match parts.next() {
+                Some(level_name) => parsed.targets.push((first.to_string(), level(level_name)?)),
+                None => parsed.default = level(first)?,
+            }
+        }
+        parsed.targets.sort_by_key(|(target, _)| std::cmp::Reverse(target.len()));
+        Ok(parsed)
+    }
+
+    fn level(&self, target: &str) -> LevelFilter {
+        self.targets.iter()
+            .find(|(name, _)| target == name || (target.starts_with(name.as_str()) && target[name.len()..].starts_with("::")))
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default)
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.targets.iter().map(|(_, level)| *level).chain(Some(self.default)).max().unwrap_or(LevelFilter::Off)
+    }
+}
+
+/// A log file that is moved aside once it grows too large.
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    keep: usize,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_size: u64, keep: usize) -> io::Result<RotatingFile> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile { path, file, size, max_size, keep })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let rotated = |index: usize| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{}", index));
+            PathBuf::from(name)
+        };
+        if self.keep > 0 {
+            let _ = fs::remove_file(rotated(self.keep));
+            for index in (1..self.keep).rev() {
+                let _ = fs::rename(rotated(index), rotated(index + 1));
+            }
+            fs::rename(&self.path, rotated(1))?;
+        }
+        self.file = OpenOptions::new().create(true).write(true).truncate(true).open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+struct Logger {
+    filter: Filter,
+    file: Option<Mutex<RotatingFile>>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.filter.level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = format!("{} {:<5} [{}] {}\n", chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f"), record.level(), record.target(), record.args());
+        // Problems go to stderr, as they did before there was a logger.
+        if record.level() <= Level::Warn {
+            let _ = io::stderr().write_all(line.as_bytes());
+        } else {
+            let _ = io::stdout().write_all(line.as_bytes());
+        }
+        if let Some(file) = self.file.as_ref() {
+            let _ = file.lock().unwrap().write_line(&line);
+        }
+    }
+
+    fn flush(&self) {
+        let _ = io::stdout().flush();
+        if let Some(file) = self.file.as_ref() {
+            let _ = file.lock().unwrap().file.flush();
+        }
+    }
+}
+
+/// Starts logging as `config` says. Each `-v` given on the command line makes the default level one
+/// step more verbose, up to `trace`.
+pub fn init(config: &LogConfig, verbosity: usize) -> Result<(), String> {
+    let mut filter = Filter::parse(&config.filter)?;
+    for _ in 0..verbosity {
+        filter.default = match filter.default {
+            LevelFilter::Off => LevelFilter::Error,
+            LevelFilter::Error => LevelFilter::Warn,
+            LevelFilter::Warn => LevelFilter::Info,
+            LevelFilter::Info => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        };
+    }
+    let file = match config.file.as_ref() {
+        Some(path) => Some(RotatingFile::open(path.clone(), config.max_size_mb * 1024 * 1024, config.keep)
+            .map_err(|e| format!("Couldn't open log file {}: {}", path.display(), e))?),
+        None => None,
+    };
+    let max_level = filter.max_level();
+    log::set_boxed_logger(Box::new(Logger { filter, file: file.map(Mutex::new) }))
+        .map_err(|e| e.to_string())?;
+    log::set_max_level(max_level);
+    Ok(())
+}
+
+#[cfg(test)]
+mod logging_tests {
+    use crate::logging::*;
+
+    #[test]
+    fn parse_filter() {
+        let filter = Filter::parse("warn, reader=debug,mem=error").unwrap();
+        assert_eq!(filter.level("reader"), LevelFilter::Debug);
+        assert_eq!(filter.level("mem"), LevelFilter::Error);
+        assert_eq!(filter.level("mem::sig_cache"), LevelFilter::Error);
+        assert_eq!(filter.level("memory"), LevelFilter::Warn);
+        assert_eq!(filter.level("net"), LevelFilter::Warn);
+        assert_eq!(filter.max_level(), LevelFilter::Debug);
+        assert_eq!(Filter::parse("net=loud"), Err("\"loud\" isn't a log level".to_string()));
+    }
+
+    #[test]
+    fn rotate_log_file() {
+        let dir = std::env::temp_dir().join(format!("logging_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        let path = dir.join("app.log");
+        let mut file = RotatingFile::open(path.clone(), 10, 2).unwrap();
+        for line in &["first\n", "second\n", "third\n", "fourth\n"] {
+            file.write_line(line).unwrap();
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth\n");
+        assert_eq!(fs::read_to_string(dir.join("app.log.1")).unwrap(), "third\n");
+        assert_eq!(fs::read_to_string(dir.join("app.log.2")).unwrap(), "second\n");
+        assert!(!dir.join("app.log.3").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,289 @@
+pub mod network_log;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use chrono::{DateTime, Local};
+
+use crate::mem::{Combatant, Event};
+use crate::net::decode::{Action, Chat, EffectResult, Effect, Tick};
+
+/// ACT's number for each type of log line the host can write.
+const CHAT: &str = "00";
+const CHANGE_ZONE: &str = "01";
+const CHANGE_PRIMARY_PLAYER: &str = "02";
+const ADD_COMBATANT: &str = "03";
+const REMOVE_COMBATANT: &str = "04";
+const ABILITY: &str = "21";
+const AOE_ABILITY: &str = "22";
+const TICK: &str = "24";
+const STATUS_ADD: &str = "26";
+
+/// The id ACT gives the target of an action that hit nothing.
+const NO_TARGET: &str = "E0000000";
+
+/// The combatants the reader last saw, to name the actors in events from the network stream.
+#[derive(Default)]
+pub struct Roster {
+    combatants: HashMap<u32, Arc<Combatant>>,
+}
+
+impl Roster {
+    pub fn apply(&mut self, event: &Event) {
+        match event {
+            Event::AddCombatant(combatant) => {
+                self.combatants.insert(combatant.id, combatant.clone());
+            },
+            Event::RemoveCombatant(combatant) => {
+                self.combatants.remove(&combatant.id);
+            },
+            Event::Combatants(combatants) => {
+                self.combatants = combatants.iter().map(|combatant| (combatant.id, combatant.clone())).collect();
+            },
+            _ => {},
+        }
+    }
+
+    pub fn get(&self, id: u32) -> Option<&Arc<Combatant>> {
+        self.combatants.get(&id)
+    }
+
+    pub fn name(&self, id: u32) -> String {
+        self.get(id).map(|combatant| combatant.name()).unwrap_or_default()
+    }
+}
+
+/// The fields of the ACT log lines for `event`, the line type first, as ACT's FFXIV plugin writes
+/// them, with actors named from `roster`. Ability and status names need the game's data files,
+/// which the host doesn't read, so they are left empty as ACT leaves those it doesn't know.
+pub fn log_lines(event: &Event, time: DateTime<Local>, roster: &Roster) -> Vec<Vec<String>> {
+    let mut lines = match event {
+        Event::ChangeZone(zone) => vec![vec![CHANGE_ZONE.to_string(), String::new(), format!("{:X}", zone), String::new()]],
+        Event::ChangePrimaryPlayer(player) => vec![vec![CHANGE_PRIMARY_PLAYER.to_string(), String::new(), format!("{:08X}", player.id), player.name()]],
+        Event::AddCombatant(combatant) => vec![combatant_fields(ADD_COMBATANT, combatant)],
+        Event::RemoveCombatant(combatant) => vec![combatant_fields(REMOVE_COMBATANT, combatant)],
+        Event::Action(action) => action_lines(action, roster),
+        Event::Tick(tick) => vec![tick_fields(tick, roster)],
+        Event::EffectResult(result) => status_lines(result, roster),
+        Event::Chat(chat) => vec![chat_fields(chat)],
+        Event::Combatants(_) => Vec::new(),
+    };
+    for fields in lines.iter_mut() {
+        fields[1] = timestamp(time);
+    }
+    lines
+}
+
+/// ACT's timestamps have seven decimal places, of which the game's clock fills three.
+fn timestamp(time: DateTime<Local>) -> String {
+    format!("{}0000{}", time.format("%Y-%m-%dT%H:%M:%S%.3f"), time.format("%:z"))
+}
+
+/// Lines 03 and 04 describe the combatant being added or removed. World names and the fields
+/// that used to hold TP are left empty.
+fn combatant_fields(line_type: &str, combatant: &Combatant) -> Vec<String> {
+    let owner = if combatant.ownerid == 0 { "0000".to_string() } else { format!("{:08X}", combatant.ownerid) };
+    vec![
+        line_type.to_string(),
+        String::new(),
+        format!("{:08X}", combatant.id),
+        combatant.name(),
+        format!("{:X}", combatant.job),
+        format!("{:X}", combatant.level),
+        owner,
+        format!("{:X}", combatant.current_world_id),
+        String::new(),
+        combatant.bnpcnameid.to_string(),
+        combatant.bnpcid.to_string(),
+        combatant.current_hp.to_string(),
+        combatant.max_hp.to_string(),
+        combatant.current_mp.to_string(),
+        combatant.max_mp.to_string(),
+        String::new(),
+        String::new(),
+        format!("{:.2}", combatant.pos_x),
+        format!("{:.2}", combatant.pos_y),
+        format!("{:.2}", combatant.pos_z),
+        format!("{:.2}", combatant.heading),
+    ]
+}
+
+fn chat_fields(chat: &Chat) -> Vec<String> {
+    vec![CHAT.to_string(), String::new(), format!("{:04x}", chat.code), chat.name.clone(), chat.message.clone()]
+}
+
+/// Lines 21 and 22 have one line per target, with each of its eight effect slots as a pair of hex
+/// fields, the HP, MP and position of the target and the source, and the action's sequence number.
+fn action_lines(action: &Action, roster: &Roster) -> Vec<Vec<String>> {
+    let line_type = if action.aoe { AOE_ABILITY } else { ABILITY };
+    let count = action.targets.len();
+    let targets = if count == 0 {
+        vec![None]
+    } else {
+        action.targets.iter().map(Some).collect()
+    };
+    targets.into_iter().enumerate().map(|(index, target)| {
+        let mut fields = vec![
+            line_type.to_string(),
+            String::new(),
+            format!("{:08X}", action.source),
+            roster.name(action.source),
+            format!("{:X}", action.id),
+            String::new(),
+            target.map(|target| format!("{:08X}", target.id)).unwrap_or_else(|| NO_TARGET.to_string()),
+            target.map(|target| roster.name(target.id)).unwrap_or_default(),
+        ];
+        let effects = target.map(|target| &target.effects[..]).unwrap_or(&[]);
+        for slot in 0..8 {
+            match effects.get(slot) {
+                Some(effect) => fields.extend(effect_fields(effect)),
+                None => fields.extend(vec!["0".to_string(), "0".to_string()]),
+            }
+        }
+        fields.extend(resource_fields(target.and_then(|target| roster.get(target.id))));
+        fields.extend(resource_fields(roster.get(action.source)));
+        fields.push(format!("{:08X}", action.sequence));
+        fields.push(index.to_string());
+        fields.push(count.to_string());
+        fields
+    }).collect()
+}
+
+/// ACT packs an effect into two fields: its type and three parameters, then its value, flags and
+/// the value's high byte.
+fn effect_fields(effect: &Effect) -> Vec<String> {
+    let kind = effect.kind as u32 | (effect.params[0] as u32) << 8 | (effect.params[1] as u32) << 16 | (effect.params[2] as u32) << 24;
+    let value = (effect.value as u32) << 16 | (effect.flags as u32) << 8 | effect.high_byte as u32;
+    vec![format!("{:X}", kind), format!("{:X}", value)]
+}
+
+/// HP, MP, the fields that used to hold TP and the position, all empty for a combatant the reader
+/// hasn't seen.
+fn resource_fields(combatant: Option<&Arc<Combatant>>) -> Vec<String> {
+    match combatant {
+        Some(combatant) => vec![
+            combatant.current_hp.to_string(),
+            combatant.max_hp.to_string(),
+            combatant.current_mp.to_string(),
+            combatant.max_mp.to_string(),
+            String::new(),
+            String::new(),
+            format!("{:.2}", combatant.pos_x),
+            format!("{:.2}", combatant.pos_y),
+            format!("{:.2}", combatant.pos_z),
+            format!("{:.2}", combatant.heading),
+        ],
+        None => vec![String::new(); 10],
+    }
+}
+
+/// Line 24 is damage or healing over time landing on its target, with the status it comes from.
+fn tick_fields(tick: &Tick, roster: &Roster) -> Vec<String> {
+    let mut fields = vec![
+        TICK.to_string(),
+        String::new(),
+        format!("{:08X}", tick.target),
+        roster.name(tick.target),
+        if tick.heal { "HoT" } else { "DoT" }.to_string(),
+        format!("{:X}", tick.status),
+        format!("{:X}", tick.amount),
+    ];
+    fields.extend(resource_fields(roster.get(tick.target)));
+    fields.push(if tick.source == 0 { String::new() } else { format!("{:08X}", tick.source) });
+    fields.push(roster.name(tick.source));
+    fields
+}
+
+/// Line 26 has one line for each status an effect result puts on its target.
+fn status_lines(result: &EffectResult, roster: &Roster) -> Vec<Vec<String>> {
+    result.statuses.iter().map(|status| vec![
+        STATUS_ADD.to_string(),
+        String::new(),
+        format!("{:X}", status.id),
+        String::new(),
+        format!("{:.2}", status.duration),
+        format!("{:08X}", status.source),
+        roster.name(status.source),
+        format!("{:08X}", result.target),
+        roster.name(result.target),
+        format!("{:02X}", status.param),
+        result.max_hp.to_string(),
+        roster.get(status.source).map(|source| source.max_hp.to_string()).unwrap_or_default(),
+    ]).collect()
+}
+
+#[cfg(test)]
+mod act_tests {
+    use crate::act::*;
+    use crate::net::decode::{ActionTarget, Status};
+    use chrono::TimeZone;
+
+    fn log_line(event: &Event, time: DateTime<Local>, roster: &Roster) -> String {
+        let lines = log_lines(event, time, roster);
+        assert_eq!(lines.len(), 1);
+        lines[0].join("|")
+    }
+
+    #[test]
+    fn format_log_lines() {
+        let time = Local.timestamp_millis_opt(1_760_000_000_123).unwrap();
+        let stamp = format!("{}.1230000{}", time.format("%Y-%m-%dT%H:%M:%S"), time.format("%:z"));
+        let roster = Roster::default();
+        assert_eq!(log_lines(&Event::ChangeZone(0x84), time, &roster), vec![vec!["01".to_string(), stamp.clone(), "84".to_string(), String::new()]]);
+
+        let mut player = Combatant::named(0x1040_2A3B, "Kate Arch");
+        player.pos_x = 100.5;
+        player.current_world_id = 0x28;
+        player.current_hp = 51_234;
+        player.max_hp = 60_000;
+        player.job = 0x18;
+        player.level = 90;
+        let player = Arc::new(player);
+        assert_eq!(log_line(&Event::ChangePrimaryPlayer(player.clone()), time, &roster), format!("02|{}|10402A3B|Kate Arch", stamp));
+        assert_eq!(log_line(&Event::AddCombatant(player), time, &roster),
+            format!("03|{}|10402A3B|Kate Arch|18|5A|0000|28||0|0|51234|60000|0|0|||100.50|0.00|0.00|0.00", stamp));
+        assert!(log_lines(&Event::Combatants(Arc::new(Vec::new())), time, &roster).is_empty());
+    }
+
+    #[test]
+    fn format_network_lines() {
+        let time = Local.timestamp_millis_opt(1_760_000_000_123).unwrap();
+        let stamp = format!("{}.1230000{}", time.format("%Y-%m-%dT%H:%M:%S"), time.format("%:z"));
+        let mut roster = Roster::default();
+        let mut player = Combatant::named(0x1040_2A3B, "Kate Arch");
+        player.current_hp = 51_234;
+        player.max_hp = 60_000;
+        let dummy = Combatant::named(0x4000_0001, "Striking Dummy");
+        roster.apply(&Event::Combatants(Arc::new(vec![Arc::new(player), Arc::new(dummy)])));
+        roster.apply(&Event::RemoveCombatant(Arc::new(Combatant::named(0x4000_0001, "Striking Dummy"))));
+        roster.apply(&Event::AddCombatant(Arc::new(Combatant::named(0x4000_0002, "Ifrit"))));
+
+        let chat = Chat { code: 0x1d, name: "Kate Arch".to_string(), message: "hello".to_string() };
+        assert_eq!(log_line(&Event::Chat(Arc::new(chat)), time, &roster), format!("00|{}|001d|Kate Arch|hello", stamp));
+
+        let damage = Effect { kind: 3, params: [0x20, 0, 0], high_byte: 1, flags: 0x40, value: 0x3d52 };
+        let action = Action { source: 0x1040_2A3B, id: 0x1d0c, sequence: 0xa1b2, aoe: false, targets: vec![ActionTarget { id: 0x4000_0002, effects: vec![damage] }] };
+        assert_eq!(log_line(&Event::Action(Arc::new(action.clone())), time, &roster), format!(
+            "21|{}|10402A3B|Kate Arch|1D0C||40000002|Ifrit|2003|3D524001|0|0|0|0|0|0|0|0|0|0|0|0|0|0|0|0|0|0|||0.00|0.00|0.00|0.00|51234|60000|0|0|||0.00|0.00|0.00|0.00|0000A1B2|0|1",
+            stamp));
+
+        let aoe = Action { aoe: true, targets: vec![ActionTarget { id: 0x4000_0001, effects: Vec::new() }, action.targets[0].clone()], ..action.clone() };
+        let lines = log_lines(&Event::Action(Arc::new(aoe)), time, &roster);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0][..8].join("|"), format!("22|{}|10402A3B|Kate Arch|1D0C||40000001|", stamp));
+        assert_eq!(lines[0][8..24], vec!["0".to_string(); 16][..]);
+        assert_eq!(lines[1][6..10].join("|"), "40000002|Ifrit|2003|3D524001");
+        assert_eq!(lines[1][44..].join("|"), "0000A1B2|1|2");
+        let missed = Action { targets: Vec::new(), ..action };
+        assert_eq!(log_lines(&Event::Action(Arc::new(missed)), time, &roster)[0][6..8].join("|"), "E0000000|");
+
+        let tick = Tick { target: 0x4000_0002, source: 0x1040_2A3B, status: 0x74f, heal: false, amount: 4_321 };
+        assert_eq!(log_line(&Event::Tick(tick), time, &roster), format!("24|{}|40000002|Ifrit|DoT|74F|10E1|0|0|0|0|||0.00|0.00|0.00|0.00|10402A3B|Kate Arch", stamp));
+
+        let result = EffectResult { target: 0x4000_0002, current_hp: 9_000, max_hp: 10_000, statuses: vec![
+            Status { id: 0x74f, param: 0, duration: 30.0, source: 0x1040_2A3B },
+        ] };
+        assert_eq!(log_line(&Event::EffectResult(Arc::new(result)), time, &roster),
+            format!("26|{}|74F||30.00|10402A3B|Kate Arch|40000002|Ifrit|00|10000|60000", stamp));
+    }
+}
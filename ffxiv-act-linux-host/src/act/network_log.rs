@@ -0,0 +1,170 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+
+use chrono::{DateTime, Local, NaiveDate};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::act::{log_lines, Roster};
+use crate::mem::Event;
+
+/// Where to write ACT network logs. They are only written when the config has a `network_log`
+/// section.
+#[derive(Deserialize, Clone)]
+pub struct NetworkLogConfig {
+    pub directory: PathBuf,
+    /// The FFXIV_ACT_Plugin version ACT puts in the names of its logs, which should match the
+    /// plugin the logs are imported into.
+    #[serde(default = "default_plugin_version")]
+    pub plugin_version: String,
+}
+
+fn default_plugin_version() -> String {
+    "2.7.4.0".to_string()
+}
+
+/// Writes the lines for the events given to the returned sender to a log in the configured
+/// directory, named and laid out as ACT names and lays out its `Network_*.log` files, so it can be
+/// imported into ACT or uploaded to FFLogs. Chat, ability and buff lines (00, 21, 22 and 26) are
+/// only written when `net_config.opcodes` lets the host decode them from the network stream.
+pub fn start(config: NetworkLogConfig) -> io::Result<Sender<Event>> {
+    fs::create_dir_all(&config.directory)?;
+    let mut log = NetworkLog::open(config.directory, &config.plugin_version, Local::now())?;
+    info!(target: "act", "Writing network log to {}", log.path.display());
+    let (events_tx, events) = mpsc::channel();
+    thread::spawn(move || {
+        let mut roster = Roster::default();
+        for event in events {
+            roster.apply(&event);
+            let time = Local::now();
+            for fields in log_lines(&event, time, &roster) {
+                if let Err(e) = log.write(&fields, time) {
+                    error!(target: "act", "Unable to write to {}: {}", log.path.display(), e);
+                }
+            }
+        }
+    });
+    Ok(events_tx)
+}
+
+/// The day's log, which moves on to a new file at midnight, as ACT's does.
+struct NetworkLog {
+    directory: PathBuf,
+    plugin_version: String,
+    date: NaiveDate,
+    path: PathBuf,
+    file: File,
+    /// The number of lines in the file, which each line's hash covers.
+    lines: u64,
+}
+
+impl NetworkLog {
+    fn open(directory: PathBuf, plugin_version: &str, time: DateTime<Local>) -> io::Result<NetworkLog> {
+        let date = time.date_naive();
+        let path = directory.join(file_name(plugin_version, date));
+        // Picking up a log from earlier in the day carries on its line count.
+        let lines = match File::open(&path) {
+            Ok(file) => BufReader::new(file).lines().count() as u64,
+            Err(_) => 0,
+        };
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(NetworkLog { directory, plugin_version: plugin_version.to_string(), date, path, file, lines })
+    }
+
+    fn write(&mut self, fields: &[String], time: DateTime<Local>) -> io::Result<()> {
+        if time.date_naive() != self.date {
+            *self = NetworkLog::open(self.directory.clone(), &self.plugin_version, time)?;
+            info!(target: "act", "Writing network log to {}", self.path.display());
+        }
+        self.lines += 1;
+        let line = fields.join("|");
+        writeln!(self.file, "{}|{}", line, hash_line(&line, self.lines))
+    }
+}
+
+/// ACT names its logs after the FFXIV plugin's version, without the dots, and the day.
+fn file_name(plugin_version: &str, date: NaiveDate) -> String {
+    format!("Network_{}_{}.log", plugin_version.replace('.', ""), date.format("%Y%m%d"))
+}
+
+/// Checks that `version` looks like the plugin's four-part version.
+pub fn check_plugin_version(version: &str) -> Result<(), String> {
+    let parts = version.split('.').collect::<Vec<_>>();
+    if parts.len() == 4 && parts.iter().all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit())) {
+        Ok(())
+    } else {
+        Err("expected the FFXIV_ACT_Plugin's version, e.g. 2.7.4.0".to_string())
+    }
+}
+
+/// The hash ACT ends each line with: the first 8 bytes of the SHA-256 of the line followed by its
+/// number in the file, in lowercase hex.
+fn hash_line(line: &str, number: u64) -> String {
+    let digest = Sha256::digest(format!("{}|{}", line, number).as_bytes());
+    hex::encode(&digest[..8])
+}
+
+/// Checks that `directory` can hold the logs, without creating it.
+pub fn check_directory(directory: &Path) -> Result<(), String> {
+    match fs::metadata(directory) {
+        Ok(metadata) if !metadata.is_dir() => Err("it isn't a directory".to_string()),
+        Ok(_) => {
+            // The permission bits don't say whether the host can write there, e.g. as root, on a
+            // read-only mount or under an ACL, so a file is created to find out.
+            let probe = directory.join(format!(".network_log_check_{}", std::process::id()));
+            OpenOptions::new().write(true).create_new(true).open(&probe).map_err(|e| format!("a file can't be created in it: {}", e))?;
+            let _ = fs::remove_file(&probe);
+            Ok(())
+        },
+        // It's created when the host starts.
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(format!("it can't be looked at: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod network_log_tests {
+    use crate::act::network_log::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn hash_lines() {
+        assert_eq!(hash_line("01|2026-10-19T12:00:00.0000000+00:00|84|", 1), "9b6dd7023a96866c");
+    }
+
+    #[test]
+    fn write_daily_logs() {
+        let directory = std::env::temp_dir().join(format!("network_log_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir(&directory).unwrap();
+        let day = Local.with_ymd_and_hms(2026, 10, 19, 23, 59, 0).unwrap();
+        let next_day = Local.with_ymd_and_hms(2026, 10, 20, 0, 1, 0).unwrap();
+        let fields = |zone: &str| vec!["01".to_string(), "time".to_string(), zone.to_string(), String::new()];
+
+        let mut log = NetworkLog::open(directory.clone(), "2.7.4.0", day).unwrap();
+        log.write(&fields("84"), day).unwrap();
+        // The host restarting carries on the same file.
+        let mut log = NetworkLog::open(directory.clone(), "2.7.4.0", day).unwrap();
+        log.write(&fields("85"), day).unwrap();
+        log.write(&fields("86"), next_day).unwrap();
+
+        let name = "Network_2740_20261019.log";
+        assert_eq!(fs::read_to_string(directory.join(name)).unwrap(),
+            format!("01|time|84||{}\n01|time|85||{}\n", hash_line("01|time|84|", 1), hash_line("01|time|85|", 2)));
+        assert_eq!(fs::read_to_string(directory.join(name.replace("20261019", "20261020"))).unwrap(),
+            format!("01|time|86||{}\n", hash_line("01|time|86|", 1)));
+        assert_eq!(check_directory(&directory.join(name)), Err("it isn't a directory".to_string()));
+        assert_eq!(check_directory(&directory.join("missing")), Ok(()));
+        assert_eq!(check_directory(&directory), Ok(()));
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 2);
+        assert!(check_directory(Path::new("/proc")).unwrap_err().starts_with("a file can't be created in it"));
+        assert!(check_directory(&directory.join(name).join("missing")).unwrap_err().starts_with("it can't be looked at"));
+        assert_eq!(check_plugin_version("2.7.4.0"), Ok(()));
+        assert!(check_plugin_version("2.7").is_err());
+        assert!(check_plugin_version("2.7.x.0").is_err());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,195 @@
+use std::env;
+use std::path::PathBuf;
+
+use proc_maps::Pid;
+
+pub const CONFIG_FILE: &str = "config.json";
+pub const SIGNATURE_FILE: &str = "signatures_64.json";
+/// The host's directory inside each XDG config directory.
+const APP_DIR: &str = "ffxiv-act-linux";
+
+pub const USAGE: &str = "\
+Usage: ffxiv_act_linux_host [options] [command]
+
+Commands:
+    run                         Wait for FFXIV and serve memory-sync and network-passthrough (default)
+    scan                        Find the memory signatures in the running game, print where they are and exit
+    check                       Print a detailed report on every memory signature and exit
+    discover <signature> <old binary> [new binary]
+                                Propose an updated pattern for a signature broken by a patch
+
+Options:
+    -c, --config <path>         Config file to use
+    -s, --signatures <path>     Signature file to use
+    -p, --pid <pid>             Use this FFXIV process instead of looking for one
+    -i, --interface <name>      Capture on this interface instead of the configured one
+    -v, --verbose               Print more detail; repeat for even more
+    -h, --help                  Print this help
+
+Without --config or --signatures, config.json and signatures_64.json are looked for in the working
+directory, then in $XDG_CONFIG_HOME/ffxiv-act-linux (~/.config/ffxiv-act-linux), then in
+ffxiv-act-linux under each of $XDG_CONFIG_DIRS (/etc/xdg).";
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Run,
+    Scan,
+    Check,
+    Discover { signature: String, old_binary: String, new_binary: Option<String> },
+    Help,
+}
+
+/// The host's command line.
+#[derive(Debug, PartialEq)]
+pub struct Args {
+    pub command: Command,
+    pub config: Option<PathBuf>,
+    pub signatures: Option<PathBuf>,
+    pub pid: Option<Pid>,
+    pub interface: Option<String>,
+    pub verbosity: usize,
+}
+
+/// Parses the arguments after the program name. Options may come before or after the command, and
+/// take their value either as the next argument or after `=`.
+pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Args, String> {
+    let mut parsed = Args { command: Command::Run, config: None, signatures: None, pid: None, interface: None, verbosity: 0 };
+    let mut positional = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with('-') || arg == "-" {
+            positional.push(arg);
+            continue;
+        }
+        // Bundled short flags, e.g. `-vv`.
+        if !arg.starts_with("--") && arg.len() > 2 && arg[1..].chars().all(|c| c == 'v') {
+            parsed.verbosity += arg.len() - 1;
+            continue;
+        }
+        let (option, inline) = match arg.find('=') {
+            Some(eq) => (arg[..eq].to_string(), Some(arg[eq + 1..].to_string())),
+            None => (arg.clone(), None),
+        };
+        let mut value = || inline.clone().or_else(|| args.next()).ok_or_else(|| format!("{} needs a value", option));
+        match option.as_str() {
+            "-c" | "--config" => parsed.config = Some(PathBuf::from(value()?)),
+            "-s" | "--signatures" => parsed.signatures = Some(PathBuf::from(value()?)),
+            "-p" | "--pid" => {
+                let pid = value()?;
+                parsed.pid = Some(pid.parse().map_err(|_| format!("{} isn't a process id", pid))?);
+            },
+            "-i" | "--interface" => parsed.interface = Some(value()?),
+            "-v" | "--verbose" => parsed.verbosity += 1,
+            "-h" | "--help" => parsed.command = Command::Help,
+            _ => return Err(format!("Unknown option {}", arg)),
+        }
+    }
+    if parsed.command == Command::Help {
+        return Ok(parsed);
+    }
+
+    let mut positional = positional.into_iter();
+    parsed.command = match positional.next().as_deref() {
+        None | Some("run") => Command::Run,
+        Some("scan") => Command::Scan,
+        Some("check") => Command::Check,
+        Some("help") => Command::Help,
+        Some("discover") => {
+            let signature = positional.next().ok_or("discover needs a signature name or pattern")?;
+            let old_binary = positional.next().ok_or("discover needs the game binary from before the patch")?;
+            Command::Discover { signature, old_binary, new_binary: positional.next() }
+        },
+        Some(command) => return Err(format!("Unknown command {}", command)),
+    };
+    match positional.next() {
+        Some(extra) => Err(format!("Unexpected argument {}", extra)),
+        None => Ok(parsed),
+    }
+}
+
+/// The path to use for one of the host's files: `given` if there is one, else the first place the
+/// file exists out of `search_paths`. If it exists nowhere, the path in the working directory is
+/// returned so that errors name it.
+pub fn find_file(name: &str, given: Option<PathBuf>) -> PathBuf {
+    given.unwrap_or_else(|| {
+        let candidates = search_paths(name, env::var("XDG_CONFIG_HOME").ok(), env::var("HOME").ok(), env::var("XDG_CONFIG_DIRS").ok());
+        candidates.iter()
+            .find(|path| path.is_file())
+            .cloned()
+            .unwrap_or_else(|| PathBuf::from(name))
+    })
+}
+
+/// Where to look for `name`, in order: the working directory, the user's config directory, then
+/// the system's, following the XDG base directory spec.
+fn search_paths(name: &str, xdg_config_home: Option<String>, home: Option<String>, xdg_config_dirs: Option<String>) -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::from(name)];
+    // Empty or relative values are ignored, as the spec says.
+    let config_home = xdg_config_home.filter(|dir| dir.starts_with('/')).map(PathBuf::from)
+        .or_else(|| home.filter(|dir| !dir.is_empty()).map(|home| PathBuf::from(home).join(".config")));
+    if let Some(config_home) = config_home {
+        paths.push(config_home.join(APP_DIR).join(name));
+    }
+    let config_dirs = xdg_config_dirs.filter(|dirs| !dirs.is_empty()).unwrap_or_else(|| "/etc/xdg".to_string());
+    for dir in config_dirs.split(':').filter(|dir| dir.starts_with('/')) {
+        paths.push(PathBuf::from(dir).join(APP_DIR).join(name));
+    }
+    paths
+}
+
+#[cfg(test)]
+mod cli_tests {
+    use crate::cli::*;
+
+    fn args(line: &str) -> Result<Args, String> {
+        parse(line.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn parse_options_and_commands() {
+        let parsed = args("-c /etc/host.json --signatures=sigs.json -vv --pid 4242 -i eth0 scan").unwrap();
+        assert_eq!(parsed, Args {
+            command: Command::Scan,
+            config: Some(PathBuf::from("/etc/host.json")),
+            signatures: Some(PathBuf::from("sigs.json")),
+            pid: Some(4242),
+            interface: Some("eth0".to_string()),
+            verbosity: 2,
+        });
+        assert_eq!(args("").unwrap().command, Command::Run);
+        assert_eq!(args("check -v").unwrap().verbosity, 1);
+        assert_eq!(args("discover target old.exe").unwrap().command, Command::Discover {
+            signature: "target".to_string(),
+            old_binary: "old.exe".to_string(),
+            new_binary: None,
+        });
+        assert_eq!(args("bogus --help").unwrap().command, Command::Help);
+    }
+
+    #[test]
+    fn reject_bad_arguments() {
+        assert_eq!(args("--pid ffxiv"), Err("ffxiv isn't a process id".to_string()));
+        assert_eq!(args("run --config"), Err("--config needs a value".to_string()));
+        assert_eq!(args("--frobnicate"), Err("Unknown option --frobnicate".to_string()));
+        assert_eq!(args("run check"), Err("Unexpected argument check".to_string()));
+        assert!(args("discover target").is_err());
+    }
+
+    #[test]
+    fn xdg_search_paths() {
+        let paths = search_paths(CONFIG_FILE, None, Some("/home/kate".to_string()), None);
+        assert_eq!(paths, vec![
+            PathBuf::from("config.json"),
+            PathBuf::from("/home/kate/.config/ffxiv-act-linux/config.json"),
+            PathBuf::from("/etc/xdg/ffxiv-act-linux/config.json"),
+        ]);
+
+        let paths = search_paths(SIGNATURE_FILE, Some("/srv/config".to_string()), Some("/root".to_string()), Some("/opt/xdg:relative:/etc/xdg".to_string()));
+        assert_eq!(paths, vec![
+            PathBuf::from("signatures_64.json"),
+            PathBuf::from("/srv/config/ffxiv-act-linux/signatures_64.json"),
+            PathBuf::from("/opt/xdg/ffxiv-act-linux/signatures_64.json"),
+            PathBuf::from("/etc/xdg/ffxiv-act-linux/signatures_64.json"),
+        ]);
+    }
+}
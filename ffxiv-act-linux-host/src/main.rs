@@ -1,7 +1,13 @@
 //mod signatures;
+mod act;
+mod cli;
+mod metrics;
 mod utils;
 mod mem;
 mod net;
+mod overlay;
+mod parser;
+mod validate;
 
 extern crate byteorder;
 extern crate read_process_memory;
@@ -13,34 +19,108 @@ extern crate pcap;
 extern crate etherparse;
 extern crate bincode;
 extern crate flate2;
+#[macro_use]
+extern crate log;
+extern crate chrono;
+extern crate ffxiv_act_linux_common;
 
 
 use serde::{Deserialize};
-use std::fs::File;
 
 use std::thread;
 use std::time::Duration;
-use std::sync::mpsc;
+use std::sync::{mpsc, Arc};
+use std::net::TcpListener;
 use crate::net::get_src_port;
+use crate::cli::Command;
+use ffxiv_act_linux_common::logging;
 
 fn main() {
-    let config_fixed: Config = {
-        if let Ok(file) = File::open("config.json") {
-            if let Ok(config) = serde_json::from_reader(file) {
-                config
-            } else {
-                eprintln!("Couldn't read / parse config file.");
-                std::process::exit(1);
+    let args = match cli::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("{}", cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+    let signature_file = cli::find_file(cli::SIGNATURE_FILE, args.signatures);
+
+    // Commands other than `run` only log what goes wrong, to the console.
+    if args.command != Command::Run {
+        start_logging(&logging::LogConfig { filter: "warn".to_string(), ..logging::LogConfig::default() }, args.verbosity);
+    }
+    match args.command {
+        Command::Help => {
+            println!("{}", cli::USAGE);
+            std::process::exit(0);
+        },
+        // `scan` and `check` report on the memory signatures against the running game, then exit.
+        Command::Scan => exit_with(mem::scan(running_ffxiv(args.pid), &signature_file)),
+        Command::Check => exit_with(mem::check(running_ffxiv(args.pid), &signature_file)),
+        // `discover` proposes a fixed pattern for a signature after a patch, from the new binary or
+        // the running game.
+        Command::Discover { signature, old_binary, new_binary } => {
+            let ffxiv = utils::find_ffxiv_or(args.pid);
+            exit_with(mem::discover(&signature, &old_binary, new_binary.as_deref(), ffxiv, &signature_file))
+        },
+        Command::Run => {},
+    }
+
+    let config_file = cli::find_file(cli::CONFIG_FILE, args.config);
+    let interface = args.interface;
+    let config_fixed = validate::read_config(&config_file)
+        .and_then(|mut config| {
+            if let Some(interface) = interface {
+                config.net_config.interface = Some(interface);
+            }
+            let problems = validate::validate(&config, net::interface_names());
+            if problems.is_empty() { Ok(config) } else { Err(problems) }
+        })
+        .unwrap_or_else(|problems| {
+            eprintln!("Problems with config file {}:", config_file.display());
+            for problem in problems {
+                eprintln!("  - {}", problem);
             }
-        } else {
-            eprintln!("Couldn't open config file.");
+            std::process::exit(1);
+        });
+    start_logging(&config_fixed.log, args.verbosity);
+    info!(target: "host", "Using config {} and signatures {}", config_file.display(), signature_file.display());
+
+    let metrics = Arc::new(metrics::Metrics::new());
+    if let Some(metrics_config) = config_fixed.metrics.as_ref() {
+        if let Err(e) = metrics::start(metrics.clone(), &metrics_config.bind_address) {
+            error!(target: "metrics", "Unable to serve metrics on {}: {}", metrics_config.bind_address, e);
             std::process::exit(1);
         }
-    };
+    }
+    // Everything the reader and the network decoder see happen in the game is sent to each of these.
+    let mut observers = Vec::new();
+    if let Some(overlay_config) = config_fixed.overlay.as_ref() {
+        match TcpListener::bind(&overlay_config.bind_address).and_then(overlay::start) {
+            Ok(events) => observers.push(events),
+            Err(e) => {
+                error!(target: "overlay", "Unable to serve overlays on {}: {}", overlay_config.bind_address, e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(network_log_config) = config_fixed.network_log.as_ref() {
+        match act::network_log::start(network_log_config.clone()) {
+            Ok(events) => observers.push(events),
+            Err(e) => {
+                error!(target: "act", "Unable to write network logs to {}: {}", network_log_config.directory.display(), e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(parser_config) = config_fixed.parser.clone() {
+        observers.push(parser::start(parser_config));
+    }
 
     loop {
         let config = config_fixed.clone();
-        let ffxiv = wait_for_ffxiv();
+        let ffxiv = wait_for_ffxiv(args.pid);
 
         let (tx, rx) = mpsc::channel();
         let mem_tx = tx.clone();
@@ -48,10 +128,16 @@ fn main() {
 
         let mem_config = config.mem_config;
         let net_config = config.net_config;
+        let signature_file = signature_file.clone();
+        let net_address = net_config.bind_address.clone();
+        let mem_metrics = metrics.clone();
+        let net_metrics = metrics.clone();
+        let net_observers = observers.clone();
+        let observers = observers.clone();
 
         // Memory
         thread::spawn(move || {
-            if !mem::begin(ffxiv, mem_config) {
+            if !mem::begin(ffxiv, mem_config, signature_file, &net_address, mem_metrics, observers) {
                 mem_tx.send(false).unwrap();
             }
             mem_tx.send(true).unwrap();
@@ -59,7 +145,7 @@ fn main() {
 
         // Network
         thread::spawn(move || {
-            if !net::start_packet_redirection(net_config, ffxiv) {
+            if !net::start_packet_redirection(net_config, ffxiv, net_metrics, net_observers) {
                 net_tx.send(false).unwrap();
             }
             net_tx.send(true).unwrap();
@@ -67,7 +153,7 @@ fn main() {
 
         for rec in rx {
             if !rec {
-                eprintln!("Terminating due to error in memory or network thread.");
+                error!(target: "host", "Terminating due to error in memory or network thread.");
                 std::process::exit(1);
             }
         }
@@ -76,27 +162,46 @@ fn main() {
 
 }
 
-fn wait_for_ffxiv() -> i32 {
+fn start_logging(config: &logging::LogConfig, verbosity: usize) {
+    if let Err(e) = logging::init(config, verbosity) {
+        eprintln!("Unable to start logging: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn exit_with(success: bool) -> ! {
+    std::process::exit(if success { 0 } else { 1 })
+}
+
+/// The running game for the commands that need it, exiting if it isn't running.
+fn running_ffxiv(pid: Option<i32>) -> i32 {
+    utils::find_ffxiv_or(pid).unwrap_or_else(|| {
+        eprintln!("FFXIV isn't running.");
+        std::process::exit(1);
+    })
+}
+
+fn wait_for_ffxiv(pid: Option<i32>) -> i32 {
     let mut ffxiv;
     let mut port;
     let mut said_message = false;
     let mut said_ffxiv = false;
     let mut said_port = false;
     loop {
-        ffxiv = utils::find_ffxiv();
+        ffxiv = utils::find_ffxiv_or(pid);
         if ffxiv.is_some() {
             if !said_ffxiv {
-                println!("Found FFXIV on PID {}!", ffxiv.unwrap());
+                info!(target: "host", "Found FFXIV on PID {}!", ffxiv.unwrap());
                 said_ffxiv = true;
             }
             port = get_src_port(ffxiv.unwrap());
             if port.is_some() {
-                println!("Found FFXIV network port at {}!", port.unwrap());
-                println!("Starting memory-sync and network-passthrough now...");
+                info!(target: "host", "Found FFXIV network port at {}!", port.unwrap());
+                info!(target: "host", "Starting memory-sync and network-passthrough now...");
                 break;
             } else {
                 if !said_port {
-                    println!("Waiting for FFXIV network connection...");
+                    info!(target: "host", "Waiting for FFXIV network connection...");
                     said_port = true;
                 }
                 std::thread::sleep(Duration::from_secs(1));
@@ -104,7 +209,7 @@ fn wait_for_ffxiv() -> i32 {
             said_message = false;
         } else {
             if !said_message {
-                println!("Waiting for FFXIV...");
+                info!(target: "host", "Waiting for FFXIV...");
                 said_message = true;
             }
             said_ffxiv = false;
@@ -118,16 +223,74 @@ fn wait_for_ffxiv() -> i32 {
 pub struct Config {
     pub net_config: NetConfig,
     pub mem_config: MemConfig,
+    #[serde(default)]
+    pub log: logging::LogConfig,
+    /// Serves the host's metrics over HTTP when set.
+    #[serde(default)]
+    pub metrics: Option<metrics::MetricsConfig>,
+    /// Serves overlays written for OverlayPlugin when set.
+    #[serde(default)]
+    pub overlay: Option<overlay::OverlayConfig>,
+    /// Writes ACT network logs when set.
+    #[serde(default)]
+    pub network_log: Option<act::network_log::NetworkLogConfig>,
+    /// Parses encounters into DPS and HPS when set.
+    #[serde(default)]
+    pub parser: Option<parser::ParserConfig>,
 }
 
 #[derive(Deserialize, Clone)]
 pub struct NetConfig {
-    pub interface: String,
-    pub hostname_exclude: String,
+    /// The interface to capture the game's traffic on. Found from the routing table if not set.
+    #[serde(default)]
+    pub interface: Option<String>,
+    /// A host whose packets aren't captured, on top of the network-passthrough clients, which are
+    /// excluded as they connect.
+    #[serde(default)]
+    pub hostname_exclude: Option<String>,
     pub bind_address: String,
+    /// The current patch's opcodes, to decode abilities, buffs and chat from the captured traffic.
+    #[serde(default)]
+    pub opcodes: Option<net::decode::Opcodes>,
 }
 
 #[derive(Deserialize, Clone)]
 pub struct MemConfig {
     pub bind_address: String,
+    /// Frame codecs in order of preference; the first one the client supports is used.
+    #[serde(default = "mem::default_codecs")]
+    pub compression: Vec<mem::Codec>,
+    #[serde(default)]
+    pub tick_rates: TickRates,
+}
+
+/// How often the reader polls each kind of data, in milliseconds.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct TickRates {
+    pub server_time_ms: u64,
+    pub target_ms: u64,
+    pub mobs_ms: u64,
+    pub party_list_ms: u64,
+    pub zone_ms: u64,
+    /// Slow every rate down by `idle_multiplier` when nothing has changed for `idle_after_ms`,
+    /// e.g. standing around in town, and return to the configured rates once combat starts.
+    pub adaptive: bool,
+    pub idle_after_ms: u64,
+    pub idle_multiplier: u32,
+}
+
+impl Default for TickRates {
+    fn default() -> TickRates {
+        TickRates {
+            server_time_ms: 10,
+            target_ms: 20,
+            mobs_ms: 50,
+            party_list_ms: 100,
+            zone_ms: 1000,
+            adaptive: false,
+            idle_after_ms: 10_000,
+            idle_multiplier: 4,
+        }
+    }
 }
@@ -0,0 +1,125 @@
+use std::path::Path;
+
+use proc_maps::Pid;
+
+use crate::mem::SignatureEntry;
+use crate::mem::pe::GameImage;
+use crate::mem::pointer_path::PointerPath;
+use crate::mem::reader::{self, MemErrorType};
+use crate::mem::scanner;
+use crate::mem::vm_read;
+
+/// Bytes shown either side of a match in its hexdump.
+const CONTEXT: usize = 32;
+
+/// Scans the game for every signature in the signature file and prints what it finds: each match's
+/// address and RVA, the static pointer it refers to and every level of its pointer chain, and the
+/// bytes around it. Signatures with more than one match are flagged, since the reader would only
+/// ever use the first. Returns whether every required signature was found exactly once.
+pub fn check_signatures(ffxiv: Pid, signature_file: &Path) -> Result<bool, MemErrorType> {
+    let registry = reader::load_registry(signature_file)?;
+    let game = GameImage::find(ffxiv).ok_or(MemErrorType::LocatingGameImage)?;
+    let text = game.text().ok_or(MemErrorType::LocatingGameImage)?;
+    println!("ffxiv_dx11.exe at {:#x}, .text {:#x}..{:#x}", game.base, text.start, text.end);
+
+    let signatures = registry.iter().map(|entry| entry.signature()).collect::<Vec<_>>();
+    let report = scanner::scan_process(ffxiv, text, &signatures);
+
+    let mut healthy = true;
+    for ((entry, signature), matches) in registry.iter().zip(signatures.iter()).zip(report.matches.iter()) {
+        let required = if entry.required { "required" } else { "optional" };
+        println!();
+        match matches.len() {
+            0 => println!("[{}] ({}) NOT FOUND", entry.name, required),
+            1 => println!("[{}] ({}) 1 match", entry.name, required),
+            n => println!("[{}] ({}) AMBIGUOUS: {} matches, the reader uses the first", entry.name, required, n),
+        }
+        if entry.required && matches.len() != 1 {
+            healthy = false;
+        }
+        for address in matches {
+            print_match(ffxiv, &game, entry, *address, signature.signature_bytes.len());
+        }
+    }
+
+    if !report.skipped.is_empty() {
+        println!();
+        println!("Unreadable memory skipped while scanning:");
+        for skip in &report.skipped {
+            println!("    {:x?}: {}", skip.range, skip.reason);
+        }
+    }
+    Ok(healthy)
+}
+
+fn print_match(ffxiv: Pid, game: &GameImage, entry: &SignatureEntry, address: usize, len: usize) {
+    println!("    match at {:#x} (ffxiv_dx11.exe+{:#x})", address, address - game.base);
+    match reader::read_signature(address + len, &ffxiv) {
+        Ok(static_address) => {
+            println!("    static pointer {:#x} (ffxiv_dx11.exe+{:#x})", static_address, static_address.wrapping_sub(game.base));
+            // Follow the chain one level further each time, to show where it goes wrong.
+            for level in 1..=entry.pointer_chain.len() {
+                let path = PointerPath { base: static_address, offsets: entry.pointer_chain[..level].to_vec(), value_type: None };
+                match path.resolve(ffxiv) {
+                    Ok(address) => println!("    level {} (+{:#x}) -> {:#x}", level, entry.pointer_chain[level - 1], address),
+                    Err(e) => {
+                        println!("    level {} (+{:#x}): {}", level, entry.pointer_chain[level - 1], e);
+                        break;
+                    },
+                }
+            }
+            if let Some(value_type) = entry.value_type {
+                let path = PointerPath { base: static_address, offsets: entry.pointer_chain.clone(), value_type: Some(value_type) };
+                match path.read_value(ffxiv) {
+                    Ok(value) => println!("    value {:?}", value),
+                    Err(e) => println!("    value: {}", e),
+                }
+            }
+        },
+        Err(e) => println!("    static pointer: {}", e),
+    }
+
+    let start = address.saturating_sub(CONTEXT);
+    match vm_read::read(ffxiv, start, len + 2 * CONTEXT) {
+        Some(bytes) => {
+            for line in hexdump(start, &bytes, address..address + len) {
+                println!("    {}", line);
+            }
+        },
+        None => println!("    couldn't read the surrounding bytes"),
+    }
+}
+
+/// Formats `bytes`, which start at `address`, 16 to a line. Bytes in `highlight` are bracketed.
+fn hexdump(address: usize, bytes: &[u8], highlight: std::ops::Range<usize>) -> Vec<String> {
+    bytes.chunks(16).enumerate().map(|(line, chunk)| {
+        let line_address = address + line * 16;
+        let hex = chunk.iter().enumerate().map(|(i, byte)| {
+            if highlight.contains(&(line_address + i)) {
+                format!("[{:02x}]", byte)
+            } else {
+                format!(" {:02x} ", byte)
+            }
+        }).collect::<String>();
+        let ascii = chunk.iter()
+            .map(|byte| if byte.is_ascii_graphic() { *byte as char } else { '.' })
+            .collect::<String>();
+        format!("{:#014x} {:<64} |{}|", line_address, hex, ascii)
+    }).collect()
+}
+
+#[cfg(test)]
+mod check_tests {
+    use crate::mem::check::*;
+
+    #[test]
+    fn hexdump_highlights_match() {
+        let bytes = (0x40..0x54u8).collect::<Vec<_>>();
+        let lines = hexdump(0x1000, &bytes, 0x100e..0x1011);
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("0x000000001000  40  41 "));
+        assert!(lines[0].contains("[4e][4f]"));
+        assert!(lines[0].ends_with("|@ABCDEFGHIJKLMNO|"));
+        assert!(lines[1].starts_with("0x000000001010 [50] 51 "));
+    }
+}
@@ -0,0 +1,154 @@
+use std::io::Write;
+
+use serde::Deserialize;
+
+use flate2::Compression;
+use flate2::write::DeflateEncoder;
+
+/// Compression applied to the entries of each tick frame. The host picks the first codec in its
+/// configured preference list that the client offers in its handshake.
+#[derive(Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    None,
+    Deflate,
+    Lz4,
+    Zstd,
+}
+
+impl Codec {
+    pub fn id(self) -> u8 {
+        match self {
+            Codec::None => 0,
+            Codec::Deflate => 1,
+            Codec::Lz4 => 2,
+            Codec::Zstd => 3,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Codec> {
+        match id {
+            0 => Some(Codec::None),
+            1 => Some(Codec::Deflate),
+            2 => Some(Codec::Lz4),
+            3 => Some(Codec::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Picks the first codec of `preference` that is also in `offered`, falling back to no
+    /// compression, which every client supports.
+    pub fn negotiate(preference: &[Codec], offered: &[Codec]) -> Codec {
+        preference.iter()
+            .find(|codec| offered.contains(codec))
+            .cloned()
+            .unwrap_or(Codec::None)
+    }
+
+    pub fn compress(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Codec::None => data.to_vec(),
+            Codec::Deflate => {
+                let mut deflate = DeflateEncoder::new(Vec::new(), Compression::fast());
+                deflate.write_all(data).expect("Unable to deflate frame");
+                deflate.finish().expect("Unable to deflate frame")
+            },
+            Codec::Lz4 => lz4_flex::compress_prepend_size(data),
+            Codec::Zstd => zstd::encode_all(data, 1).expect("Unable to compress frame"),
+        }
+    }
+}
+
+pub fn default_codecs() -> Vec<Codec> {
+    vec![Codec::Lz4, Codec::Zstd, Codec::Deflate, Codec::None]
+}
+
+#[cfg(test)]
+mod codec_tests {
+    use crate::mem::codec::*;
+    use crate::mem::models::Combatant;
+    use crate::mem::packets::{FrameEncoder, SyncPacket, SyncTick};
+    use byteorder::{LittleEndian as LE, WriteBytesExt};
+    use std::io::Cursor;
+    use std::time::Instant;
+
+    #[test]
+    fn negotiate_codec() {
+        let preference = default_codecs();
+        assert_eq!(Codec::negotiate(&preference, &[Codec::None, Codec::Deflate]), Codec::Deflate);
+        assert_eq!(Codec::negotiate(&preference, &[Codec::Deflate, Codec::Zstd]), Codec::Zstd);
+        assert_eq!(Codec::negotiate(&preference, &[Codec::Lz4, Codec::Deflate]), Codec::Lz4);
+        assert_eq!(Codec::negotiate(&preference, &[]), Codec::None);
+        assert_eq!(Codec::negotiate(&[Codec::Deflate], &[Codec::Lz4]), Codec::None);
+    }
+
+    #[test]
+    fn compress_round_trip() {
+        let data = (0..4000u32).map(|i| (i % 13) as u8).collect::<Vec<_>>();
+        assert_eq!(Codec::None.compress(&data), data);
+        assert_eq!(lz4_flex::decompress_size_prepended(&Codec::Lz4.compress(&data)).unwrap(), data);
+        assert_eq!(zstd::decode_all(Codec::Zstd.compress(&data).as_slice()).unwrap(), data);
+        let mut inflated = Vec::new();
+        let mut inflate = flate2::write::DeflateDecoder::new(&mut inflated);
+        inflate.write_all(&Codec::Deflate.compress(&data)).unwrap();
+        inflate.finish().unwrap();
+        assert_eq!(inflated, data);
+    }
+
+    fn full_mob_tick() -> Vec<Combatant> {
+        (0..421u32).map(|i| {
+            let mut actor = vec![0u8; 11520];
+            let mut cursor = Cursor::new(&mut actor);
+            cursor.get_mut()[48..48 + 12].copy_from_slice(&format!("Combatant{:03}", i).as_bytes()[..12]);
+            cursor.set_position(116);
+            cursor.write_u32::<LE>(0x4000_0000 + i).unwrap();
+            cursor.set_position(160);
+            cursor.write_f32::<LE>(100.0 + i as f32 * 0.37).unwrap();
+            cursor.write_f32::<LE>(-3.5).unwrap();
+            cursor.write_f32::<LE>(42.0 - i as f32 * 0.11).unwrap();
+            cursor.set_position(6308);
+            cursor.write_u32::<LE>(90_000 + i * 17).unwrap();
+            cursor.write_u32::<LE>(120_000).unwrap();
+            cursor.write_u32::<LE>(10_000).unwrap();
+            cursor.write_u32::<LE>(10_000).unwrap();
+            cursor.set_position(6364);
+            cursor.write_u8((i % 38) as u8).unwrap();
+            cursor.set_position(6366);
+            cursor.write_u8(80).unwrap();
+            Combatant::from_slice(&actor)
+        }).collect()
+    }
+
+    /// Compares bytes on the wire and encoding time for a full 421-mob tick across the legacy
+    /// per-combatant gzip and each frame codec. Run with
+    /// `cargo test --release codec_benchmark -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn codec_benchmark() {
+        const ROUNDS: u32 = 200;
+        let mobs = full_mob_tick();
+
+        let start = Instant::now();
+        let mut legacy_bytes = 0;
+        for _ in 0..ROUNDS {
+            legacy_bytes = mobs.iter().map(|mob| mob.binary_serialize_compressed().len() + 29).sum::<usize>();
+        }
+        println!("{:>16} {:>8} bytes {:>10.1?}/tick", "gzip per mob", legacy_bytes, start.elapsed() / ROUNDS);
+
+        for codec in &[Codec::None, Codec::Deflate, Codec::Lz4, Codec::Zstd] {
+            let start = Instant::now();
+            let mut bytes = 0;
+            let mut datagrams = 0;
+            let mut encoder = FrameEncoder::new(*codec);
+            for _ in 0..ROUNDS {
+                let tick = mobs.iter().enumerate()
+                    .map(|(i, mob)| SyncPacket::MobUpdate(i as u16, i as u64, mob.binary_serialize()))
+                    .collect::<SyncTick>();
+                let encoded = encoder.encode_tick(tick, 0, &mut 0);
+                datagrams = encoded.len();
+                bytes = encoded.iter().map(|datagram| datagram.len()).sum::<usize>();
+            }
+            println!("{:>16} {:>8} bytes {:>10.1?}/tick ({} datagrams)", format!("{:?} frames", codec), bytes, start.elapsed() / ROUNDS, datagrams);
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use proc_maps::Pid;
+
+use crate::mem::{ParseSignature, Signature};
+use crate::mem::pe::GameImage;
+use crate::mem::reader;
+use crate::mem::scanner::MultiPatternScanner;
+use crate::mem::sig_import::check_pattern;
+use crate::mem::vm_read;
+use crate::utils::find_subsequence;
+
+/// Length of the byte runs of the old signature looked up in the new binary to find candidates.
+const GRAM_LEN: usize = 3;
+/// How many of the best-voted candidates are compared byte by byte.
+const CANDIDATES: usize = 64;
+
+/// The closest match in the new binary for a signature from the old one.
+#[derive(Debug, PartialEq)]
+pub struct Discovery {
+    /// Where in the new binary the match starts.
+    pub offset: usize,
+    /// How many of the old signature's non-wildcard bytes are unchanged there.
+    pub matched: usize,
+    pub compared: usize,
+    /// The old signature with every byte that changed turned into a wildcard.
+    pub pattern: String,
+    /// How many places in the new binary `pattern` matches. Anything but 1 needs a closer look.
+    pub matches: usize,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum DiscoverError {
+    /// The old signature doesn't match the old binary, so there's nothing to go on.
+    NotInOldBinary,
+    /// Not even a few bytes of the signature turn up in the new binary.
+    NoCandidate,
+}
+
+/// Finds where `signature`, which matches `old`, most likely moved to in `new`. The bytes of the
+/// old match are compared against candidate positions in the new binary, so immediates and
+/// displacements that changed with the patch only lower a candidate's score instead of ruling it
+/// out, and the bytes that differ become wildcards in the proposed pattern.
+pub fn discover(signature: &Signature, old: &[u8], new: &[u8]) -> Result<Discovery, DiscoverError> {
+    let old_offset = find_subsequence(old, &signature.signature_bytes, signature.wildcard_ranges.as_ref())
+        .ok_or(DiscoverError::NotInOldBinary)?;
+    let len = signature.signature_bytes.len();
+    let old_bytes = &old[old_offset..old_offset + len];
+    let literal = (0..len)
+        .map(|i| signature.wildcard_ranges.iter().flatten().all(|wild| !wild.contains(&i)))
+        .collect::<Vec<_>>();
+
+    // Every run of literal bytes votes for where the signature would start if it came from there.
+    let grams = (0..len.saturating_sub(GRAM_LEN - 1))
+        .filter(|start| literal[*start..*start + GRAM_LEN].iter().all(|literal| *literal))
+        .collect::<Vec<_>>();
+    if grams.is_empty() {
+        return Err(DiscoverError::NoCandidate);
+    }
+    let gram_signatures = grams.iter()
+        .map(|start| Signature { signature_bytes: old_bytes[*start..*start + GRAM_LEN].to_vec(), wildcard_ranges: None })
+        .collect::<Vec<_>>();
+    let mut votes: HashMap<usize, usize> = HashMap::new();
+    for (gram, offset) in MultiPatternScanner::new(&gram_signatures).find_iter(new) {
+        if let Some(start) = offset.checked_sub(grams[gram]) {
+            if start + len <= new.len() {
+                *votes.entry(start).or_insert(0) += 1;
+            }
+        }
+    }
+    let mut candidates = votes.into_iter().collect::<Vec<_>>();
+    candidates.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+    let score = |start: usize| (0..len).filter(|i| literal[*i] && new[start + i] == old_bytes[*i]).count();
+    let (offset, matched) = candidates.iter()
+        .take(CANDIDATES)
+        .map(|(start, _)| (*start, score(*start)))
+        .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(&a.0)))
+        .ok_or(DiscoverError::NoCandidate)?;
+
+    let pattern = (0..len)
+        .map(|i| if literal[i] && new[offset + i] == old_bytes[i] { format!("{:02x}", old_bytes[i]) } else { "??".to_string() })
+        .collect::<String>();
+    let proposed = pattern.parse_signature();
+    let matches = MultiPatternScanner::new(&[proposed]).find_iter(new).count();
+
+    Ok(Discovery { offset, matched, compared: literal.iter().filter(|literal| **literal).count(), pattern, matches })
+}
+
+/// Proposes an updated pattern for `signature`, a name from the signature file or a pattern, from
+/// the old game binary at `old_path` and the new one at `new_path`, or the running game's code if
+/// there's no new binary. Returns whether a unique match was found.
+pub fn run_discover(signature: &str, old_path: &str, new_path: Option<&str>, ffxiv: Option<Pid>, signature_file: &Path) -> bool {
+    let pattern = reader::load_registry(signature_file).ok()
+        .and_then(|registry| registry.into_iter().find(|entry| entry.name == signature))
+        .map(|entry| entry.pattern)
+        .unwrap_or_else(|| signature.to_string());
+    if let Err(e) = check_pattern(&pattern) {
+        eprintln!("[{}] isn't in the signature file or a usable pattern: {}", signature, e);
+        return false;
+    }
+
+    let old = match fs::read(old_path) {
+        Ok(old) => old,
+        Err(e) => {
+            eprintln!("Couldn't read {}: {}", old_path, e);
+            return false;
+        }
+    };
+    let new = match (new_path, ffxiv) {
+        (Some(new_path), _) => fs::read(new_path).map_err(|e| format!("Couldn't read {}: {}", new_path, e)),
+        (None, Some(ffxiv)) => GameImage::find(ffxiv)
+            .and_then(|game| game.text())
+            .and_then(|text| vm_read::read(ffxiv, text.start, text.end - text.start))
+            .ok_or_else(|| "Couldn't read the running game's code".to_string()),
+        (None, None) => Err("FFXIV isn't running and no new binary was given.".to_string()),
+    };
+    let new = match new {
+        Ok(new) => new,
+        Err(e) => {
+            eprintln!("{}", e);
+            return false;
+        }
+    };
+
+    match discover(&pattern.parse_signature(), &old, &new) {
+        Ok(discovery) => {
+            println!("Old pattern:      {}", pattern);
+            println!("Proposed pattern: {}", discovery.pattern);
+            println!("Best match at offset {:#x}, {} of {} bytes unchanged", discovery.offset, discovery.matched, discovery.compared);
+            if discovery.matches != 1 {
+                println!("The proposed pattern matches {} places; extend it before using it.", discovery.matches);
+            }
+            discovery.matches == 1
+        },
+        Err(DiscoverError::NotInOldBinary) => {
+            eprintln!("The signature doesn't match the old binary.");
+            false
+        },
+        Err(DiscoverError::NoCandidate) => {
+            eprintln!("Nothing resembling the signature was found in the new binary.");
+            false
+        },
+    }
+}
+
+#[cfg(test)]
+mod discover_tests {
+    use crate::mem::discover::*;
+
+    fn noise(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 24) as u8
+        }).collect()
+    }
+
+    #[test]
+    fn follow_changed_displacement() {
+        // mov rcx, [rip+disp32]; test eax, eax; je +0x0e; mov rcx, [rip+disp32]
+        let signature = "488b0d????????85c0740e488b0d".parse_signature();
+        let old_code = hex::decode("488b0d1122334485c0740e488b0d").unwrap();
+        // The patch moved the code and changed both the displacement and the jump distance.
+        let new_code = hex::decode("488b0d5566778885c07412488b0d").unwrap();
+
+        let mut old = noise(100_000, 1);
+        old[40_000..40_000 + old_code.len()].copy_from_slice(&old_code);
+        let mut new = noise(120_000, 2);
+        new[73_123..73_123 + new_code.len()].copy_from_slice(&new_code);
+
+        let discovery = discover(&signature, &old, &new).unwrap();
+        assert_eq!(discovery, Discovery {
+            offset: 73_123,
+            matched: 9,
+            compared: 10,
+            pattern: "488b0d????????85c074??488b0d".to_string(),
+            matches: 1,
+        });
+    }
+
+    #[test]
+    fn trailing_wildcards_parse() {
+        let signature = "????488b0d????".parse_signature();
+        assert_eq!(signature.wildcard_ranges, Some(vec![0..2, 5..7]));
+    }
+
+    #[test]
+    fn reject_half_byte_wildcards() {
+        assert_eq!(check_pattern("488b0d????????"), Ok(()));
+        assert_eq!(check_pattern("4?8b"), Err("byte 0 is only half a wildcard".to_string()));
+        assert_eq!(check_pattern("48?8b?"), Err("byte 1 is only half a wildcard".to_string()));
+        assert_eq!(check_pattern("mob_array"), Err("it isn't hex".to_string()));
+        assert!(!run_discover("4?8b", "/nonexistent", None, None, Path::new("/nonexistent")));
+    }
+
+    #[test]
+    fn missing_from_old_binary() {
+        let signature = "488b0d85c0740e".parse_signature();
+        assert_eq!(discover(&signature, &noise(1000, 3), &noise(1000, 4)), Err(DiscoverError::NotInOldBinary));
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::mem::models::Combatant;
+use crate::mem::packets::SyncPacket;
+use crate::net::decode::{Action, Chat, EffectResult, Tick};
+
+/// Something that happened in the game, as seen from what the reader reads or, for the actions,
+/// ticks, effect results and chat, from the decoded network stream.
+#[derive(Clone)]
+pub enum Event {
+    ChangeZone(u32),
+    /// The player's own character, the first slot of the mob array, changed.
+    ChangePrimaryPlayer(Arc<Combatant>),
+    AddCombatant(Arc<Combatant>),
+    RemoveCombatant(Arc<Combatant>),
+    /// Every combatant, each time the mob array is read.
+    Combatants(Arc<Vec<Arc<Combatant>>>),
+    Action(Arc<Action>),
+    Tick(Tick),
+    EffectResult(Arc<EffectResult>),
+    Chat(Arc<Chat>),
+}
+
+/// Turns the reader's ticks into events by remembering what earlier ticks said.
+pub struct Tracker {
+    zone: u32,
+    mobs: BTreeMap<u16, Arc<Combatant>>,
+}
+
+impl Tracker {
+    pub fn new() -> Tracker {
+        Tracker { zone: 0, mobs: BTreeMap::new() }
+    }
+
+    pub fn apply(&mut self, tick: &[SyncPacket]) -> Vec<Event> {
+        let mut events = Vec::new();
+        let mut mobs_read = false;
+        for packet in tick {
+            match packet {
+                SyncPacket::ZoneID(zone) if *zone != self.zone => {
+                    self.zone = *zone;
+                    events.push(Event::ChangeZone(*zone));
+                },
+                SyncPacket::MobUpdate(index, _, data) => {
+                    mobs_read = true;
+                    let combatant = match bincode::deserialize::<Combatant>(data) {
+                        Ok(combatant) => Arc::new(combatant),
+                        Err(_) => continue,
+                    };
+                    let previous = self.mobs.insert(*index, combatant.clone());
+                    if previous.as_ref().map(|previous| previous.id) != Some(combatant.id) {
+                        if let Some(previous) = previous {
+                            events.push(Event::RemoveCombatant(previous));
+                        }
+                        if *index == 0 {
+                            events.push(Event::ChangePrimaryPlayer(combatant.clone()));
+                        }
+                        events.push(Event::AddCombatant(combatant));
+                    }
+                },
+                SyncPacket::MobNull(index) => {
+                    mobs_read = true;
+                    if let Some(previous) = self.mobs.remove(index) {
+                        events.push(Event::RemoveCombatant(previous));
+                    }
+                },
+                // Clients drop their mobs when the signatures change, and so does the tracker.
+                SyncPacket::SignaturesReloaded(_) => {
+                    events.extend(std::mem::take(&mut self.mobs).into_values().map(Event::RemoveCombatant));
+                },
+                _ => {},
+            }
+        }
+        if mobs_read {
+            events.push(Event::Combatants(Arc::new(self.mobs.values().cloned().collect())));
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod events_tests {
+    use crate::mem::events::*;
+
+    fn mob(id: u32, name: &str, hp: u32) -> Vec<u8> {
+        let mut mob = Combatant::named(id, name);
+        mob.current_hp = hp;
+        mob.binary_serialize()
+    }
+
+    fn describe(events: Vec<Event>) -> Vec<String> {
+        events.into_iter()
+            .map(|event| match event {
+                Event::ChangeZone(zone) => format!("zone {}", zone),
+                Event::ChangePrimaryPlayer(player) => format!("player {}", player.name()),
+                Event::AddCombatant(mob) => format!("add {}", mob.name()),
+                Event::RemoveCombatant(mob) => format!("remove {}", mob.name()),
+                Event::Combatants(mobs) => format!("{} combatants", mobs.len()),
+                _ => unreachable!("the tracker only reads memory"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn track_zone_and_combatants() {
+        let mut tracker = Tracker::new();
+        let events = tracker.apply(&[
+            SyncPacket::ZoneID(132),
+            SyncPacket::MobUpdate(0, 0x1000, mob(0x10000001, "Kate Arch", 1000)),
+            SyncPacket::MobUpdate(1, 0x2000, mob(0x40000002, "Striking Dummy", 5000)),
+        ]);
+        assert_eq!(describe(events), vec!["zone 132", "player Kate Arch", "add Kate Arch", "add Striking Dummy", "2 combatants"]);
+
+        let events = tracker.apply(&[
+            SyncPacket::ZoneID(132),
+            SyncPacket::MobUpdate(0, 0x1000, mob(0x10000001, "Kate Arch", 900)),
+            SyncPacket::MobUpdate(1, 0x3000, mob(0x40000003, "Ifrit", 9000)),
+            SyncPacket::MobNull(2),
+        ]);
+        assert_eq!(describe(events), vec!["remove Striking Dummy", "add Ifrit", "2 combatants"]);
+
+        let events = tracker.apply(&[SyncPacket::MobNull(1), SyncPacket::ServerTime(0)]);
+        assert_eq!(describe(events), vec!["remove Ifrit", "1 combatants"]);
+        assert!(tracker.apply(&[SyncPacket::ServerTime(1)]).is_empty());
+    }
+}
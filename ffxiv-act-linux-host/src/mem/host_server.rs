@@ -10,24 +10,32 @@ use std::thread::JoinHandle;
 use std::sync::mpsc::{Receiver, Sender};
 use std::sync::mpsc;
 use std::sync::{Arc, Mutex};
-use crate::mem::packets::{SyncPacket, EncodePacket};
+use crate::mem::packets::{SyncTick, FrameEncoder, encode_handshake, encode_refusal, encode_announcement, PROTOCOL_VERSION};
+use crate::mem::codec::Codec;
+use crate::metrics::Metrics;
 use std::collections::HashMap;
 use std::time::Duration;
 
 const MEMORY_MAGIC:[u8; 8] = [7,2,6,2,2,5,4,4];
 const KEEP_ALIVE_MAGIC:[u8; 8] = [123,157,225,223,116,254,178,126];
+/// Broadcast by clients that have no host address configured.
+const DISCOVERY_MAGIC:[u8; 8] = [68,73,83,67,55,50,54,50];
 
 struct MemoryClient {
     pub keep_alive_sender: Sender<()>,
-    pub memory_sender: Sender<SyncPacket>,
+    pub memory_sender: Sender<SyncTick>,
 }
 
-pub fn run_server(rx: Receiver<SyncPacket>, addr: String) -> JoinHandle<Result<(), ServerError>> {
+/// Serves memory-sync to every client that connects, and answers clients looking for a host with
+/// the memory-sync and network-passthrough ports. What is sent to each client is counted in
+/// `metrics`.
+pub fn run_server(rx: Receiver<SyncTick>, addr: String, codecs: Vec<Codec>, net_port: u16, metrics: Arc<Metrics>) -> JoinHandle<Result<(), ServerError>> {
     thread::spawn(move || {
         let rx = rx;
         let udp = UdpSocket::bind(&addr).map_err(|_| ServerError::Binding(addr.clone()))?;
-        println!("[MEM] UDP memory-sync socket bound to {}", addr);
+        info!(target: "server", "UDP memory-sync socket bound to {}", addr);
         udp.set_broadcast(true).expect("Unable to set broadcast");
+        let announcement = encode_announcement(udp.local_addr().map(|local| local.port()).unwrap_or(0), net_port);
 
         let udp_ref = Arc::new(udp);
 
@@ -36,7 +44,7 @@ pub fn run_server(rx: Receiver<SyncPacket>, addr: String) -> JoinHandle<Result<(
         let cc1 = client_channels.clone();
         let cc2 = client_channels.clone();
 
-        let mut recv_buffer = [0u8; 8];
+        let mut recv_buffer = [0u8; 64];
         thread::spawn(move || {
             for sync in rx {
                 let channel_lock = cc1.lock().unwrap();
@@ -46,14 +54,24 @@ pub fn run_server(rx: Receiver<SyncPacket>, addr: String) -> JoinHandle<Result<(
             }
         });
         loop {
-            let (_, client) = udp_ref.recv_from(&mut recv_buffer).unwrap();
-            if recv_buffer == MEMORY_MAGIC {
+            let (num, client) = udp_ref.recv_from(&mut recv_buffer).unwrap();
+            let message = &recv_buffer[..num];
+            if message.starts_with(&MEMORY_MAGIC) {
+                let (version, offered) = parse_hello(&message[MEMORY_MAGIC.len()..]);
+                if version != PROTOCOL_VERSION {
+                    warn!(target: "server", "Rejected memory-sync client {} using protocol version {} (host uses {})", client, version, PROTOCOL_VERSION);
+                    let _ = udp_ref.send_to(&encode_refusal(), client);
+                    continue;
+                }
+                let codec = Codec::negotiate(&codecs, &offered);
+                udp_ref.send_to(&encode_handshake(codec), client).unwrap();
                 let (udp_tx, udp_rx) = mpsc::channel();
                 let (ka_tx, ka_rx) = mpsc::channel();
                 cc2.lock().unwrap().insert(client.clone(), MemoryClient{ keep_alive_sender: ka_tx, memory_sender: udp_tx });
                 let cc3 = cc2.clone();
-                println!("[MEM] UDP memory-sync client connected from {}", client);
+                info!(target: "server", "UDP memory-sync client connected from {} using {:?} compression", client, codec);
                 let udp_ref2 = udp_ref.clone();
+                let metrics = metrics.clone();
                 thread::spawn(move || {
                     let udp_rx = udp_rx;
                     let client = client;
@@ -77,19 +95,31 @@ pub fn run_server(rx: Receiver<SyncPacket>, addr: String) -> JoinHandle<Result<(
                             }
                         }
                     });
-                    let mut sync_sequence = 0u64;
-                    'mem_sync: for sync in udp_rx {
-                        let buf = sync.encode_packet(sync_sequence);
-                        udp_ref2.send_to(buf.as_slice(), &client).unwrap();
+                    let mut sync_sequence = 1u64;
+                    let mut encoder = FrameEncoder::new(codec);
+                    let client_label = client.to_string();
+                    'mem_sync: for (tick_id, tick) in udp_rx.into_iter().enumerate() {
+                        for buf in encoder.encode_tick(tick, tick_id as u64, &mut sync_sequence) {
+                            match udp_ref2.send_to(buf.as_slice(), client) {
+                                Ok(sent) => metrics.sent_bytes.add(&client_label, sent as u64),
+                                Err(e) => {
+                                    metrics.dropped_sync_packets.add(1);
+                                    debug!(target: "server", "Unable to send memory-sync to {}: {}", client, e);
+                                },
+                            }
+                        }
                         if let Some(_) = stop_channnel_rx.try_iter().next() {
-                            println!("[MEM] {} missed too many heartbeats, disconnecting.", client);
+                            info!(target: "server", "{} missed too many heartbeats, disconnecting.", client);
                             cc3.lock().unwrap().remove(&client);
+                            metrics.sent_bytes.remove(&client_label);
                             break 'mem_sync;
                         }
-                        sync_sequence += 1;
                     }
                 });
-            } else if recv_buffer == KEEP_ALIVE_MAGIC {
+            } else if message == DISCOVERY_MAGIC {
+                debug!(target: "server", "Answering host discovery from {}", client);
+                let _ = udp_ref.send_to(&announcement, client);
+            } else if message == KEEP_ALIVE_MAGIC {
                 if let Some(mem_client) = cc2.lock().unwrap().get(&client) {
                     mem_client.keep_alive_sender.send(()).expect("Keep alive signal failed to send");
                 }
@@ -99,6 +129,19 @@ pub fn run_server(rx: Receiver<SyncPacket>, addr: String) -> JoinHandle<Result<(
     })
 }
 
+/// Reads the rest of a client's handshake, `[version: u8][count: u8][codec ids...]`. Clients that
+/// predate the handshake send only the magic, which reads as version 0 with no codecs.
+fn parse_hello(hello: &[u8]) -> (u8, Vec<Codec>) {
+    let version = hello.first().cloned().unwrap_or(0);
+    let count = hello.get(1).cloned().unwrap_or(0) as usize;
+    let offered = hello.iter()
+        .skip(2)
+        .take(count)
+        .filter_map(|id| Codec::from_id(*id))
+        .collect();
+    (version, offered)
+}
+
 pub enum ServerError {
     Binding(String),
 }
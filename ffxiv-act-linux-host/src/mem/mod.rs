@@ -2,28 +2,53 @@ mod host_server;
 mod reader;
 mod packets;
 mod models;
+mod codec;
+mod schedule;
+mod vm_read;
+mod scanner;
+mod sig_cache;
+mod pe;
+mod sig_import;
+mod pointer_path;
+mod check;
+mod discover;
+mod watch;
+mod events;
 
 use crate::{Deserialize, MemConfig};
 use crate::hex;
 use std::ops::Range;
+use std::path::{Path, PathBuf};
 use proc_maps::Pid;
-use std::sync::mpsc;
+use std::sync::{mpsc, Arc};
+use std::sync::mpsc::{Receiver, Sender};
+use std::thread;
 use crate::mem::reader::MemErrorType;
+use crate::mem::packets::SyncTick;
+use crate::metrics::Metrics;
+
+pub use self::codec::{Codec, default_codecs};
+pub use self::events::Event;
+pub use self::models::Combatant;
 
 /// Begins the memory portion of the interface. Starts a thread for memory reading and a thread for
-/// memory synchronization to the client.
-pub fn begin(ffxiv: Pid, mem_config: MemConfig) -> bool {
+/// memory synchronization to the client. `net_address` is where network-passthrough listens, which
+/// is announced to clients looking for a host. Both threads record what they do in `metrics`, and
+/// what the reader sees happen in the game is sent to each of `observers`.
+pub fn begin(ffxiv: Pid, mem_config: MemConfig, signature_file: PathBuf, net_address: &str, metrics: Arc<Metrics>, observers: Vec<Sender<Event>>) -> bool {
     let (sender, receiver) = mpsc::channel();
-    let reader_result = reader::run_reader(sender, ffxiv);
+    let reader_result = reader::run_reader(sender, ffxiv, mem_config.tick_rates, signature_file, metrics.clone());
     match reader_result {
         Ok(reader_handle) => {
-            let host_handle = host_server::run_server(receiver, mem_config.bind_address);
+            let receiver = observe_ticks(receiver, observers);
+            let net_port = net_address.rsplit(':').next().and_then(|port| port.parse().ok()).unwrap_or(0);
+            let host_handle = host_server::run_server(receiver, mem_config.bind_address, mem_config.compression, net_port, metrics);
             let host_fine = match host_handle.join() {
                 Ok(host_res) => {
                     match host_res {
                         Ok(_) => true,
                         Err(server_error) => {
-                            eprintln!("[MEM] {:?}", server_error);
+                            error!(target: "server", "{:?}", server_error);
                             false
                         }
                     }
@@ -37,16 +62,7 @@ pub fn begin(ffxiv: Pid, mem_config: MemConfig) -> bool {
             host_fine && reader_fine
         },
         Err(mem_err) => {
-            match mem_err {
-                MemErrorType::OpeningSignatureFile => eprintln!("Failed to open signature file."),
-                MemErrorType::ReadingSignatureFile => eprintln!("Failed to read/parse signature file."),
-                MemErrorType::FindingSignature(sigs) => {
-                    eprintln!("Failed to find the following signatures:");
-                    for sig in sigs {
-                        eprintln!("{:?}", sig);
-                    }
-                },
-            }
+            report_mem_error(mem_err);
             false
         }
     }
@@ -55,47 +71,137 @@ pub fn begin(ffxiv: Pid, mem_config: MemConfig) -> bool {
 
 }
 
-#[derive(Deserialize)]
-struct Signatures {
-    target: String,
-    chat_log: String,
-    mob_array: String,
-    party_list: String,
-    server_time: String,
-    zone_id: String,
-    player: String,
+/// Passes the reader's ticks through a tracker that tells `observers` what happened, on their way
+/// to the memory-sync server. Observers that have gone away are dropped.
+fn observe_ticks(ticks: Receiver<SyncTick>, mut observers: Vec<Sender<Event>>) -> Receiver<SyncTick> {
+    if observers.is_empty() {
+        return ticks;
+    }
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut tracker = events::Tracker::new();
+        for tick in ticks {
+            for event in tracker.apply(&tick) {
+                observers.retain(|observer| observer.send(event.clone()).is_ok());
+            }
+            if sender.send(tick).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
 }
 
-#[derive(Debug)]
-struct Signature {
-    pub signature_bytes: Vec<u8>,
-    pub wildcard_ranges: Option<Vec<Range<usize>>>,
+/// Scans for every signature and prints where each static pointer is. Returns whether every
+/// required signature was found.
+pub fn scan(ffxiv: Pid, signature_file: &Path) -> bool {
+    match reader::load_pointers(signature_file, &ffxiv) {
+        Ok(pointers) => {
+            let mut pointers = pointers.into_iter().collect::<Vec<_>>();
+            pointers.sort_by(|a, b| a.0.cmp(&b.0));
+            for (name, path) in pointers {
+                println!("[{}] {:#x} {:x?}", name, path.base, path.offsets);
+            }
+            true
+        },
+        Err(mem_err) => {
+            report_mem_error(mem_err);
+            false
+        }
+    }
 }
 
-impl Signatures {
-    pub fn get_target(&self) -> Signature {
-        self.target.parse_signature()
-    }
-    pub fn get_chat_log(&self) -> Signature {
-        self.chat_log.parse_signature()
-    }
-    pub fn get_mob_array(&self) -> Signature {
-        self.mob_array.parse_signature()
-    }
-    pub fn get_party_list(&self) -> Signature {
-        self.party_list.parse_signature()
-    }
-    pub fn get_server_time(&self) -> Signature {
-        self.server_time.parse_signature()
+/// Scans for every signature and prints a report of what was found, for fixing the signature file
+/// after a game patch. Returns whether every required signature was found exactly once.
+pub fn check(ffxiv: Pid, signature_file: &Path) -> bool {
+    match check::check_signatures(ffxiv, signature_file) {
+        Ok(healthy) => healthy,
+        Err(mem_err) => {
+            report_mem_error(mem_err);
+            false
+        }
     }
-    pub fn get_zone_id(&self) -> Signature {
-        self.zone_id.parse_signature()
+}
+
+/// Proposes an updated pattern for a signature that broke with a game patch. See
+/// `discover::run_discover`.
+pub fn discover(signature: &str, old_binary: &str, new_binary: Option<&str>, ffxiv: Option<Pid>, signature_file: &Path) -> bool {
+    discover::run_discover(signature, old_binary, new_binary, ffxiv, signature_file)
+}
+
+fn report_mem_error(mem_err: MemErrorType) {
+    match mem_err {
+        MemErrorType::OpeningSignatureFile => error!(target: "mem", "Failed to open signature file."),
+        MemErrorType::ReadingSignatureFile => error!(target: "mem", "Failed to read/parse signature file."),
+        MemErrorType::MissingSignatures(sigs) => {
+            error!(target: "mem", "The signature file has no pattern for the following signatures:");
+            for sig in sigs {
+                error!(target: "mem", "[{}]", sig);
+            }
+        },
+        MemErrorType::UnusablePatterns(sigs) => {
+            error!(target: "mem", "The signature file has patterns that can't be scanned for:");
+            for (sig, reason) in sigs {
+                error!(target: "mem", "[{}]: {}", sig, reason);
+            }
+        },
+        MemErrorType::FindingSignature(sigs, skipped) => {
+            error!(target: "mem", "Failed to find the following signatures:");
+            for sig in sigs {
+                error!(target: "mem", "[{}]", sig);
+            }
+            if !skipped.is_empty() {
+                error!(target: "mem", "The following memory couldn't be read and may hold them:");
+                for skip in skipped {
+                    error!(target: "mem", "{:x?}: {}", skip.range, skip.reason);
+                }
+            }
+        },
+        MemErrorType::ResolvingPointer(sigs) => {
+            error!(target: "mem", "Failed to read the static pointers of the following signatures:");
+            for sig in sigs {
+                error!(target: "mem", "[{}]", sig);
+            }
+        },
+        MemErrorType::LocatingGameImage => error!(target: "mem", "Failed to find the ffxiv_dx11.exe image in the game's memory."),
     }
-    pub fn get_player(&self) -> Signature {
-        self.player.parse_signature()
+}
+
+/// One entry of the signature file.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+struct SignatureEntry {
+    /// What the reader knows the signature as, e.g. `mob_array`.
+    pub name: String,
+    pub pattern: String,
+    /// Pointers to follow from the static address the signature refers to, each read adding its
+    /// offset to the pointer read at the previous level.
+    #[serde(default)]
+    pub pointer_chain: Vec<usize>,
+    /// The reader won't start without a required signature. One that isn't required only turns off
+    /// what it is used for when it can't be found.
+    #[serde(default = "required_by_default")]
+    pub required: bool,
+    /// What the pointer chain ends in, for data read as a single value such as the server time.
+    #[serde(default)]
+    pub value_type: Option<pointer_path::ValueType>,
+}
+
+fn required_by_default() -> bool {
+    true
+}
+
+impl SignatureEntry {
+    pub fn signature(&self) -> Signature {
+        self.pattern.parse_signature()
     }
 }
 
+#[derive(Debug)]
+struct Signature {
+    pub signature_bytes: Vec<u8>,
+    pub wildcard_ranges: Option<Vec<Range<usize>>>,
+}
+
 trait ParseSignature {
     fn parse_signature(&self) -> Signature;
 }
@@ -146,6 +252,10 @@ impl<S: AsRef<str>> ParseWildcards for S {
                 }
             }
         }
+        if is_on_wild_range {
+            let range_end = s.len() / 2 - 1;
+            ranges.push((range_end - cur_range_len)..(range_end + 1));
+        }
         if ranges.len() > 0 {
             Some(ranges)
         } else {
@@ -3,11 +3,10 @@ use std::io::Cursor;
 
 use byteorder::{ReadBytesExt, WriteBytesExt, LittleEndian as LE};
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 use bincode;
 
-use flate2::{GzBuilder, Compression};
 
 #[derive(Copy, Clone)]
 pub struct Target {
@@ -53,7 +52,7 @@ impl Target {
     }
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 pub struct Combatant {
     pub name: [u8; 30],
     pub id: u32,
@@ -216,12 +215,34 @@ impl Combatant {
             })
     }
 
+    /// The name, which the game stores as a NUL-terminated string.
+    pub fn name(&self) -> String {
+        let len = self.name.iter().position(|byte| *byte == 0).unwrap_or(self.name.len());
+        String::from_utf8_lossy(&self.name[..len]).into_owned()
+    }
+
+    /// A combatant with nothing but `id` and `name` set, for tests to fill in the rest of.
+    #[cfg(test)]
+    pub fn named(id: u32, name: &str) -> Combatant {
+        let mut combatant = Combatant::from_slice(&[0u8; 7308][..]);
+        combatant.id = id;
+        combatant.name[..name.len()].copy_from_slice(name.as_bytes());
+        combatant
+    }
+
+    pub fn binary_serialize(&self) -> Vec<u8> {
+        bincode::serialize(&self).expect("Unable to serialize combatant")
+    }
+
+    /// Per-combatant gzip encoding used before frames were compressed as a whole, kept to compare
+    /// the frame codecs against.
+    #[cfg(test)]
     pub fn binary_serialize_compressed(&self) -> Vec<u8> {
         let mut ret = Vec::new();
         let cursor = Cursor::new(&mut ret);
 
-        let mut gz = GzBuilder::new()
-            .write(cursor, Compression::default());
+        let mut gz = flate2::GzBuilder::new()
+            .write(cursor, flate2::Compression::default());
         bincode::serialize_into(&mut gz, &self).expect("Unable to serialize combatant");
         gz.finish().expect("Unable to compress combatant");
         ret
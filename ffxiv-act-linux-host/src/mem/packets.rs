@@ -1,5 +1,24 @@
 use crate::byteorder::{LittleEndian as LE, WriteBytesExt};
 use crate::mem::models::Target;
+use crate::mem::codec::Codec;
+
+/// Largest UDP payload the host will put on the wire. Kept below the usual 1500 byte Ethernet MTU
+/// once IP/UDP headers and any VM bridge overhead are accounted for, so datagrams never need IP
+/// fragmentation.
+pub const MAX_DATAGRAM_SIZE: usize = 1400;
+
+const FRAGMENT_PACKET_ID: u8 = 0x10;
+const FRAGMENT_HEADER_SIZE: usize = 13;
+const FRAME_PACKET_ID: u8 = 0x11;
+const FRAME_HEADER_SIZE: usize = 20;
+const FRAME_LAST_IN_TICK: u8 = 0x01;
+const HANDSHAKE_PACKET_ID: u8 = 0x12;
+/// The codec byte of a handshake reply that refuses the client.
+const NO_CODEC: u8 = 0xff;
+const ANNOUNCEMENT_PACKET_ID: u8 = 0x13;
+
+/// Bumped whenever the memory-sync wire format changes incompatibly.
+pub const PROTOCOL_VERSION: u8 = 2;
 
 #[derive(Clone)]
 pub enum SyncPacket {
@@ -8,10 +27,23 @@ pub enum SyncPacket {
     MobNull(u16),
     Target(Target),
     ServerTime(u64),
+    PartyList(Vec<u8>),
+    /// The host re-scanned its signatures after the signature file changed. Carries the names of
+    /// the signatures now in use.
+    SignaturesReloaded(Vec<String>),
 }
 
+/// Every packet read during one pass of the reader loop. A tick is sent to clients as a unit so
+/// they can apply it all at once.
+pub type SyncTick = Vec<SyncPacket>;
+
 pub trait EncodePacket {
+    /// Encodes the packet on its own. Packets are normally sent inside tick frames instead.
+    #[allow(dead_code)]
     fn encode_packet(self, seq: u64) -> Vec<u8>;
+
+    /// Encodes the packet without a sequence number, for use as an entry inside a tick frame.
+    fn encode_entry(self) -> Vec<u8>;
 }
 
 impl EncodePacket for SyncPacket {
@@ -21,17 +53,28 @@ impl EncodePacket for SyncPacket {
         header.write_u8(get_packet_id(&self)).unwrap();
         header.write_u64::<LE>(seq).unwrap();
 
-        let mut encoded =
-        match self {
-            SyncPacket::ZoneID(zone) => write_zone_packet(header, zone),
-            SyncPacket::MobUpdate(index, pointer, mob_data) => write_mob_packet(header, index, pointer, mob_data),
-            SyncPacket::MobNull(index) => write_mob_null_packet(header, index),
-            SyncPacket::Target(target) => write_target_packet(header, target),
-            SyncPacket::ServerTime(server_time) => write_server_time_packet(header, server_time),
-        };
+        let mut encoded = write_packet_body(header, self);
         encoded.shrink_to_fit();
         encoded
     }
+
+    fn encode_entry(self) -> Vec<u8> {
+        let mut header = Vec::new();
+        header.write_u8(get_packet_id(&self)).unwrap();
+        write_packet_body(header, self)
+    }
+}
+
+fn write_packet_body(header: Vec<u8>, packet: SyncPacket) -> Vec<u8> {
+    match packet {
+        SyncPacket::ZoneID(zone) => write_zone_packet(header, zone),
+        SyncPacket::MobUpdate(index, pointer, mob_data) => write_mob_packet(header, index, pointer, mob_data),
+        SyncPacket::MobNull(index) => write_mob_null_packet(header, index),
+        SyncPacket::Target(target) => write_target_packet(header, target),
+        SyncPacket::ServerTime(server_time) => write_server_time_packet(header, server_time),
+        SyncPacket::PartyList(party_data) => write_party_list_packet(header, party_data),
+        SyncPacket::SignaturesReloaded(names) => write_signatures_reloaded_packet(header, names),
+    }
 }
 
 #[inline]
@@ -42,7 +85,143 @@ fn get_packet_id(packet_data: &SyncPacket) -> u8 {
         SyncPacket::MobNull(_) => 3,
         SyncPacket::Target(_) => 4,
         SyncPacket::ServerTime(_) => 5,
+        SyncPacket::PartyList(_) => 6,
+        SyncPacket::SignaturesReloaded(_) => 7,
+    }
+}
+
+/// Splits an encoded packet into fragment datagrams if it doesn't fit in a single datagram.
+///
+/// Each fragment is laid out as `[0x10][seq: u64][index: u16][count: u16][chunk...]`, where `seq`
+/// is the sequence number of the packet being fragmented. The client reassembles the chunks in
+/// index order and handles the result as if it had arrived in one piece.
+pub fn fragment_packet(encoded: Vec<u8>, seq: u64) -> Vec<Vec<u8>> {
+    if encoded.len() <= MAX_DATAGRAM_SIZE {
+        return vec![encoded];
+    }
+    let chunks = encoded.chunks(MAX_DATAGRAM_SIZE - FRAGMENT_HEADER_SIZE);
+    let count = chunks.len();
+    assert!(count <= u16::MAX as usize, "sync packet too large to fragment");
+    chunks
+        .enumerate()
+        .map(|(index, chunk)| {
+            let mut fragment = Vec::with_capacity(FRAGMENT_HEADER_SIZE + chunk.len());
+            fragment.write_u8(FRAGMENT_PACKET_ID).unwrap();
+            fragment.write_u64::<LE>(seq).unwrap();
+            fragment.write_u16::<LE>(index as u16).unwrap();
+            fragment.write_u16::<LE>(count as u16).unwrap();
+            fragment.extend_from_slice(chunk);
+            fragment
+        })
+        .collect()
+}
+
+/// Packs the packets of each reader tick into frames for one client, compressing the entries of
+/// every frame with the codec negotiated in that client's handshake.
+///
+/// A frame is laid out as `[0x11][seq: u64][tick: u64][index: u16][flags: u8]` followed by the
+/// (compressed) entries, each `[len: u16][packet id][packet body...]`. The low bit of `flags`
+/// marks the last frame of a tick, which tells the client the tick is complete; the high nibble is
+/// the codec id. Each frame takes the next value of `seq`.
+pub struct FrameEncoder {
+    codec: Codec,
+    /// Running estimate of compressed size over raw size, used to pack enough entries into a
+    /// frame that it still fits in one datagram once compressed.
+    ratio: f64,
+}
+
+impl FrameEncoder {
+    pub fn new(codec: Codec) -> FrameEncoder {
+        FrameEncoder { codec, ratio: 1.0 }
+    }
+
+    /// Coalesces the packets of one reader tick into frames of about `MAX_DATAGRAM_SIZE`. A packet
+    /// too large for a frame on its own, or a frame that compressed worse than expected, is
+    /// fragmented.
+    pub fn encode_tick(&mut self, tick: SyncTick, tick_id: u64, seq: &mut u64) -> Vec<Vec<u8>> {
+        let budget = self.raw_budget();
+        let mut datagrams = Vec::new();
+        let mut entries: Vec<u8> = Vec::new();
+        let mut frame_index = 0u16;
+
+        for packet in tick {
+            let entry = packet.encode_entry();
+            let entry_len = 2 + entry.len();
+            if !entries.is_empty() && entries.len() + entry_len > budget {
+                let frame = self.write_frame(*seq, tick_id, frame_index, 0, &entries);
+                datagrams.extend(fragment_packet(frame, *seq));
+                *seq += 1;
+                frame_index += 1;
+                entries.clear();
+            }
+            entries.write_u16::<LE>(entry.len() as u16).unwrap();
+            entries.extend_from_slice(&entry);
+        }
+
+        let frame = self.write_frame(*seq, tick_id, frame_index, FRAME_LAST_IN_TICK, &entries);
+        datagrams.extend(fragment_packet(frame, *seq));
+        *seq += 1;
+        datagrams
+    }
+
+    fn raw_budget(&self) -> usize {
+        let capacity = (MAX_DATAGRAM_SIZE - FRAME_HEADER_SIZE) as f64;
+        if self.codec == Codec::None {
+            capacity as usize
+        } else {
+            // Leave some headroom, since entries vary in how well they compress.
+            (capacity * 0.9 / self.ratio) as usize
+        }
     }
+
+    fn write_frame(&mut self, seq: u64, tick_id: u64, index: u16, flags: u8, entries: &[u8]) -> Vec<u8> {
+        let compressed = self.codec.compress(entries);
+        if !entries.is_empty() {
+            let ratio = compressed.len() as f64 / entries.len() as f64;
+            self.ratio = (self.ratio * 0.8 + ratio * 0.2).clamp(0.05, 1.5);
+        }
+
+        let mut frame = Vec::with_capacity(FRAME_HEADER_SIZE + compressed.len());
+        frame.write_u8(FRAME_PACKET_ID).unwrap();
+        frame.write_u64::<LE>(seq).unwrap();
+        frame.write_u64::<LE>(tick_id).unwrap();
+        frame.write_u16::<LE>(index).unwrap();
+        frame.write_u8(flags | (self.codec.id() << 4)).unwrap();
+        frame.extend_from_slice(&compressed);
+        frame
+    }
+}
+
+/// Replies to a client's handshake with the host's protocol version and the codec its frames will
+/// be compressed with, laid out as `[0x12][seq: u64][version: u8][codec: u8]`. The sequence is
+/// always 0 since the client handles the reply before any frame.
+pub fn encode_handshake(codec: Codec) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(11);
+    packet.write_u8(HANDSHAKE_PACKET_ID).unwrap();
+    packet.write_u64::<LE>(0).unwrap();
+    packet.write_u8(PROTOCOL_VERSION).unwrap();
+    packet.write_u8(codec.id()).unwrap();
+    packet
+}
+
+/// Refuses a client of another protocol version: a handshake reply with no codec, from which the
+/// client reads the host's version and reports the mismatch.
+pub fn encode_refusal() -> Vec<u8> {
+    let mut packet = encode_handshake(Codec::None);
+    packet[10] = NO_CODEC;
+    packet
+}
+
+/// Answers a client looking for hosts on the LAN, laid out as
+/// `[0x13][version: u8][memory-sync port: u16][network-passthrough port: u16]`. The client takes the
+/// host's address from where the answer came from.
+pub fn encode_announcement(mem_port: u16, net_port: u16) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(6);
+    packet.write_u8(ANNOUNCEMENT_PACKET_ID).unwrap();
+    packet.write_u8(PROTOCOL_VERSION).unwrap();
+    packet.write_u16::<LE>(mem_port).unwrap();
+    packet.write_u16::<LE>(net_port).unwrap();
+    packet
 }
 
 fn write_zone_packet(header: Vec<u8>, zone_id: u32) -> Vec<u8> {
@@ -73,6 +252,25 @@ fn write_mob_null_packet(header: Vec<u8>, index: u16) -> Vec<u8> {
     packet
 }
 
+fn write_party_list_packet(header: Vec<u8>, party_data: Vec<u8>) -> Vec<u8> {
+    let mut packet = header;
+    packet.write_u64::<LE>(party_data.len() as u64).unwrap();
+    packet.extend_from_slice(&party_data);
+    packet
+}
+
+/// Laid out as `[count: u8]` followed by `[len: u8][name...]` for each signature name.
+fn write_signatures_reloaded_packet(header: Vec<u8>, names: Vec<String>) -> Vec<u8> {
+    let mut packet = header;
+    packet.write_u8(names.len().min(u8::MAX as usize) as u8).unwrap();
+    for name in names.iter().take(u8::MAX as usize) {
+        let name = &name.as_bytes()[..name.len().min(u8::MAX as usize)];
+        packet.write_u8(name.len() as u8).unwrap();
+        packet.extend_from_slice(name);
+    }
+    packet
+}
+
 fn write_target_packet(header: Vec<u8>, target: Target) -> Vec<u8>  {
     let mut packet = header;
     packet.write_u64::<LE>(target.target).unwrap();
@@ -86,6 +284,7 @@ mod sync_packet_tests {
 
     use crate::mem::packets::*;
     use crate::mem::models::Target;
+    use crate::mem::codec::Codec;
 
     #[test]
     fn encode_zone() {
@@ -140,4 +339,104 @@ mod sync_packet_tests {
         assert_eq!(packet.len(), expected.len());
         assert_eq!(packet, expected);
     }
+
+    #[test]
+    fn encode_signatures_reloaded_packet() {
+        let packet = SyncPacket::SignaturesReloaded(vec!["target".to_string(), "zone_id".to_string()]);
+        let packet = packet.encode_entry();
+        let expected = [&[7u8, 2, 6][..], b"target", &[7], b"zone_id"].concat();
+        assert_eq!(packet, expected);
+    }
+
+    #[test]
+    fn small_packet_is_not_fragmented() {
+        let datagrams = fragment_packet(SyncPacket::ZoneID(641).encode_packet(8), 8);
+        assert_eq!(datagrams, vec![SyncPacket::ZoneID(641).encode_packet(8)]);
+    }
+
+    #[test]
+    fn fragment_party_list_packet() {
+        let party_data = (0..25600u32).map(|i| i as u8).collect::<Vec<_>>();
+        let encoded = SyncPacket::PartyList(party_data.clone()).encode_packet(77);
+        let datagrams = fragment_packet(encoded.clone(), 77);
+
+        assert_eq!(datagrams.len(), 19);
+        let mut reassembled = Vec::new();
+        for (i, datagram) in datagrams.iter().enumerate() {
+            assert!(datagram.len() <= MAX_DATAGRAM_SIZE);
+            assert_eq!(datagram[0], 0x10);
+            assert_eq!(&datagram[1..9], &77u64.to_le_bytes());
+            assert_eq!(&datagram[9..11], &(i as u16).to_le_bytes());
+            assert_eq!(&datagram[11..13], &19u16.to_le_bytes());
+            reassembled.extend_from_slice(&datagram[13..]);
+        }
+        assert_eq!(reassembled, encoded);
+    }
+
+    #[test]
+    fn encode_tick_single_frame() {
+        let tick = vec![SyncPacket::ZoneID(641), SyncPacket::MobNull(65000)];
+        let mut seq = 3;
+        let datagrams = FrameEncoder::new(Codec::None).encode_tick(tick, 12, &mut seq);
+        let expected = vec![vec![
+            0x11u8, 3,0,0,0,0,0,0,0, 12,0,0,0,0,0,0,0, 0,0, 1,
+            5,0, 1, 0x81,0x02,0,0,
+            3,0, 3, 0xe8,0xfd,
+        ]];
+        assert_eq!(datagrams, expected);
+        assert_eq!(seq, 4);
+    }
+
+    #[test]
+    fn encode_tick_splits_frames() {
+        let tick = (0..421u16).map(|i| SyncPacket::MobUpdate(i, i as u64, vec![0u8; 90])).collect::<SyncTick>();
+        let mut seq = 0;
+        let datagrams = FrameEncoder::new(Codec::None).encode_tick(tick, 1, &mut seq);
+
+        assert_eq!(datagrams.len() as u64, seq);
+        let mut entries = 0;
+        for (i, datagram) in datagrams.iter().enumerate() {
+            assert!(datagram.len() <= MAX_DATAGRAM_SIZE);
+            assert_eq!(datagram[0], 0x11);
+            assert_eq!(&datagram[17..19], &(i as u16).to_le_bytes());
+            assert_eq!(datagram[19], if i == datagrams.len() - 1 { 1 } else { 0 });
+            let mut pos = 20;
+            while pos < datagram.len() {
+                let len = u16::from_le_bytes([datagram[pos], datagram[pos + 1]]) as usize;
+                assert_eq!(datagram[pos + 2], 2);
+                pos += 2 + len;
+                entries += 1;
+            }
+            assert_eq!(pos, datagram.len());
+        }
+        assert_eq!(entries, 421);
+    }
+
+    #[test]
+    fn encode_tick_compressed() {
+        let tick = (0..421u16).map(|i| SyncPacket::MobUpdate(i, i as u64, vec![7u8; 90])).collect::<SyncTick>();
+        let mut encoder = FrameEncoder::new(Codec::Lz4);
+        let mut seq = 0;
+        let first = encoder.encode_tick(tick.clone(), 1, &mut seq);
+        let second = encoder.encode_tick(tick, 2, &mut seq);
+
+        assert!(second.len() < first.len());
+        for datagram in first.iter().chain(second.iter()) {
+            assert!(datagram.len() <= MAX_DATAGRAM_SIZE);
+            assert_eq!(datagram[19] >> 4, Codec::Lz4.id());
+        }
+    }
+
+    #[test]
+    fn encode_handshake_packet() {
+        let packet = encode_handshake(Codec::Deflate);
+        assert_eq!(packet, vec![0x12u8, 0,0,0,0,0,0,0,0, PROTOCOL_VERSION, 1]);
+        assert_eq!(encode_refusal(), vec![0x12u8, 0,0,0,0,0,0,0,0, PROTOCOL_VERSION, 0xff]);
+    }
+
+    #[test]
+    fn encode_announcement_packet() {
+        let packet = encode_announcement(7262, 54992);
+        assert_eq!(packet, vec![0x13u8, PROTOCOL_VERSION, 0x5e, 0x1c, 0xd0, 0xd6]);
+    }
 }
\ No newline at end of file
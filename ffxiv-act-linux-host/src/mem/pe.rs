@@ -0,0 +1,150 @@
+use std::io::Cursor;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use byteorder::{LittleEndian as LE, ReadBytesExt};
+use proc_maps::{get_process_maps, Pid};
+
+use crate::mem::vm_read;
+
+const GAME_EXECUTABLE: &str = "ffxiv_dx11.exe";
+/// Where a 64-bit executable is loaded unless it asks to be relocated. Used when Wine has mapped
+/// the image anonymously, so it can't be found by name.
+const DEFAULT_IMAGE_BASE: usize = 0x1_4000_0000;
+/// The DOS header, PE headers and section table all fit in the image's first page.
+const HEADER_SIZE: usize = 0x1000;
+
+/// A section of a PE image, relative to the image base.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Section {
+    pub name: String,
+    pub rva: usize,
+    pub size: usize,
+}
+
+/// Where the game executable lives on disk and where its PE image is mapped in the game's process.
+pub struct GameImage {
+    pub path: Option<PathBuf>,
+    pub base: usize,
+    pub sections: Vec<Section>,
+}
+
+impl GameImage {
+    /// Locates the game's image in the Wine address space and reads its section table.
+    pub fn find(pid: Pid) -> Option<GameImage> {
+        let mapped = get_process_maps(pid).ok()?
+            .into_iter()
+            .filter(|map| map.filename().as_ref().is_some_and(|name| is_game_executable(name)))
+            .min_by_key(|map| map.start());
+        let (path, base) = match mapped {
+            Some(map) => (map.filename().as_ref().map(PathBuf::from), map.start()),
+            None => (None, DEFAULT_IMAGE_BASE),
+        };
+        let headers = vm_read::read(pid, base, HEADER_SIZE)?;
+        let sections = parse_sections(&headers)?;
+        Some(GameImage { path, base, sections })
+    }
+
+    /// The absolute address range of the game's code.
+    pub fn text(&self) -> Option<Range<usize>> {
+        self.sections.iter()
+            .find(|section| section.name == ".text")
+            .map(|section| self.base + section.rva..self.base + section.rva + section.size)
+    }
+}
+
+fn is_game_executable(name: &str) -> bool {
+    Path::new(name).file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.eq_ignore_ascii_case(GAME_EXECUTABLE))
+}
+
+/// Reads the section table out of the headers at the start of a PE image.
+pub fn parse_sections(headers: &[u8]) -> Option<Vec<Section>> {
+    if headers.get(0..2) != Some(b"MZ") {
+        return None;
+    }
+    let mut cursor = Cursor::new(headers);
+    cursor.set_position(0x3c);
+    let pe_offset = cursor.read_u32::<LE>().ok()? as u64;
+    if headers.get(pe_offset as usize..pe_offset as usize + 4) != Some(b"PE\0\0") {
+        return None;
+    }
+
+    // COFF file header: machine, section count, timestamp, symbol table, symbol count, optional
+    // header size, characteristics.
+    cursor.set_position(pe_offset + 4 + 2);
+    let section_count = cursor.read_u16::<LE>().ok()?;
+    cursor.set_position(pe_offset + 4 + 16);
+    let optional_header_size = cursor.read_u16::<LE>().ok()? as u64;
+
+    let table = pe_offset + 4 + 20 + optional_header_size;
+    (0..section_count as u64)
+        .map(|i| {
+            cursor.set_position(table + i * 40);
+            let mut name = [0u8; 8];
+            std::io::Read::read_exact(&mut cursor, &mut name).ok()?;
+            let size = cursor.read_u32::<LE>().ok()? as usize;
+            let rva = cursor.read_u32::<LE>().ok()? as usize;
+            let name = String::from_utf8_lossy(&name).trim_end_matches('\0').to_string();
+            Some(Section { name, rva, size })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod pe_tests {
+    use crate::mem::pe::*;
+    use byteorder::WriteBytesExt;
+
+    /// Builds the headers of a PE32+ image with the given sections.
+    fn headers(sections: &[(&str, u32, u32)]) -> Vec<u8> {
+        let mut headers = vec![0u8; HEADER_SIZE];
+        headers[0..2].copy_from_slice(b"MZ");
+        let mut cursor = Cursor::new(&mut headers);
+        cursor.set_position(0x3c);
+        cursor.write_u32::<LE>(0x80).unwrap();
+        cursor.get_mut()[0x80..0x84].copy_from_slice(b"PE\0\0");
+        cursor.set_position(0x84);
+        cursor.write_u16::<LE>(0x8664).unwrap();
+        cursor.write_u16::<LE>(sections.len() as u16).unwrap();
+        cursor.set_position(0x84 + 16);
+        cursor.write_u16::<LE>(240).unwrap();
+        for (i, (name, size, rva)) in sections.iter().enumerate() {
+            let entry = 0x84 + 20 + 240 + i * 40;
+            cursor.get_mut()[entry..entry + name.len()].copy_from_slice(name.as_bytes());
+            cursor.set_position(entry as u64 + 8);
+            cursor.write_u32::<LE>(*size).unwrap();
+            cursor.write_u32::<LE>(*rva).unwrap();
+        }
+        headers
+    }
+
+    #[test]
+    fn parse_section_table() {
+        let headers = headers(&[(".text", 0x1a2_b000, 0x1000), (".rdata", 0x50_0000, 0x1a3_0000), (".data", 0x10_0000, 0x1f3_0000)]);
+        let sections = parse_sections(&headers).unwrap();
+        assert_eq!(sections.len(), 3);
+        assert_eq!(sections[0], Section { name: ".text".to_string(), rva: 0x1000, size: 0x1a2_b000 });
+        assert_eq!(sections[2].name, ".data");
+
+        let image = GameImage { path: None, base: DEFAULT_IMAGE_BASE, sections };
+        assert_eq!(image.text(), Some(0x1_4000_1000..0x1_41a2_c000));
+    }
+
+    #[test]
+    fn reject_non_pe() {
+        assert_eq!(parse_sections(&[0u8; 64]), None);
+        let mut headers = headers(&[(".text", 0x1000, 0x1000)]);
+        headers[0x80] = b'N';
+        assert_eq!(parse_sections(&headers), None);
+        assert_eq!(parse_sections(b"MZ"), None);
+    }
+
+    #[test]
+    fn recognise_game_executable() {
+        assert!(is_game_executable("/games/ffxiv/drive_c/Program Files (x86)/SquareEnix/FINAL FANTASY XIV - A Realm Reborn/game/ffxiv_dx11.exe"));
+        assert!(is_game_executable("/games/FFXIV_DX11.EXE"));
+        assert!(!is_game_executable("/games/ffxiv.exe"));
+    }
+}
@@ -0,0 +1,164 @@
+use std::io::Cursor;
+
+use byteorder::{LittleEndian as LE, ReadBytesExt};
+use proc_maps::Pid;
+use serde::Deserialize;
+
+use crate::mem::vm_read;
+
+/// What a pointer path ends in.
+#[derive(Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ValueType {
+    U8,
+    U16,
+    U32,
+    U64,
+    F32,
+}
+
+impl ValueType {
+    fn size(self) -> usize {
+        match self {
+            ValueType::U8 => 1,
+            ValueType::U16 => 2,
+            ValueType::U32 | ValueType::F32 => 4,
+            ValueType::U64 => 8,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Value {
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    U64(u64),
+    F32(f32),
+}
+
+impl Value {
+    pub fn as_u64(self) -> u64 {
+        match self {
+            Value::U8(value) => value as u64,
+            Value::U16(value) => value as u64,
+            Value::U32(value) => value as u64,
+            Value::U64(value) => value,
+            Value::F32(value) => value as u64,
+        }
+    }
+}
+
+/// Where following a pointer path went wrong. Levels count the pointers read, from 1.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PathError {
+    /// The pointer at this level couldn't be read from `address`.
+    ReadingPointer { level: usize, address: usize },
+    /// The pointer at this level, read from `address`, was null; the game hasn't set it up yet.
+    NullPointer { level: usize, address: usize },
+    /// The value at the end of the path couldn't be read from `address`.
+    ReadingValue { address: usize },
+}
+
+impl std::fmt::Display for PathError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PathError::ReadingPointer { level, address } => write!(f, "couldn't read pointer {} at {:#x}", level, address),
+            PathError::NullPointer { level, address } => write!(f, "pointer {} at {:#x} is null", level, address),
+            PathError::ReadingValue { address } => write!(f, "couldn't read the value at {:#x}", address),
+        }
+    }
+}
+
+/// A static address in the game's image and the pointers to follow from it: at each level the
+/// pointer stored at the current address is read and the level's offset added to it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PointerPath {
+    pub base: usize,
+    pub offsets: Vec<usize>,
+    pub value_type: Option<ValueType>,
+}
+
+impl PointerPath {
+    /// Follows the path to the address it ends at.
+    pub fn resolve(&self, pid: Pid) -> Result<usize, PathError> {
+        self.offsets.iter().enumerate().try_fold(self.base, |address, (level, offset)| {
+            let pointer = vm_read::read(pid, address, 8)
+                .and_then(|pointer| pointer.as_slice().read_u64::<LE>().ok())
+                .ok_or(PathError::ReadingPointer { level: level + 1, address })?;
+            if pointer == 0 {
+                Err(PathError::NullPointer { level: level + 1, address })
+            } else {
+                Ok(pointer as usize + offset)
+            }
+        })
+    }
+
+    /// Follows the path and reads the value at its end as its `value_type`, or as a `u64` if it has
+    /// none.
+    pub fn read_value(&self, pid: Pid) -> Result<Value, PathError> {
+        let address = self.resolve(pid)?;
+        let value_type = self.value_type.unwrap_or(ValueType::U64);
+        vm_read::read(pid, address, value_type.size())
+            .and_then(|data| {
+                let mut cursor = Cursor::new(data);
+                match value_type {
+                    ValueType::U8 => cursor.read_u8().map(Value::U8),
+                    ValueType::U16 => cursor.read_u16::<LE>().map(Value::U16),
+                    ValueType::U32 => cursor.read_u32::<LE>().map(Value::U32),
+                    ValueType::U64 => cursor.read_u64::<LE>().map(Value::U64),
+                    ValueType::F32 => cursor.read_f32::<LE>().map(Value::F32),
+                }.ok()
+            })
+            .ok_or(PathError::ReadingValue { address })
+    }
+}
+
+#[cfg(test)]
+mod pointer_path_tests {
+    use crate::mem::pointer_path::*;
+
+    /// Lays out `base -> first -> second` in our own memory, where the value sits 16 bytes into
+    /// `second`.
+    struct Chain {
+        second: Box<[u64; 4]>,
+        first: Box<[usize; 8]>,
+        base: Box<usize>,
+    }
+
+    fn chain() -> Chain {
+        let second = Box::new([0, 0, 1_566_000_000_123, 0]);
+        let mut first = Box::new([0usize; 8]);
+        first[1] = second.as_ptr() as usize;
+        let base = Box::new(first.as_ptr() as usize);
+        Chain { second, first, base }
+    }
+
+    #[test]
+    fn follow_chain() {
+        let chain = chain();
+        let pid = std::process::id() as Pid;
+        let path = PointerPath { base: &*chain.base as *const usize as usize, offsets: vec![8, 16], value_type: Some(ValueType::U64) };
+        assert_eq!(path.resolve(pid), Ok(chain.second.as_ptr() as usize + 16));
+        assert_eq!(path.read_value(pid), Ok(Value::U64(1_566_000_000_123)));
+
+        let narrow = PointerPath { value_type: Some(ValueType::U16), ..path.clone() };
+        assert_eq!(narrow.read_value(pid), Ok(Value::U16((1_566_000_000_123u64 & 0xffff) as u16)));
+        let unchained = PointerPath { base: 1234, offsets: vec![], value_type: None };
+        assert_eq!(unchained.resolve(pid), Ok(1234));
+    }
+
+    #[test]
+    fn report_failing_level() {
+        let mut chain = chain();
+        let pid = std::process::id() as Pid;
+        let first_level = chain.first.as_ptr() as usize + 8;
+        chain.first[1] = 0;
+        let path = PointerPath { base: &*chain.base as *const usize as usize, offsets: vec![8, 16], value_type: None };
+        assert_eq!(path.resolve(pid), Err(PathError::NullPointer { level: 2, address: first_level }));
+
+        let unreadable = PointerPath { base: 8, offsets: vec![8], value_type: None };
+        assert_eq!(unreadable.resolve(pid), Err(PathError::ReadingPointer { level: 1, address: 8 }));
+        assert_eq!(PointerPath { base: 8, offsets: vec![], value_type: None }.read_value(pid), Err(PathError::ReadingValue { address: 8 }));
+    }
+}
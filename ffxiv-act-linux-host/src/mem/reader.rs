@@ -1,147 +1,415 @@
 use std::fs::File;
+use std::path::{Path, PathBuf};
 
 use std::collections::HashMap;
 
 use std::thread::{spawn, JoinHandle, sleep};
 use crate::serde_json::from_reader;
-use crate::mem::{Signatures, Signature};
+use crate::mem::SignatureEntry;
 
-use crate::read_process_memory::{Pid, TryIntoProcessHandle, ProcessHandle, CopyAddress};
-use crate::utils;
-use crate::proc_maps::{get_process_maps};
+use crate::read_process_memory::{Pid, CopyAddress};
 use byteorder::{LittleEndian as LE, ReadBytesExt};
 use std::io::Cursor;
-use std::time::Duration;
-use crate::mem::packets::SyncPacket;
+use std::time::{Duration, Instant};
+use crate::mem::packets::{SyncPacket, SyncTick};
 
-use std::sync::mpsc::Sender;
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
 use crate::mem::models::{Combatant, Target};
-
-const SCAN_SIZE: usize = 65536;
-
-#[derive(PartialEq, Eq, Copy, Clone, Hash)]
-pub enum SignatureType {
-    Target,
-    ChatLog,
-    MobArray,
-    PartyList,
-    ServerTime,
-    ZoneID,
-    Player,
+use crate::mem::schedule::{Schedule, Category};
+use crate::mem::vm_read;
+use crate::mem::scanner::{self, SkippedRange};
+use crate::mem::sig_cache;
+use crate::mem::sig_import;
+use crate::mem::pe::GameImage;
+use crate::mem::pointer_path::{PointerPath, PathError};
+use crate::mem::watch::FileWatcher;
+use crate::metrics::Metrics;
+use crate::TickRates;
+
+const MOB_ARRAY_LEN: usize = 421;
+const ACTOR_SIZE: usize = 11520;
+/// How often the signature file is checked for changes.
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The names of the signatures the reader reads from.
+pub mod names {
+    pub const TARGET: &str = "target";
+    pub const CHAT_LOG: &str = "chat_log";
+    pub const MOB_ARRAY: &str = "mob_array";
+    pub const PARTY_LIST: &str = "party_list";
+    pub const SERVER_TIME: &str = "server_time";
+    pub const ZONE_ID: &str = "zone_id";
+    pub const PLAYER: &str = "player";
 }
 
 pub enum MemErrorType {
     OpeningSignatureFile,
     ReadingSignatureFile,
-    FindingSignature(Vec<SignatureType>),
+    MissingSignatures(Vec<String>),
+    /// Signatures whose patterns can't be scanned for, with why.
+    UnusablePatterns(Vec<(String, String)>),
+    FindingSignature(Vec<String>, Vec<SkippedRange>),
+    ResolvingPointer(Vec<String>),
+    LocatingGameImage,
+}
 
+fn open_sig_file(path: &Path) -> Result<File, MemErrorType> {
+    File::open(path).map_err(|_| MemErrorType::OpeningSignatureFile)
 }
 
-fn open_sig_file() -> Result<File, MemErrorType> {
-    File::open("signatures_64.json").map_err(|_| MemErrorType::OpeningSignatureFile)
+fn read_signatures(f: File) -> Result<Vec<SignatureEntry>, MemErrorType> {
+    let registry = from_reader::<_, serde_json::Value>(f).ok()
+        .and_then(sig_import::import_signatures)
+        .ok_or(MemErrorType::ReadingSignatureFile)?;
+    let missing = sig_import::missing_signatures(&registry);
+    if !missing.is_empty() {
+        return Err(MemErrorType::MissingSignatures(missing));
+    }
+    let unusable = sig_import::unusable_patterns(&registry);
+    if unusable.is_empty() {
+        Ok(registry)
+    } else {
+        Err(MemErrorType::UnusablePatterns(unusable))
+    }
 }
 
-fn read_signatures(f: File) -> Result<Signatures, MemErrorType> {
-    from_reader(f).map_err(|_| MemErrorType::ReadingSignatureFile)
+/// Reads the signature file at `path`.
+pub fn load_registry(path: &Path) -> Result<Vec<SignatureEntry>, MemErrorType> {
+    open_sig_file(path).and_then(read_signatures)
 }
 
-type SignatureMap = HashMap<SignatureType, usize>;
+type SignatureMap = HashMap<String, usize>;
 
-struct SignatureResult {
-    pub signature_type: SignatureType,
-    pub signature: Option<usize>,
+/// Splits the signatures that weren't found into the required ones, which are returned, and the
+/// optional ones, which the reader will do without.
+fn required_failures<'a, I: Iterator<Item = &'a str>>(registry: &[SignatureEntry], failed: I, what: &str) -> Vec<String> {
+    failed
+        .filter(|name| {
+            let required = registry.iter().any(|entry| entry.name == *name && entry.required);
+            if !required {
+                warn!(target: "reader", "Couldn't {} optional signature [{}], continuing without it", what, name);
+            }
+            required
+        })
+        .map(|name| name.to_string())
+        .collect()
 }
 
-fn scan_signatures(sigs: Signatures, ffxiv: &Pid) -> Result<SignatureMap, MemErrorType> {
-    let sigs_to_scan = vec![
-        (SignatureType::Target, sigs.get_target()),
-        (SignatureType::ChatLog, sigs.get_chat_log()),
-        (SignatureType::MobArray, sigs.get_mob_array()),
-        (SignatureType::PartyList, sigs.get_party_list()),
-        (SignatureType::ServerTime, sigs.get_server_time()),
-        (SignatureType::ZoneID, sigs.get_zone_id()),
-        (SignatureType::Player, sigs.get_player())
-    ];
-    let max_sig_len = sigs_to_scan.iter().map(|a| a.1.signature_bytes.len()).max().unwrap();
-
-    let (signature_map_res, signature_map_fail): (Vec<SignatureResult>, Vec<SignatureResult>) = sigs_to_scan
-        .into_iter()
-        .map(|a| {
-            let sig = scan(*ffxiv, a.1, max_sig_len);
-            SignatureResult{ signature_type: a.0, signature: sig }
-        })
-        .partition(|sig_res| sig_res.signature.is_some());
+fn scan_signatures(registry: &[SignatureEntry], signature_file: &Path, ffxiv: &Pid) -> Result<SignatureMap, MemErrorType> {
+    let signatures = registry.iter().map(|entry| entry.signature()).collect::<Vec<_>>();
+
+    let game = GameImage::find(*ffxiv).ok_or(MemErrorType::LocatingGameImage)?;
+    let text = game.text().ok_or(MemErrorType::LocatingGameImage)?;
+    let cache_key = sig_cache::game_version(&game)
+        .map(|version| (version, sig_cache::signature_hash(registry)));
+    if let Some((version, signatures_hash)) = cache_key.as_ref() {
+        if let Some(cached) = sig_cache::load(signature_file, version, signatures_hash, game.base) {
+            // Optional signatures that weren't found last time won't be found now either.
+            let verified = registry.iter().zip(signatures.iter()).all(|(entry, signature)| {
+                match cached.get(&entry.name) {
+                    Some(address) => address.checked_sub(signature.signature_bytes.len())
+                        .is_some_and(|start| scanner::verify(*ffxiv, signature, start)),
+                    None => !entry.required,
+                }
+            });
+            if verified {
+                info!(target: "reader", "Using cached memory signatures");
+                return Ok(cached);
+            }
+        }
+    }
+
+    let report = scanner::scan_process(*ffxiv, text, &signatures);
+    for skip in &report.skipped {
+        debug!(target: "reader", "Skipped unreadable memory {:x?}: {}", skip.range, skip.reason);
+    }
+
+    let (found, missing): (Vec<_>, Vec<_>) = registry.iter()
+        .zip(signatures.iter().zip(report.first()))
+        // The static pointer's offset follows the signature.
+        .map(|(entry, (signature, address))| (entry.name.as_str(), address.map(|address| address + signature.signature_bytes.len())))
+        .partition(|(_, address)| address.is_some());
 
-    if signature_map_fail.len() == 0 {
-        let map = signature_map_res.into_iter().map(|sig_res| (sig_res.signature_type, sig_res.signature.unwrap()))
+    let failed = required_failures(registry, missing.into_iter().map(|(name, _)| name), "find");
+    if failed.is_empty() {
+        let map = found.into_iter().map(|(name, address)| (name.to_string(), address.unwrap()))
             .collect::<SignatureMap>();
-        println!("[MEM] Found all memory signatures");
+        info!(target: "reader", "Found all required memory signatures");
+        for (name, address) in &map {
+            debug!(target: "reader", "[{}] at ffxiv_dx11.exe+{:#x}", name, address - game.base);
+        }
+        if let Some((version, signatures_hash)) = cache_key {
+            sig_cache::store(signature_file, version, signatures_hash, game.base, &map);
+        }
         Ok(map)
     } else {
-        let failed = signature_map_fail.into_iter()
-            .map(|sig_res| sig_res.signature_type)
-            .collect::<Vec<_>>();
-        Err(MemErrorType::FindingSignature(failed))
+        Err(MemErrorType::FindingSignature(failed, report.skipped))
     }
+}
 
-//    Ok(signature_map)
+/// Logs why a signature's pointer path can't be followed, once each time the reason changes, rather
+/// than on every tick. Every failure is counted in the metrics.
+struct PathErrors {
+    last: HashMap<&'static str, PathError>,
+    metrics: Arc<Metrics>,
+}
 
+impl PathErrors {
+    fn new(metrics: Arc<Metrics>) -> PathErrors {
+        PathErrors { last: HashMap::new(), metrics }
+    }
+
+    /// Counts a read of signature `name`'s data that failed.
+    fn count(&self, name: &str) {
+        self.metrics.reader_failures.add(name, 1);
+    }
+
+    fn check<T>(&mut self, name: &'static str, result: Result<T, PathError>) -> Option<T> {
+        match result {
+            Ok(value) => {
+                if self.last.remove(name).is_some() {
+                    info!(target: "reader", "Reading [{}] again", name);
+                }
+                Some(value)
+            },
+            Err(e) => {
+                self.count(name);
+                if self.last.insert(name, e) != Some(e) {
+                    warn!(target: "reader", "Unable to read [{}]: {}", name, e);
+                }
+                None
+            },
+        }
+    }
 }
 
+/// Resolves the address of the data behind signature `name`, if it is due to be read and there is
+/// such a signature.
+fn resolve_due(due: bool, name: &'static str, pointers: &HashMap<String, PointerPath>, path_errors: &mut PathErrors, ffxiv: Pid) -> Option<usize> {
+    if due {
+        pointers.get(name).and_then(|path| path_errors.check(name, path.resolve(ffxiv)))
+    } else {
+        None
+    }
+}
 
-pub fn run_reader(sender: Sender<SyncPacket>, ffxiv: Pid) -> Result<JoinHandle<()>, MemErrorType> {
+/// Follows each signature's RIP-relative operand to the static address it refers to. These live in
+/// the game's image and don't move while it runs, so they only need resolving once.
+fn resolve_static_pointers(signature_map: SignatureMap, registry: &[SignatureEntry], ffxiv: &Pid) -> Result<HashMap<String, PointerPath>, MemErrorType> {
+    let (resolved, unresolved): (Vec<_>, Vec<_>) = signature_map.into_iter()
+        .map(|(name, signature)| {
+            let address = read_signature(signature, ffxiv);
+            (name, address)
+        })
+        .partition(|(_, pointer)| pointer.is_ok());
+    let failed = required_failures(registry, unresolved.iter().map(|(name, _)| name.as_str()), "resolve");
+    if failed.is_empty() {
+        Ok(resolved.into_iter()
+            .map(|(name, address)| {
+                let entry = registry.iter().find(|entry| entry.name == name);
+                let path = PointerPath {
+                    base: address.unwrap(),
+                    offsets: entry.map(|entry| entry.pointer_chain.clone()).unwrap_or_default(),
+                    value_type: entry.and_then(|entry| entry.value_type),
+                };
+                (name, path)
+            })
+            .collect())
+    } else {
+        Err(MemErrorType::ResolvingPointer(failed))
+    }
+}
+
+/// Reads the signature file, scans for every signature and resolves their static pointers.
+pub fn load_pointers(signature_file: &Path, ffxiv: &Pid) -> Result<HashMap<String, PointerPath>, MemErrorType> {
+    load_registry(signature_file)
+        .and_then(|registry| {
+            scan_signatures(&registry, signature_file, ffxiv)
+                .and_then(|signature_map| resolve_static_pointers(signature_map, &registry, ffxiv))
+        })
+}
+
+/// The signatures in use, as told to clients when they change.
+fn signature_names(pointers: &HashMap<String, PointerPath>) -> Vec<String> {
+    let mut names = pointers.keys().cloned().collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+fn report_missing_sources(pointers: &HashMap<String, PointerPath>) {
+    for (name, what) in &[(names::ZONE_ID, "zone changes"), (names::SERVER_TIME, "server time"), (names::MOB_ARRAY, "mobs"),
+            (names::TARGET, "targets"), (names::PARTY_LIST, "the party list")] {
+        if !pointers.contains_key(*name) {
+            info!(target: "reader", "No [{}] signature, not reading {}", name, what);
+        }
+    }
+}
+
+/// Watches the signature file and, whenever it changes, scans for its signatures again and sends
+/// the reader the new pointers. If the new file is broken the reader keeps what it has, so a
+/// signature can be fixed while the game and clients keep running. Stops once the reader is gone.
+fn watch_signatures(reloads: Sender<HashMap<String, PointerPath>>, signature_file: PathBuf, ffxiv: Pid) -> JoinHandle<()> {
+    spawn(move || {
+        let mut watcher = FileWatcher::new(vec![signature_file.clone()]);
+        loop {
+            sleep(WATCH_INTERVAL);
+            if !watcher.changed() {
+                continue;
+            }
+            info!(target: "reader", "{} changed, rescanning", signature_file.display());
+            match load_pointers(&signature_file, &ffxiv) {
+                Ok(pointers) => {
+                    if reloads.send(pointers).is_err() {
+                        break;
+                    }
+                },
+                Err(mem_err) => {
+                    super::report_mem_error(mem_err);
+                    warn!(target: "reader", "Keeping the previous signatures");
+                },
+            }
+        }
+    })
+}
 
-    open_sig_file()
-        .and_then(read_signatures)
-        .and_then(|a| scan_signatures(a, &ffxiv))
-        .and_then(|signature_map| {
+pub fn run_reader(sender: Sender<SyncTick>, ffxiv: Pid, tick_rates: TickRates, signature_file: PathBuf, metrics: Arc<Metrics>) -> Result<JoinHandle<()>, MemErrorType> {
+
+    load_pointers(&signature_file, &ffxiv)
+        .and_then(|pointer_map| {
+            let (reload_sender, reloads) = mpsc::channel();
+            watch_signatures(reload_sender, signature_file, ffxiv);
             Ok(spawn(move || {
-                let sigs = signature_map;
+                let mut pointers = pointer_map;
                 let ffxiv = ffxiv;
                 let sender = sender;
-                let base_addr = sigs.get(&SignatureType::ZoneID).unwrap();
+                let mut path_errors = PathErrors::new(metrics.clone());
+                report_missing_sources(&pointers);
+                let mut schedule = Schedule::new(tick_rates, Instant::now());
+                let mut zone = 0u32;
+                let mut mob_hp = [0u32; MOB_ARRAY_LEN];
+                // The pointers from before a reload, until the reloaded ones have read a zone.
+                let mut previous_pointers = None;
                 'mem: loop {
-
-                    // SERVER TIME
-                    if let Ok(server_time) = read_server_time(*sigs.get(&SignatureType::ServerTime).unwrap(), &ffxiv) {
-                        if let Err(_) = sender.send(SyncPacket::ServerTime(server_time)) { break 'mem; }
+                    let now = Instant::now();
+                    let mut tick = SyncTick::new();
+                    let mut active = false;
+
+                    // Swapped between ticks, so every read in a tick uses the same signatures.
+                    if let Ok(reloaded) = reloads.try_recv() {
+                        previous_pointers = Some(std::mem::replace(&mut pointers, reloaded));
+                        path_errors = PathErrors::new(metrics.clone());
+                        info!(target: "reader", "Reloaded memory signatures");
+                        report_missing_sources(&pointers);
+                        tick.push(SyncPacket::SignaturesReloaded(signature_names(&pointers)));
                     }
 
                     // ZONE
-                    if let Ok(zone) = read_zone_id(*base_addr, &ffxiv) {
-                        if let Err(_) = sender.send(SyncPacket::ZoneID(zone)) { break 'mem; }
+                    if schedule.due(Category::Zone, now) {
+                        if let Some(zone_path) = pointers.get(names::ZONE_ID) {
+                            let zone_id = path_errors.check(names::ZONE_ID, zone_path.resolve(ffxiv))
+                                .map(|zone_addr| read_zone_id(zone_addr, &ffxiv));
+                            match zone_id {
+                                Some(Ok(zone_id)) => {
+                                    previous_pointers = None;
+                                    zone = zone_id;
+                                    tick.push(SyncPacket::ZoneID(zone));
+                                },
+                                failed => {
+                                    if let Some(Err(_)) = failed {
+                                        path_errors.count(names::ZONE_ID);
+                                    }
+                                    // Reloaded signatures can resolve and still point somewhere
+                                    // else, which shouldn't stop a reader that was working.
+                                    match previous_pointers.take() {
+                                        Some(previous) => {
+                                            warn!(target: "reader", "Unable to read the zone with the reloaded signatures, going back to the previous ones");
+                                            pointers = previous;
+                                            path_errors = PathErrors::new(metrics.clone());
+                                            report_missing_sources(&pointers);
+                                            tick.push(SyncPacket::SignaturesReloaded(signature_names(&pointers)));
+                                        },
+                                        None => break 'mem,
+                                    }
+                                },
+                            }
+                        }
+                    }
 
-                        if zone != 0 {
-                            // MOB ARRAY
+                    // SERVER TIME
+                    if schedule.due(Category::ServerTime, now) {
+                        if let Some(time_path) = pointers.get(names::SERVER_TIME) {
+                            if let Some(server_time) = path_errors.check(names::SERVER_TIME, time_path.read_value(ffxiv)) {
+                                tick.push(SyncPacket::ServerTime(server_time.as_u64()));
+                            }
+                        }
+                    }
 
-                            let mob_array_ptr = sigs.get(&SignatureType::MobArray).unwrap();
-                            for i in 0..421usize {
-                                if let Ok(mob_opt) = read_mob(*mob_array_ptr, i, &ffxiv) {
+                    // Categories are scheduled even while not in a zone, so they don't stay overdue.
+                    let mobs_due = schedule.due(Category::Mobs, now);
+                    let target_due = schedule.due(Category::Target, now);
+                    let party_list_due = schedule.due(Category::PartyList, now);
+
+                    // Without a zone signature, which the file may mark as not required, assume
+                    // we're always in one.
+                    if zone != 0 || !pointers.contains_key(names::ZONE_ID) {
+                        // MOB ARRAY
+                        if let Some(mob_array_ptr) = resolve_due(mobs_due, names::MOB_ARRAY, &pointers, &mut path_errors, ffxiv) {
+                            let mobs = read_mobs(mob_array_ptr, ffxiv).unwrap_or_else(|_| {
+                                path_errors.count(names::MOB_ARRAY);
+                                Vec::new()
+                            });
+                            let mut mob_count = 0;
+                            for ((i, hp), mob) in mob_hp.iter_mut().enumerate().zip(mobs) {
+                                if let Ok(mob_opt) = mob {
                                     if let Some((this_ptr, combatant)) = mob_opt {
-                                        let com = combatant.binary_serialize_compressed();
-                                        if let Err(_) = sender.send(SyncPacket::MobUpdate(i as u16, this_ptr as u64, com)) {
-                                            break 'mem;
+                                        mob_count += 1;
+                                        if combatant.current_hp != *hp {
+                                            *hp = combatant.current_hp;
+                                            active = true;
                                         }
+                                        let com = combatant.binary_serialize();
+                                        tick.push(SyncPacket::MobUpdate(i as u16, this_ptr, com));
                                     } else {
-                                        if let Err(_) = sender.send(SyncPacket::MobNull(i as u16)) {
-                                            break 'mem;
-                                        }
+                                        *hp = 0;
+                                        tick.push(SyncPacket::MobNull(i as u16));
                                     }
+                                } else {
+                                    path_errors.count(names::MOB_ARRAY);
                                 }
                             }
+                            metrics.reader_mobs.observe(mob_count as f64);
+                        }
+
+                        // TARGET
+                        if let Some(target_addr) = resolve_due(target_due, names::TARGET, &pointers, &mut path_errors, ffxiv) {
+                            match read_target(target_addr, &ffxiv) {
+                                Ok(targets) => tick.push(SyncPacket::Target(targets)),
+                                Err(_) => path_errors.count(names::TARGET),
+                            }
+                        }
 
-                            // TARGET
-                            let target_sig = sigs.get(&SignatureType::Target).unwrap();
-                            if let Ok(targets) = read_target(*target_sig, &ffxiv) {
-                                if let Err(_) = sender.send(SyncPacket::Target(targets)) { break 'mem; }
+                        // PARTY LIST
+                        if let Some(party_list_addr) = resolve_due(party_list_due, names::PARTY_LIST, &pointers, &mut path_errors, ffxiv) {
+                            match read_party_list(party_list_addr, &ffxiv) {
+                                Ok(party_list) => tick.push(SyncPacket::PartyList(party_list)),
+                                Err(_) => path_errors.count(names::PARTY_LIST),
                             }
                         }
+                    }
 
-                    } else {
-                        break 'mem;
+                    if active {
+                        schedule.mark_active(now);
                     }
+                    metrics.reader_tick_seconds.observe_duration(now.elapsed());
+
+                    if !tick.is_empty() && sender.send(tick).is_err() { break 'mem; }
 
-                    sleep(Duration::from_millis(10));
+                    let wakeup = schedule.next_wakeup();
+                    let now = Instant::now();
+                    if wakeup > now {
+                        sleep(wakeup - now);
+                    }
                 }
         }))
 
@@ -152,7 +420,7 @@ pub fn run_reader(sender: Sender<SyncPacket>, ffxiv: Pid) -> Result<JoinHandle<(
 
 }
 
-enum ReadingError {
+pub enum ReadingError {
     ReadingProcessMemory,
     ReadingData
 }
@@ -174,7 +442,7 @@ impl std::fmt::Display for ReadingError {
 }
 
 
-fn read_signature<C: CopyAddress>(signature: usize, ffxiv: &C) -> Result<usize, ReadingError> {
+pub fn read_signature<C: CopyAddress>(signature: usize, ffxiv: &C) -> Result<usize, ReadingError> {
     read_process_memory::copy_address(signature, 4, ffxiv)
         .map_err(|_| ReadingError::ReadingProcessMemory)
         .and_then(|copy| {
@@ -185,141 +453,67 @@ fn read_signature<C: CopyAddress>(signature: usize, ffxiv: &C) -> Result<usize,
 
 }
 
-fn read_target(signature: usize, ffxiv: &Pid) -> Result<Target, ReadingError> {
-    read_signature(signature, ffxiv)
-        .and_then(|target| read_process_memory::copy_address(target, 512, &ffxiv as &Pid).map_err(|_| ReadingError::ReadingProcessMemory))
+fn read_target(target: usize, ffxiv: &Pid) -> Result<Target, ReadingError> {
+    read_process_memory::copy_address(target, 512, ffxiv).map_err(|_| ReadingError::ReadingProcessMemory)
         .and_then(|data| Target::try_from_ffxiv_slice(data).map_err(|_| ReadingError::ReadingData))
 }
 
-fn read_server_time<C: CopyAddress>(signature: usize, ffxiv: &C) -> Result<u64, ReadingError> {
-    const OFFSET_1: usize = 72;
-    const OFFSET_2: usize = 8;
-    const OFFSET_3: usize = 2116;
-    read_signature(signature, ffxiv)
-        .and_then(|time_ptr| read_process_memory::copy_address(time_ptr, 8, ffxiv).map_err(|_| ReadingError::ReadingProcessMemory))
-        .and_then(|ptr1| ptr1.as_slice().read_u64::<LE>().map_err(|_| ReadingError::ReadingData))
-        .map(|ptr1| ptr1 as usize + OFFSET_1)
-        .and_then(|incptr1| {
-            if incptr1 - OFFSET_1 != 0 {
-                read_process_memory::copy_address(incptr1, 8, ffxiv).map_err(|_| ReadingError::ReadingProcessMemory)
-            } else { Ok(vec![0; 8]) } } )
-        .and_then(|ptr2| ptr2.as_slice().read_u64::<LE>().map_err(|_| ReadingError::ReadingData))
-        .map(|ptr2| ptr2 as usize + OFFSET_2)
-        .and_then(|incptr2| {
-            if incptr2 - OFFSET_2 == 0 {
-                read_process_memory::copy_address(incptr2, 8, ffxiv).map_err(|_| ReadingError::ReadingProcessMemory)
-            } else { Ok(vec![0; 8]) } })
-        .and_then(|ptr3| ptr3.as_slice().read_u64::<LE>().map_err(|_| ReadingError::ReadingData))
-        .map(|ptr3| ptr3 as usize + OFFSET_3)
-        .and_then(|incptr3| {
-            if incptr3 - OFFSET_3 == 0 {
-                read_process_memory::copy_address(incptr3, 8, ffxiv).map_err(|_| ReadingError::ReadingProcessMemory)
-            } else { Ok(vec![0; 8]) }
-        })
-        .and_then(|server_time_vec| server_time_vec.as_slice().read_u64::<LE>().map_err(|_| ReadingError::ReadingData))
-
+fn read_party_list(party_list: usize, ffxiv: &Pid) -> Result<Vec<u8>, ReadingError> {
+    const PARTY_LIST_SIZE: usize = 25600;
+    read_process_memory::copy_address(party_list, PARTY_LIST_SIZE, ffxiv).map_err(|_| ReadingError::ReadingProcessMemory)
 }
 
-fn read_mob(signature: usize, index: usize, ffxiv: &Pid) -> Result<Option<(u64, Combatant)>, ReadingError> {
-
-    read_signature(signature, ffxiv)
-        .and_then(|mob_array| {
-            let mob_ptr_ptr = mob_array + 8 * index;
-            read_process_memory::copy_address(mob_ptr_ptr, 8, &ffxiv as &Pid).map_err(|_| ReadingError::ReadingProcessMemory)
-        })
-        .and_then(|mob_ptr_vec| {
-            let mut cursor = Cursor::new(mob_ptr_vec);
-            let mob_ptr = cursor.read_u64::<LE>().map_err(|_| ReadingError::ReadingData)? as usize;
-//            let mob_ptr = LittleEndian::read_u64(mob_ptr_vec.as_slice()) as usize;
+/// A mob array slot: the actor's address in the game and its data, or `None` if the slot is empty.
+type MobSlot = Result<Option<(u64, Combatant)>, ReadingError>;
+
+/// Reads every slot of the mob array: one read for the pointer array, then one vectored read for
+/// all of the actors it points to.
+fn read_mobs(mob_array: usize, ffxiv: Pid) -> Result<Vec<MobSlot>, ReadingError> {
+    let pointer_data = vm_read::read(ffxiv, mob_array, 8 * MOB_ARRAY_LEN).ok_or(ReadingError::ReadingProcessMemory)?;
+    let mut cursor = Cursor::new(pointer_data);
+    let mob_ptrs = (0..MOB_ARRAY_LEN)
+        .map(|_| cursor.read_u64::<LE>().map(|ptr| ptr as usize))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| ReadingError::ReadingData)?;
+
+    let requests = mob_ptrs.iter()
+        .filter(|mob_ptr| **mob_ptr != 0)
+        .map(|mob_ptr| (*mob_ptr, ACTOR_SIZE))
+        .collect::<Vec<_>>();
+    let mut actors = vm_read::read_many(ffxiv, &requests).into_iter();
+
+    Ok(mob_ptrs.into_iter()
+        .map(|mob_ptr| {
             if mob_ptr != 0 {
-                let data = read_process_memory::copy_address(mob_ptr, 11520, &ffxiv as &Pid).map_err(|_| ReadingError::ReadingProcessMemory)?;
+                let data = actors.next().unwrap().ok_or(ReadingError::ReadingProcessMemory)?;
                 let combatant = Combatant::try_from_slice(data).map_err(|_| ReadingError::ReadingData)?;
                 Ok(Some((mob_ptr as u64, combatant)))
             } else {
                 Ok(None)
             }
         })
-
+        .collect())
 }
 
-fn read_zone_id(signature: usize, ffxiv: &Pid) -> Result<u32, ReadingError> {
-    read_signature(signature, ffxiv)
-        .and_then(|zone_id_addr| {
-            read_process_memory::copy_address(zone_id_addr, 4, &ffxiv as &Pid).map_err(|_| ReadingError::ReadingProcessMemory)
-        })
+fn read_zone_id(zone_id_addr: usize, ffxiv: &Pid) -> Result<u32, ReadingError> {
+    read_process_memory::copy_address(zone_id_addr, 4, ffxiv).map_err(|_| ReadingError::ReadingProcessMemory)
         .and_then(|zone_id| {
             let mut cur = Cursor::new(zone_id);
             cur.read_u32::<LE>().map_err(|_| ReadingError::ReadingData)
         })
 }
 
-impl std::fmt::Debug for SignatureType {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        match self {
-            SignatureType::Target => write!(f, "[Target]"),
-            SignatureType::ChatLog => write!(f, "[ChatLog]"),
-            SignatureType::MobArray => write!(f, "[MobArray]"),
-            SignatureType::PartyList => write!(f, "[PartyList]"),
-            SignatureType::ServerTime => write!(f, "[ServerTime]"),
-            SignatureType::ZoneID => write!(f, "[ZoneID]"),
-            SignatureType::Player => write!(f, "[Player]"),
-        }
-    }
-}
-
-fn scan(pid: Pid, signature: Signature, max_sig_len: usize) -> Option<usize> {
-    let mut scan_buffer = [0u8; SCAN_SIZE];
-    let handle = pid.try_into_process_handle() as std::io::Result<ProcessHandle>;
-    if handle.is_err() {
-        eprintln!("Unable to open process handle.");
-        return None;
-    }
-    let handle = handle.unwrap();
-
-    let maps = get_process_maps(pid);
-    if maps.is_err() {
-        eprintln!("Unable to read process maps");
-        return None;
-    }
-    let maps = maps.unwrap();
-    let mut ret: Option<usize> = None;
-    'outer: for addr_range  in maps.iter()
-        .filter(|s| s.is_read() && s.size() > 0 && s.filename().is_none() && s.is_exec() && !s.is_write()) {
-
-        let mut addr = addr_range.start();
-        let end = addr + addr_range.size();
-        let mut buffer_range = 0usize..SCAN_SIZE;
-        loop {
-            let res = (&handle).copy_address(addr, &mut scan_buffer[buffer_range.clone()]);
-            if let Ok(_) = res {
-                if let Some(x) = utils::find_subsequence(&scan_buffer[buffer_range.clone()], signature.signature_bytes.as_ref(), signature.wildcard_ranges.as_ref()) {
-//                    println!("Found at {:x?}, offset {}, addr {}", x + addr, x, addr);
-                    ret = Some(x + addr + signature.signature_bytes.len());
-                    break 'outer;
-                }
-                addr += SCAN_SIZE - max_sig_len;
-                if addr > end {
-                    continue 'outer;
-                }
-                if addr + SCAN_SIZE > end {
-                    buffer_range.end = SCAN_SIZE - (addr + SCAN_SIZE - end);
-                }
-            } else if let Err(x) = res {
-                eprintln!("Error at addr {:x?}: {:?}", addr, x);
-                ret = None;
-                break 'outer;
-            }
-        }
-
-    }
-
-    ret
-}
-
 #[cfg(test)]
 mod reader_tests {
     use std::ffi::CString;
-    use std::time::Instant;
+
+    #[test]
+    fn optional_signatures_are_not_failures() {
+        use crate::mem::reader::*;
+        let registry = crate::mem::sig_import::import_signatures(serde_json::from_str(include_str!("../../signatures_64.json")).unwrap()).unwrap();
+        let failed = required_failures(&registry, vec![names::CHAT_LOG, names::ZONE_ID, "hotbar"].into_iter(), "find");
+        assert_eq!(failed, vec![names::ZONE_ID.to_string()]);
+    }
 
     #[test]
     fn reader_test() {
@@ -327,9 +521,9 @@ mod reader_tests {
         use std::sync::mpsc;
         use crate::mem::packets::SyncPacket;
         let (sender, recv) = mpsc::channel();
-        run_reader(sender, crate::utils::find_ffxiv().unwrap());
+        let _reader = run_reader(sender, crate::utils::find_ffxiv().unwrap(), crate::TickRates::default(), PathBuf::from(crate::cli::SIGNATURE_FILE), std::sync::Arc::new(crate::metrics::Metrics::new()));
         let mut inst = Instant::now();
-        for rx in recv {
+        for rx in recv.iter().flatten() {
             if let SyncPacket::MobUpdate(index, _ptr, data) = rx {
                 if index == 0 && inst.elapsed().as_secs() >= 1 {
                     inst = Instant::now();
@@ -0,0 +1,417 @@
+use std::collections::BTreeSet;
+use std::ops::Range;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use aho_corasick::AhoCorasick;
+use proc_maps::Pid;
+
+use crate::mem::Signature;
+use crate::mem::vm_read;
+
+const SCAN_SIZE: usize = 65536;
+const PAGE_SIZE: usize = 4096;
+
+struct Pattern {
+    bytes: Vec<u8>,
+    /// `true` for bytes that must match, `false` for wildcards.
+    mask: Vec<bool>,
+    /// Where the pattern's longest run of literal bytes starts.
+    anchor_offset: usize,
+}
+
+impl Pattern {
+    fn from_signature(signature: &Signature) -> Pattern {
+        let bytes = signature.signature_bytes.clone();
+        let mut mask = vec![true; bytes.len()];
+        for range in signature.wildcard_ranges.iter().flatten() {
+            for wild in range.clone() {
+                mask[wild] = false;
+            }
+        }
+        Pattern { bytes, mask, anchor_offset: 0 }
+    }
+
+    /// The longest run of literal bytes, which the automaton searches for before the wildcards of
+    /// the full pattern are checked.
+    fn anchor(&mut self) -> Vec<u8> {
+        let (mut best_start, mut best_len) = (0, 0);
+        let mut run_start = 0;
+        for i in 0..=self.mask.len() {
+            if i == self.mask.len() || !self.mask[i] {
+                if i - run_start > best_len {
+                    best_start = run_start;
+                    best_len = i - run_start;
+                }
+                run_start = i + 1;
+            }
+        }
+        assert!(best_len > 0, "signature must contain at least one literal byte");
+        self.anchor_offset = best_start;
+        self.bytes[best_start..best_start + best_len].to_vec()
+    }
+
+    fn matches_at(&self, haystack: &[u8], start: usize) -> bool {
+        haystack.len() >= start + self.bytes.len() && self.bytes.iter()
+            .zip(self.mask.iter())
+            .zip(haystack[start..].iter())
+            .all(|((byte, literal), found)| !literal || byte == found)
+    }
+}
+
+/// Matches every signature in a single pass over a buffer. An Aho-Corasick automaton finds the
+/// longest literal run of each signature, and only those candidates are checked against the full
+/// pattern, wildcards and all.
+pub struct MultiPatternScanner {
+    automaton: AhoCorasick,
+    /// The patterns that share each distinct anchor, by automaton pattern id.
+    anchored: Vec<Vec<usize>>,
+    patterns: Vec<Pattern>,
+}
+
+impl MultiPatternScanner {
+    pub fn new(signatures: &[Signature]) -> MultiPatternScanner {
+        let mut patterns = signatures.iter().map(Pattern::from_signature).collect::<Vec<_>>();
+        let mut anchors: Vec<Vec<u8>> = Vec::new();
+        let mut anchored: Vec<Vec<usize>> = Vec::new();
+        for (index, pattern) in patterns.iter_mut().enumerate() {
+            let anchor = pattern.anchor();
+            if let Some(existing) = anchors.iter().position(|a| *a == anchor) {
+                anchored[existing].push(index);
+            } else {
+                anchors.push(anchor);
+                anchored.push(vec![index]);
+            }
+        }
+        MultiPatternScanner { automaton: AhoCorasick::new(&anchors), anchored, patterns }
+    }
+
+    pub fn max_len(&self) -> usize {
+        self.patterns.iter().map(|pattern| pattern.bytes.len()).max().unwrap_or(0)
+    }
+
+    /// Reports each match in `haystack` as `(signature index, offset)`, in order of offset for any
+    /// one signature.
+    pub fn find_iter<'a>(&'a self, haystack: &'a [u8]) -> impl Iterator<Item = (usize, usize)> + 'a {
+        self.automaton.find_overlapping_iter(haystack)
+            .flat_map(move |found| {
+                self.anchored[found.pattern()].iter().filter_map(move |index| {
+                    let pattern = &self.patterns[*index];
+                    found.start().checked_sub(pattern.anchor_offset)
+                        .filter(|start| pattern.matches_at(haystack, *start))
+                        .map(|start| (*index, start))
+                })
+            })
+    }
+
+    /// Finds the first match of each signature in `haystack`.
+    #[allow(dead_code)]
+    pub fn find_first(&self, haystack: &[u8]) -> Vec<Option<usize>> {
+        let mut found = vec![None; self.patterns.len()];
+        for (index, offset) in self.find_iter(haystack) {
+            if found[index].is_none() {
+                found[index] = Some(offset);
+            }
+        }
+        found
+    }
+}
+
+/// A range of memory the scanner couldn't read, and why.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedRange {
+    pub range: Range<usize>,
+    pub reason: String,
+}
+
+pub struct ScanReport {
+    /// Every address each signature was found at, in order.
+    pub matches: Vec<Vec<usize>>,
+    /// Unreadable memory, in address order. A signature inside it can't have been found.
+    pub skipped: Vec<SkippedRange>,
+}
+
+/// Scans the game's code, e.g. the `.text` section of its image, for every signature at once,
+/// splitting it into slices that are spread over a thread per CPU.
+pub fn scan_process(pid: Pid, code: Range<usize>, signatures: &[Signature]) -> ScanReport {
+    let scanner = MultiPatternScanner::new(signatures);
+    let matches: Mutex<BTreeSet<(usize, usize)>> = Mutex::new(BTreeSet::new());
+    let skipped: Mutex<Vec<SkippedRange>> = Mutex::new(Vec::new());
+    let next_region = AtomicUsize::new(0);
+    let threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    // A few slices per thread, so one thread landing on slow pages doesn't hold up the rest. Each
+    // slice runs on into the next far enough to see a signature straddling them.
+    let slice_len = ((code.end - code.start) / (threads * 4)).max(SCAN_SIZE);
+    let overlap = scanner.max_len().saturating_sub(1);
+    let regions = (code.start..code.end).step_by(slice_len)
+        .map(|start| (start, (start + slice_len + overlap).min(code.end)))
+        .collect::<Vec<_>>();
+
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| {
+                while let Some((start, end)) = regions.get(next_region.fetch_add(1, Ordering::Relaxed)) {
+                    let mut region_skipped = Vec::new();
+                    let region_matches = scan_region(pid, &scanner, *start, *end, &mut region_skipped);
+                    matches.lock().unwrap().extend(region_matches);
+                    skipped.lock().unwrap().extend(region_skipped);
+                }
+            });
+        }
+    });
+
+    let mut by_signature = vec![Vec::new(); signatures.len()];
+    for (index, address) in matches.into_inner().unwrap() {
+        by_signature[index].push(address);
+    }
+    let mut skipped = skipped.into_inner().unwrap();
+    skipped.sort_by_key(|skip| skip.range.start);
+    ScanReport {
+        matches: by_signature,
+        skipped: merge_skipped(skipped),
+    }
+}
+
+impl ScanReport {
+    /// The lowest address each signature was found at.
+    pub fn first(&self) -> Vec<Option<usize>> {
+        self.matches.iter().map(|matches| matches.first().cloned()).collect()
+    }
+}
+
+/// Joins skipped ranges that touch or overlap, as the slices and chunks of a scan do, and were
+/// skipped for the same reason.
+fn merge_skipped(skipped: Vec<SkippedRange>) -> Vec<SkippedRange> {
+    let mut merged: Vec<SkippedRange> = Vec::new();
+    for skip in skipped {
+        match merged.last_mut() {
+            Some(last) if skip.range.start <= last.range.end && skip.reason == last.reason => {
+                last.range.end = last.range.end.max(skip.range.end);
+            },
+            _ => merged.push(skip),
+        }
+    }
+    merged
+}
+
+/// Checks that `signature` is still found at `address`, e.g. before trusting a cached scan result.
+pub fn verify(pid: Pid, signature: &Signature, address: usize) -> bool {
+    let pattern = Pattern::from_signature(signature);
+    vm_read::read(pid, address, pattern.bytes.len())
+        .is_some_and(|found| pattern.matches_at(&found, 0))
+}
+
+/// Scans one slice of memory in `SCAN_SIZE` chunks, overlapping consecutive chunks so a signature
+/// that straddles a chunk boundary is still seen whole. A chunk that can't be read is retried a page
+/// at a time, and the pages that still fail are recorded in `skipped` while the rest are scanned.
+/// Returns every match as `(signature index, address)`.
+fn scan_region(pid: Pid, scanner: &MultiPatternScanner, start: usize, end: usize, skipped: &mut Vec<SkippedRange>) -> Vec<(usize, usize)> {
+    let overlap = scanner.max_len().saturating_sub(1);
+    let mut found: BTreeSet<(usize, usize)> = BTreeSet::new();
+    let mut addr = start;
+    while addr < end {
+        let len = SCAN_SIZE.min(end - addr);
+        let runs = match vm_read::try_read(pid, addr, len) {
+            Ok(chunk) => vec![(addr, chunk)],
+            Err(_) => readable_runs(pid, addr, addr + len, skipped),
+        };
+        for (run_addr, run) in runs {
+            for (index, offset) in scanner.find_iter(&run) {
+                found.insert((index, run_addr + offset));
+            }
+        }
+        if addr + len >= end {
+            break;
+        }
+        addr += len - overlap;
+    }
+    found.into_iter().collect()
+}
+
+/// Reads `start..end` page by page, returning each stretch of consecutive readable pages along with
+/// its address, and recording the unreadable ones in `skipped`.
+fn readable_runs(pid: Pid, start: usize, end: usize, skipped: &mut Vec<SkippedRange>) -> Vec<(usize, Vec<u8>)> {
+    let mut runs: Vec<(usize, Vec<u8>)> = Vec::new();
+    let mut run_end = None;
+    let mut page = start;
+    while page < end {
+        let page_end = ((page / PAGE_SIZE + 1) * PAGE_SIZE).min(end);
+        match vm_read::try_read(pid, page, page_end - page) {
+            Ok(data) => {
+                if run_end == Some(page) {
+                    runs.last_mut().unwrap().1.extend(data);
+                } else {
+                    runs.push((page, data));
+                }
+                run_end = Some(page_end);
+            },
+            Err(e) => skipped.push(SkippedRange { range: page..page_end, reason: e.to_string() }),
+        }
+        page = page_end;
+    }
+    runs
+}
+
+#[cfg(test)]
+mod scanner_tests {
+    use crate::mem::scanner::*;
+    use crate::mem::ParseSignature;
+    use crate::utils::find_subsequence;
+    use std::time::Instant;
+
+    const SIGNATURES: [&str; 7] = [
+        "41bc000000e041bd01000000493bc47555488d0d",
+        "e8????????85c0740e488b0d????????33D2E8????????488b0d",
+        "488b420848c1e8033da701000077248bc0488d0d",
+        "488D7C242066660F1F840000000000488B17488D0D",
+        "0fb7c0894710488b0d",
+        "f30f108d080400004c8d85580600000fb705",
+        "83f9ff7412448b048e8bd3488d0d",
+    ];
+
+    fn signatures() -> Vec<Signature> {
+        SIGNATURES.iter().map(|s| s.parse_signature()).collect()
+    }
+
+    /// Pseudo-random filler standing in for game code.
+    fn haystack(len: usize) -> Vec<u8> {
+        let mut state = 0x2545F4914F6CDD1Du64;
+        (0..len).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 24) as u8
+        }).collect()
+    }
+
+    #[test]
+    fn find_every_signature() {
+        let mut memory = haystack(200_000);
+        let planted = [190_000usize, 1_000, 50_007, 120_000, 7, 99_999, 150_001];
+        let signatures = signatures();
+        for (signature, offset) in signatures.iter().zip(planted.iter()) {
+            memory[*offset..*offset + signature.signature_bytes.len()].copy_from_slice(&signature.signature_bytes);
+        }
+        // Fill the chat log signature's wildcards with something other than the zeroes it was
+        // parsed with.
+        for wild in signatures[1].wildcard_ranges.iter().flatten() {
+            for i in wild.clone() {
+                memory[1_000 + i] = 0xcc;
+            }
+        }
+        // A second, later copy of the server time signature shouldn't be reported.
+        memory[180_000..180_009].copy_from_slice(&signatures[4].signature_bytes);
+
+        let found = MultiPatternScanner::new(&signatures).find_first(&memory);
+        assert_eq!(found, planted.iter().map(|offset| Some(*offset)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn share_anchors_between_signatures() {
+        let signatures = vec!["aabb??dd".parse_signature(), "aabbccdd".parse_signature(), "11??aabbccdd".parse_signature()];
+        let memory = [0u8, 0x11, 0x22, 0xaa, 0xbb, 0xcc, 0xdd, 0x33];
+        let found = MultiPatternScanner::new(&signatures).find_first(&memory);
+        assert_eq!(found, vec![Some(3), Some(3), Some(1)]);
+    }
+
+    #[test]
+    fn verify_in_own_process() {
+        let signature = "e8????????85c0740e".parse_signature();
+        let memory = [0xe8u8, 1, 2, 3, 4, 0x85, 0xc0, 0x74, 0x0e];
+        let pid = std::process::id() as Pid;
+        assert!(verify(pid, &signature, memory.as_ptr() as usize));
+        assert!(!verify(pid, &signature, memory.as_ptr() as usize + 1));
+    }
+
+    #[test]
+    fn scan_own_process() {
+        let signatures = vec!["0fb7c0894710488b0d".parse_signature()];
+        let found = scan_region(std::process::id() as Pid, &MultiPatternScanner::new(&signatures), 0, 0, &mut Vec::new());
+        assert!(found.is_empty());
+
+        let mut memory = haystack(3 * SCAN_SIZE);
+        let straddling = SCAN_SIZE - 4;
+        memory[straddling..straddling + 9].copy_from_slice(&signatures[0].signature_bytes);
+        let start = memory.as_ptr() as usize;
+        let found = scan_region(std::process::id() as Pid, &MultiPatternScanner::new(&signatures), start, start + memory.len(), &mut Vec::new());
+        assert_eq!(found, vec![(0, start + straddling)]);
+    }
+
+    #[test]
+    fn scan_code_range() {
+        let signatures = vec!["0fb7c0894710488b0d".parse_signature(), "83f9ff7412448b048e8bd3488d0d".parse_signature()];
+        let mut memory = haystack(8 * SCAN_SIZE);
+        let first = 5 * SCAN_SIZE - 2;
+        memory[first..first + 9].copy_from_slice(&signatures[0].signature_bytes);
+        memory[7 * SCAN_SIZE + 5..7 * SCAN_SIZE + 14].copy_from_slice(&signatures[0].signature_bytes);
+        let start = memory.as_ptr() as usize;
+        let report = scan_process(std::process::id() as Pid, start..start + memory.len(), &signatures);
+        assert_eq!(report.first(), vec![Some(start + first), None]);
+        assert_eq!(report.matches, vec![vec![start + first, start + 7 * SCAN_SIZE + 5], vec![]]);
+        assert!(report.skipped.is_empty());
+    }
+
+    #[test]
+    fn skip_unreadable_pages() {
+        let signatures = vec!["0fb7c0894710488b0d".parse_signature(), "83f9ff7412448b048e8bd3488d0d".parse_signature()];
+        let len = 2 * SCAN_SIZE;
+        let start = unsafe {
+            libc::mmap(std::ptr::null_mut(), len, libc::PROT_READ | libc::PROT_WRITE, libc::MAP_PRIVATE | libc::MAP_ANONYMOUS, -1, 0)
+        } as usize;
+        assert_ne!(start, libc::MAP_FAILED as usize);
+        let memory = unsafe { std::slice::from_raw_parts_mut(start as *mut u8, len) };
+        // One signature in the page after the unreadable one, the other in the region's last bytes.
+        memory[5 * PAGE_SIZE + 1..5 * PAGE_SIZE + 10].copy_from_slice(&signatures[0].signature_bytes);
+        memory[len - 14..].copy_from_slice(&signatures[1].signature_bytes);
+        unsafe { libc::mprotect((start + 4 * PAGE_SIZE) as *mut libc::c_void, PAGE_SIZE, libc::PROT_NONE) };
+
+        let report = scan_process(std::process::id() as Pid, start..start + len, &signatures);
+        unsafe { libc::munmap(start as *mut libc::c_void, len) };
+        assert_eq!(report.first(), vec![Some(start + 5 * PAGE_SIZE + 1), Some(start + len - 14)]);
+        assert_eq!(report.skipped.len(), 1);
+        assert_eq!(report.skipped[0].range, start + 4 * PAGE_SIZE..start + 5 * PAGE_SIZE);
+    }
+
+    #[test]
+    fn merge_adjacent_skips() {
+        let skip = |start, end, reason: &str| SkippedRange { range: start..end, reason: reason.to_string() };
+        let merged = merge_skipped(vec![skip(0, 4096, "a"), skip(4096, 8192, "a"), skip(6000, 9000, "a"), skip(9000, 10000, "b"), skip(20000, 24096, "b")]);
+        assert_eq!(merged, vec![skip(0, 9000, "a"), skip(9000, 10000, "b"), skip(20000, 24096, "b")]);
+    }
+
+    /// Compares scanning 64 MiB for all seven signatures one at a time with `find_subsequence`, as
+    /// the reader used to, against a single pass of the multi-pattern scanner and against that pass
+    /// split over every CPU. Run with `cargo test --release scanner_benchmark -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn scanner_benchmark() {
+        let signatures = signatures();
+        let memory = haystack(64 << 20);
+
+        let start = Instant::now();
+        let naive = signatures.iter()
+            .map(|s| find_subsequence(&memory, &s.signature_bytes, s.wildcard_ranges.as_ref()))
+            .collect::<Vec<_>>();
+        println!("{:>24} {:>10.1?}", "find_subsequence x7", start.elapsed());
+
+        let scanner = MultiPatternScanner::new(&signatures);
+        let start = Instant::now();
+        let single = scanner.find_first(&memory);
+        println!("{:>24} {:>10.1?}", "multi-pattern", start.elapsed());
+
+        let threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        let stride = memory.len() / threads;
+        let start = Instant::now();
+        thread::scope(|scope| {
+            for chunk_start in (0..memory.len()).step_by(stride) {
+                let chunk = &memory[chunk_start..(chunk_start + stride + scanner.max_len()).min(memory.len())];
+                let scanner = &scanner;
+                scope.spawn(move || scanner.find_first(chunk));
+            }
+        });
+        println!("{:>24} {:>10.1?} ({} threads)", "multi-pattern threaded", start.elapsed(), threads);
+
+        assert_eq!(naive, single);
+    }
+}
@@ -0,0 +1,136 @@
+use std::time::{Duration, Instant};
+
+use crate::TickRates;
+
+/// The kinds of data the reader polls, each on its own interval.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Category {
+    ServerTime,
+    Zone,
+    Mobs,
+    Target,
+    PartyList,
+}
+
+const CATEGORIES: [Category; 5] = [
+    Category::ServerTime,
+    Category::Zone,
+    Category::Mobs,
+    Category::Target,
+    Category::PartyList,
+];
+
+/// Tracks when each category of data is next due to be read. In adaptive mode every interval is
+/// stretched by `idle_multiplier` once nothing has happened for `idle_after_ms`, and snaps back as
+/// soon as the reader reports activity again.
+pub struct Schedule {
+    rates: TickRates,
+    next_due: [Instant; 5],
+    last_activity: Instant,
+}
+
+impl Schedule {
+    pub fn new(rates: TickRates, now: Instant) -> Schedule {
+        Schedule { rates, next_due: [now; 5], last_activity: now }
+    }
+
+    /// Returns whether `category` should be read at `now`, and if so schedules its next read.
+    pub fn due(&mut self, category: Category, now: Instant) -> bool {
+        if now >= self.next_due[category as usize] {
+            self.next_due[category as usize] = now + self.interval(category, now);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Records that something changed in the game, e.g. a combatant's HP.
+    pub fn mark_active(&mut self, now: Instant) {
+        let was_idle = self.is_idle(now);
+        self.last_activity = now;
+        if was_idle {
+            // Pull every category back to its normal rate instead of waiting out an idle interval.
+            for (category, slot) in CATEGORIES.iter().zip(self.next_due.iter_mut()) {
+                let interval = Duration::from_millis(self.rates.interval_ms(*category));
+                *slot = (*slot).min(now + interval);
+            }
+        }
+    }
+
+    pub fn is_idle(&self, now: Instant) -> bool {
+        self.rates.adaptive && now.duration_since(self.last_activity) >= Duration::from_millis(self.rates.idle_after_ms)
+    }
+
+    /// The earliest time any category is due.
+    pub fn next_wakeup(&self) -> Instant {
+        *self.next_due.iter().min().unwrap()
+    }
+
+    fn interval(&self, category: Category, now: Instant) -> Duration {
+        let interval = self.rates.interval_ms(category);
+        if self.is_idle(now) {
+            Duration::from_millis(interval * self.rates.idle_multiplier as u64)
+        } else {
+            Duration::from_millis(interval)
+        }
+    }
+}
+
+impl TickRates {
+    fn interval_ms(&self, category: Category) -> u64 {
+        match category {
+            Category::ServerTime => self.server_time_ms,
+            Category::Zone => self.zone_ms,
+            Category::Mobs => self.mobs_ms,
+            Category::Target => self.target_ms,
+            Category::PartyList => self.party_list_ms,
+        }
+    }
+}
+
+#[cfg(test)]
+mod schedule_tests {
+    use crate::mem::schedule::*;
+
+    fn rates(adaptive: bool) -> TickRates {
+        TickRates { adaptive, idle_after_ms: 1000, idle_multiplier: 4, ..TickRates::default() }
+    }
+
+    #[test]
+    fn categories_run_on_their_own_interval() {
+        let start = Instant::now();
+        let mut schedule = Schedule::new(rates(false), start);
+        assert!(schedule.due(Category::ServerTime, start));
+        assert!(schedule.due(Category::Zone, start));
+        assert!(!schedule.due(Category::ServerTime, start + Duration::from_millis(5)));
+        assert!(schedule.due(Category::ServerTime, start + Duration::from_millis(10)));
+        assert!(!schedule.due(Category::Zone, start + Duration::from_millis(999)));
+        assert!(schedule.due(Category::Zone, start + Duration::from_millis(1000)));
+        assert_eq!(schedule.next_wakeup(), start);
+    }
+
+    #[test]
+    fn adaptive_slows_down_when_idle() {
+        let start = Instant::now();
+        let idle = start + Duration::from_millis(1000);
+        let mut schedule = Schedule::new(rates(true), start);
+        assert!(!schedule.is_idle(start));
+        assert!(schedule.is_idle(idle));
+
+        assert!(schedule.due(Category::Mobs, idle));
+        assert!(!schedule.due(Category::Mobs, idle + Duration::from_millis(50)));
+        assert!(schedule.due(Category::Mobs, idle + Duration::from_millis(200)));
+
+        let combat = idle + Duration::from_millis(210);
+        schedule.mark_active(combat);
+        assert!(!schedule.is_idle(combat));
+        assert!(schedule.due(Category::Mobs, combat + Duration::from_millis(50)));
+    }
+
+    #[test]
+    fn fixed_rates_never_idle() {
+        let start = Instant::now();
+        let schedule = Schedule::new(rates(false), start);
+        assert!(!schedule.is_idle(start + Duration::from_secs(3600)));
+    }
+}
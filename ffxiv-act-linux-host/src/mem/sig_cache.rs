@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::mem::SignatureEntry;
+use crate::mem::pe::GameImage;
+
+const CACHE_FILE: &str = "signature_cache.json";
+/// Written by the launcher next to the executable and bumped with every patch.
+const GAME_VERSION_FILE: &str = "ffxivgame.ver";
+
+/// Signature scan results from a previous run, stored relative to the game's module base so they
+/// survive the image being mapped somewhere else.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct SignatureCache {
+    game_version: String,
+    signatures: String,
+    offsets: HashMap<String, usize>,
+}
+
+/// Identifies the installed game build: the contents of the version file next to the executable
+/// when there is one, otherwise a hash of the executable.
+pub fn game_version(game: &GameImage) -> Option<String> {
+    let path = game.path.as_ref()?;
+    if let Ok(version) = fs::read_to_string(path.with_file_name(GAME_VERSION_FILE)) {
+        Some(format!("ver:{}", version.trim()))
+    } else {
+        fs::read(path).ok().map(|exe| format!("fnv:{:016x}", fnv1a(&exe)))
+    }
+}
+
+/// Identifies the signature file a cache was built from, so editing it invalidates the cache.
+pub fn signature_hash(registry: &[SignatureEntry]) -> String {
+    let joined = registry.iter()
+        .map(|entry| format!("{}={}{:?}", entry.name, entry.pattern.to_lowercase(), entry.pointer_chain))
+        .collect::<Vec<_>>()
+        .join(",");
+    format!("{:016x}", fnv1a(joined.as_bytes()))
+}
+
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3))
+}
+
+/// The cache is kept next to the signature file it was built from, wherever that was found, rather
+/// than in the working directory, which for a service is usually `/`.
+pub fn cache_file(signature_file: &Path) -> PathBuf {
+    signature_file.with_file_name(CACHE_FILE)
+}
+
+/// Returns the cached signature addresses for this game build and signature file, if any.
+pub fn load(signature_file: &Path, game_version: &str, signatures: &str, base: usize) -> Option<HashMap<String, usize>> {
+    load_from(&cache_file(signature_file), game_version, signatures, base)
+}
+
+pub fn store(signature_file: &Path, game_version: String, signatures: String, base: usize, addresses: &HashMap<String, usize>) {
+    let path = cache_file(signature_file);
+    if let Err(e) = store_to(&path, game_version, signatures, base, addresses) {
+        warn!(target: "mem", "Unable to write {}, signatures will be scanned for again next time: {}", path.display(), e);
+    }
+}
+
+fn load_from(path: &Path, game_version: &str, signatures: &str, base: usize) -> Option<HashMap<String, usize>> {
+    let cache: SignatureCache = File::open(path).ok()
+        .and_then(|file| serde_json::from_reader(file).ok())?;
+    if cache.game_version == game_version && cache.signatures == signatures {
+        // An offset that doesn't fit with this base can't be right, so the cache isn't used.
+        cache.offsets.into_iter().map(|(name, offset)| base.checked_add(offset).map(|address| (name, address))).collect()
+    } else {
+        None
+    }
+}
+
+fn store_to(path: &Path, game_version: String, signatures: String, base: usize, addresses: &HashMap<String, usize>) -> std::io::Result<()> {
+    let offsets = addresses.iter()
+        .map(|(name, address)| (name.clone(), address.wrapping_sub(base)))
+        .collect();
+    let cache = SignatureCache { game_version, signatures, offsets };
+    serde_json::to_writer_pretty(File::create(path)?, &cache).map_err(std::io::Error::from)
+}
+
+#[cfg(test)]
+mod sig_cache_tests {
+    use crate::mem::sig_cache::*;
+
+    #[test]
+    fn cache_round_trip() {
+        let path = std::env::temp_dir().join(format!("signature_cache_test_{}.json", std::process::id()));
+        let mut addresses = HashMap::new();
+        addresses.insert("target".to_string(), 0x1_4000_1234);
+        addresses.insert("zone_id".to_string(), 0x1_4120_0000);
+        store_to(&path, "ver:2019.08.21.0000.0000".to_string(), "abc".to_string(), 0x1_4000_0000, &addresses).unwrap();
+
+        let moved = load_from(&path, "ver:2019.08.21.0000.0000", "abc", 0x2_0000_0000).unwrap();
+        assert_eq!(moved.get("target"), Some(&0x2_0000_1234));
+        assert_eq!(moved.get("zone_id"), Some(&0x2_0120_0000));
+        assert_eq!(load_from(&path, "ver:2019.09.03.0000.0000", "abc", 0x1_4000_0000), None);
+        assert_eq!(load_from(&path, "ver:2019.08.21.0000.0000", "abd", 0x1_4000_0000), None);
+        assert_eq!(load_from(&path, "ver:2019.08.21.0000.0000", "abc", usize::MAX - 0x1000), None);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn keep_cache_with_signatures() {
+        assert_eq!(cache_file(Path::new("/etc/xdg/ffxiv-act-linux/signatures_64.json")), PathBuf::from("/etc/xdg/ffxiv-act-linux/signature_cache.json"));
+        assert_eq!(cache_file(Path::new("signatures_64.json")), PathBuf::from("signature_cache.json"));
+    }
+
+    #[test]
+    fn hash_signatures() {
+        assert_ne!(fnv1a(b"0fb7c0894710488b0d"), fnv1a(b"0fb7c0894710488b0e"));
+
+        let entry = SignatureEntry {
+            name: "server_time".to_string(),
+            pattern: "488B05????????4885C0".to_string(),
+            pointer_chain: vec![72, 8, 2116],
+            required: true,
+            value_type: None,
+        };
+        let hash = signature_hash(std::slice::from_ref(&entry));
+        assert_eq!(signature_hash(&[SignatureEntry { pattern: entry.pattern.to_lowercase(), ..entry.clone() }]), hash);
+        assert_ne!(signature_hash(&[SignatureEntry { pattern: "488B0D????????4885C0".to_string(), ..entry.clone() }]), hash);
+        assert_ne!(signature_hash(&[SignatureEntry { pointer_chain: vec![72, 8, 2124], ..entry }]), hash);
+    }
+}
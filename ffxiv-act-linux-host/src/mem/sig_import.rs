@@ -0,0 +1,238 @@
+use serde_json::Value;
+
+use crate::mem::SignatureEntry;
+use crate::mem::reader::names;
+
+/// The signature file shipped with the host. Its `required` flags say which signatures the reader
+/// can't do without, for files that don't say or have no entry for them, and files that only give
+/// patterns are read through its pointer chains.
+const BUNDLED_SIGNATURES: &str = include_str!("../../signatures_64.json");
+
+/// Reads a signature file in any of the layouts we know of:
+///
+/// * this project's registry, e.g. `[{"name": "chat_log", "pattern": "e8????????85c0...", "required": false}]`;
+/// * its older fixed object, e.g. `{"chat_log": "e8????????85c0..."}`;
+/// * Sharlayan's lists, e.g. `[{"Key": "CHATLOG", "Value": "e8********85c0...", "PointerPath": [...]}]`,
+///   of which only the patterns are used, as its pointer paths count from another address;
+/// * any object of names to patterns, e.g. `{"ChatLog": "E8 ? ? ? ? 85 C0 ..."}`.
+///
+/// These aren't the FFXIV_ACT_Plugin's or Machina's signatures: the plugin builds its own into
+/// itself and Machina reads the network rather than memory, so neither has a file to import.
+///
+/// Outside of the registry, names are matched loosely against the signatures the reader uses and
+/// entries we have no use for are ignored. Patterns may be written as plain hex with `?` or `*`
+/// wildcard nibbles, or as IDA-style space-separated bytes with `?` or `??` wildcards.
+pub fn import_signatures(file: Value) -> Option<Vec<SignatureEntry>> {
+    let is_registry = match &file {
+        Value::Array(list) => list.iter().all(|entry| entry.get("name").is_some()),
+        _ => false,
+    };
+    if is_registry {
+        let mut registry: Vec<SignatureEntry> = serde_json::from_value(file).ok()?;
+        for entry in registry.iter_mut() {
+            entry.pattern = normalize_pattern(&entry.pattern);
+        }
+        return Some(registry);
+    }
+
+    let entries: Vec<(&str, &str)> = match &file {
+        Value::Array(list) => list.iter()
+            .filter_map(|entry| Some((entry.get("Key")?.as_str()?, entry.get("Value")?.as_str()?)))
+            .collect(),
+        Value::Object(map) => map.iter()
+            .filter_map(|(name, pattern)| Some((name.as_str(), pattern.as_str()?)))
+            .collect(),
+        _ => return None,
+    };
+
+    let bundled = bundled_registry();
+    let mut registry: Vec<SignatureEntry> = Vec::new();
+    for (name, pattern) in entries {
+        if let Some(name) = known_name(name) {
+            if registry.iter().all(|entry| entry.name != name) {
+                let known = bundled.iter().find(|entry| entry.name == name);
+                registry.push(SignatureEntry {
+                    name: name.to_string(),
+                    pattern: normalize_pattern(pattern),
+                    pointer_chain: known.map(|entry| entry.pointer_chain.clone()).unwrap_or_default(),
+                    required: known.is_some_and(|entry| entry.required),
+                    value_type: known.and_then(|entry| entry.value_type),
+                });
+            }
+        }
+    }
+    Some(registry)
+}
+
+fn bundled_registry() -> Vec<SignatureEntry> {
+    serde_json::from_str(BUNDLED_SIGNATURES).expect("The bundled signature file is malformed")
+}
+
+/// The signatures the bundled registry requires that `registry` has no entry for.
+pub fn missing_signatures(registry: &[SignatureEntry]) -> Vec<String> {
+    bundled_registry().into_iter()
+        .filter(|needed| needed.required && registry.iter().all(|entry| entry.name != needed.name))
+        .map(|needed| needed.name)
+        .collect()
+}
+
+/// Maps the names the various signature files use onto the signatures the reader uses.
+fn known_name(name: &str) -> Option<&'static str> {
+    let name = name.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase();
+    match name.as_str() {
+        "target" | "targetmanager" => Some(names::TARGET),
+        "chatlog" => Some(names::CHAT_LOG),
+        "mobarray" | "charmap" | "actortable" => Some(names::MOB_ARRAY),
+        "partylist" | "partymap" => Some(names::PARTY_LIST),
+        "servertime" => Some(names::SERVER_TIME),
+        "zoneid" | "zoneinfo" | "territory" => Some(names::ZONE_ID),
+        "player" | "playerinfo" => Some(names::PLAYER),
+        _ => None,
+    }
+}
+
+/// Checks that `pattern` is hex whose wildcards each cover a whole byte, as `parse_signature`
+/// expects, with at least one byte that isn't a wildcard for the scanner to search for.
+pub fn check_pattern(pattern: &str) -> Result<(), String> {
+    hex::decode(pattern.replace("?", "0")).map_err(|_| "it isn't hex".to_string())?;
+    let half_wildcard = pattern.as_bytes()
+        .chunks(2)
+        .position(|byte| byte.contains(&b'?') && byte != b"??");
+    match half_wildcard {
+        Some(byte) => Err(format!("byte {} is only half a wildcard", byte)),
+        None if pattern.chars().all(|c| c == '?') => Err("it has no bytes that aren't wildcards".to_string()),
+        None => Ok(()),
+    }
+}
+
+/// The entries of `registry` whose patterns can't be scanned for, and why.
+pub fn unusable_patterns(registry: &[SignatureEntry]) -> Vec<(String, String)> {
+    registry.iter()
+        .filter_map(|entry| check_pattern(&entry.pattern).err().map(|e| (entry.name.clone(), e)))
+        .collect()
+}
+
+/// Rewrites a pattern into the plain hex with `??` wildcard bytes that `ParseSignature` expects.
+fn normalize_pattern(pattern: &str) -> String {
+    let pattern = pattern.trim();
+    if pattern.contains(char::is_whitespace) {
+        pattern.split_whitespace()
+            .map(|byte| if byte.chars().all(|c| c == '?' || c == '*') { "??" } else { byte })
+            .collect()
+    } else {
+        pattern.replace('*', "?")
+    }
+}
+
+#[cfg(test)]
+mod sig_import_tests {
+    use crate::mem::sig_import::*;
+
+    fn entry<'a>(registry: &'a [SignatureEntry], name: &str) -> &'a SignatureEntry {
+        registry.iter().find(|entry| entry.name == name).unwrap()
+    }
+
+    fn pattern<'a>(registry: &'a [SignatureEntry], name: &str) -> &'a str {
+        &entry(registry, name).pattern
+    }
+
+    /// Imported entries are read the same way as those in `signatures_64.json`, the server time
+    /// through its pointer chain.
+    fn assert_server_time(registry: &[SignatureEntry]) {
+        let file = serde_json::from_str(include_str!("../../signatures_64.json")).unwrap();
+        let expected = import_signatures(file).unwrap();
+        for imported in registry {
+            let expected = entry(&expected, &imported.name);
+            assert_eq!((&imported.pointer_chain, imported.value_type), (&expected.pointer_chain, expected.value_type));
+        }
+        assert!(!entry(registry, names::SERVER_TIME).pointer_chain.is_empty());
+    }
+
+    #[test]
+    fn import_registry() {
+        let file = serde_json::from_str(include_str!("../../signatures_64.json")).unwrap();
+        let registry = import_signatures(file).unwrap();
+        assert_eq!(registry.len(), 7);
+        assert_eq!(pattern(&registry, names::CHAT_LOG), "e8????????85c0740e488b0d????????33D2E8????????488b0d");
+        assert!(registry.iter().all(|entry| entry.required == (entry.name != names::CHAT_LOG && entry.name != names::PLAYER)));
+
+        let file = serde_json::json!([{"name": "hotbar", "pattern": "48 8D 0D ? ? ? ?", "pointer_chain": [16, 8], "required": false}]);
+        let registry = import_signatures(file).unwrap();
+        assert_eq!(registry, vec![SignatureEntry {
+            name: "hotbar".to_string(),
+            pattern: "488D0D????????".to_string(),
+            pointer_chain: vec![16, 8],
+            required: false,
+            value_type: None,
+        }]);
+    }
+
+    #[test]
+    fn import_fixed_object() {
+        let file = serde_json::json!({
+            "target": "41bc000000e041bd01000000493bc47555488d0d",
+            "chat_log": "e8????????85c0740e488b0d????????33D2E8????????488b0d",
+            "server_time": "0fb7c0894710488b0d"
+        });
+        let registry = import_signatures(file).unwrap();
+        assert_eq!(registry.len(), 3);
+        assert_eq!(pattern(&registry, names::SERVER_TIME), "0fb7c0894710488b0d");
+        assert!(!entry(&registry, names::CHAT_LOG).required);
+        assert_server_time(&registry);
+    }
+
+    #[test]
+    fn import_sharlayan_list() {
+        let file = serde_json::json!([
+            {"Key": "CHARMAP", "Value": "488b420848c1e8033da701000077248bc0488d0d", "ASMSignature": true, "PointerPath": [0]},
+            {"Key": "CHATLOG", "Value": "e8********85c0740e488b0d********33D2E8********488b0d", "PointerPath": [0, 48]},
+            {"Key": "HOTBAR", "Value": "488d0d", "PointerPath": [0]},
+            {"Key": "PARTYMAP", "Value": "488D7C242066660F1F840000000000488B17488D0D"},
+            {"Key": "PLAYERINFO", "Value": "83f9ff7412448b048e8bd3488d0d"},
+            {"Key": "SERVERTIME", "Value": "0fb7c0894710488b0d"},
+            {"Key": "TARGET", "Value": "41bc000000e041bd01000000493bc47555488d0d"},
+            {"Key": "ZONEINFO", "Value": "f30f108d080400004c8d85580600000fb705"}
+        ]);
+        let registry = import_signatures(file).unwrap();
+        assert_eq!(registry.len(), 7);
+        assert_eq!(pattern(&registry, names::CHAT_LOG), "e8????????85c0740e488b0d????????33D2E8????????488b0d");
+        assert_eq!(pattern(&registry, names::MOB_ARRAY), "488b420848c1e8033da701000077248bc0488d0d");
+        assert_eq!(pattern(&registry, names::ZONE_ID), "f30f108d080400004c8d85580600000fb705");
+        assert_server_time(&registry);
+    }
+
+    #[test]
+    fn import_ida_patterns() {
+        assert_eq!(normalize_pattern("E8 ? ? ? ? 85 C0 74 0E 48 8B 0D ?? ?? ?? ??"), "E8????????85C0740E488B0D????????");
+        assert_eq!(normalize_pattern("0fb7c0894710488b0d"), "0fb7c0894710488b0d");
+        assert_eq!(import_signatures(serde_json::json!("0fb7c0894710488b0d")), None);
+    }
+
+    #[test]
+    fn reject_unusable_patterns() {
+        assert_eq!(check_pattern("????????"), Err("it has no bytes that aren't wildcards".to_string()));
+        assert_eq!(check_pattern(""), Err("it has no bytes that aren't wildcards".to_string()));
+        let file = serde_json::json!([
+            {"name": "target", "pattern": "?? ?? ?? ??"},
+            {"name": "zone_id", "pattern": "f30f108d"},
+            {"name": "hotbar", "pattern": "4?8b"}
+        ]);
+        assert_eq!(unusable_patterns(&import_signatures(file).unwrap()), vec![
+            ("target".to_string(), "it has no bytes that aren't wildcards".to_string()),
+            ("hotbar".to_string(), "byte 0 is only half a wildcard".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn report_missing_signatures() {
+        let file = serde_json::json!({"Target": "41bc", "chat_log": "e8", "MobArray": "48", "PartyList": "48", "Player": "83"});
+        let registry = import_signatures(file).unwrap();
+        assert_eq!(missing_signatures(&registry), vec![names::SERVER_TIME, names::ZONE_ID]);
+
+        let file = serde_json::from_str(include_str!("../../signatures_64.json")).unwrap();
+        assert!(missing_signatures(&import_signatures(file).unwrap()).is_empty());
+    }
+}
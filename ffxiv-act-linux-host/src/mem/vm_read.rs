@@ -0,0 +1,96 @@
+use proc_maps::Pid;
+
+/// Most iovecs the kernel accepts in one `process_vm_readv` call (`IOV_MAX` on Linux).
+const MAX_IOVECS: usize = 1024;
+
+/// Reads many `(address, length)` ranges out of another process using as few `process_vm_readv`
+/// calls as possible, rather than one syscall per range.
+///
+/// The result has one entry per request, in order. A range that couldn't be read in full is
+/// `None`; the kernel stops a vectored read at the first unreadable range, so the reads resume
+/// from the range after it.
+pub fn read_many(pid: Pid, requests: &[(usize, usize)]) -> Vec<Option<Vec<u8>>> {
+    let mut buffers: Vec<Vec<u8>> = requests.iter().map(|(_, len)| vec![0u8; *len]).collect();
+    let mut complete = vec![false; requests.len()];
+
+    let mut next = 0;
+    while next < requests.len() {
+        let batch_end = (next + MAX_IOVECS).min(requests.len());
+        let local = buffers[next..batch_end].iter_mut()
+            .map(|buffer| libc::iovec { iov_base: buffer.as_mut_ptr() as *mut libc::c_void, iov_len: buffer.len() })
+            .collect::<Vec<_>>();
+        let remote = requests[next..batch_end].iter()
+            .map(|(address, len)| libc::iovec { iov_base: *address as *mut libc::c_void, iov_len: *len })
+            .collect::<Vec<_>>();
+
+        let read = unsafe {
+            libc::process_vm_readv(pid, local.as_ptr(), local.len() as libc::c_ulong, remote.as_ptr(), remote.len() as libc::c_ulong, 0)
+        };
+        let mut remaining = if read < 0 { 0 } else { read as usize };
+
+        // Mark every range that was transferred in full. The first short one failed, so skip it.
+        let mut index = next;
+        while index < batch_end && remaining >= requests[index].1 {
+            remaining -= requests[index].1;
+            complete[index] = true;
+            index += 1;
+        }
+        next = if index < batch_end { index + 1 } else { batch_end };
+    }
+
+    buffers.into_iter()
+        .zip(complete)
+        .map(|(buffer, complete)| if complete { Some(buffer) } else { None })
+        .collect()
+}
+
+/// Reads a single range out of another process.
+pub fn read(pid: Pid, address: usize, len: usize) -> Option<Vec<u8>> {
+    try_read(pid, address, len).ok()
+}
+
+/// Reads a single range out of another process, reporting why it couldn't be read in full.
+pub fn try_read(pid: Pid, address: usize, len: usize) -> std::io::Result<Vec<u8>> {
+    let mut buffer = vec![0u8; len];
+    let local = libc::iovec { iov_base: buffer.as_mut_ptr() as *mut libc::c_void, iov_len: len };
+    let remote = libc::iovec { iov_base: address as *mut libc::c_void, iov_len: len };
+    let read = unsafe { libc::process_vm_readv(pid, &local, 1, &remote, 1, 0) };
+    if read < 0 {
+        Err(std::io::Error::last_os_error())
+    } else if (read as usize) < len {
+        Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, format!("only {} of {} bytes readable", read, len)))
+    } else {
+        Ok(buffer)
+    }
+}
+
+#[cfg(test)]
+mod vm_read_tests {
+    use crate::mem::vm_read::*;
+
+    #[test]
+    fn read_own_process() {
+        let pid = std::process::id() as Pid;
+        let first = [1u8, 2, 3, 4];
+        let second = vec![9u8; 5000];
+        let requests = vec![
+            (first.as_ptr() as usize, first.len()),
+            (0, 8),
+            (second.as_ptr() as usize, second.len()),
+            (first.as_ptr() as usize + 1, 2),
+        ];
+        let read = read_many(pid, &requests);
+        assert_eq!(read, vec![Some(first.to_vec()), None, Some(second.clone()), Some(vec![2, 3])]);
+        assert_eq!(super::read(pid, second.as_ptr() as usize, 3), Some(vec![9, 9, 9]));
+        assert!(try_read(pid, 0, 8).is_err());
+    }
+
+    #[test]
+    fn read_more_than_one_batch() {
+        let pid = std::process::id() as Pid;
+        let data = (0..3000u32).map(|i| i as u8).collect::<Vec<_>>();
+        let requests = (0..3000).map(|i| (data.as_ptr() as usize + i, 1)).collect::<Vec<_>>();
+        let read = read_many(pid, &requests);
+        assert!(read.iter().enumerate().all(|(i, byte)| *byte == Some(vec![i as u8])));
+    }
+}
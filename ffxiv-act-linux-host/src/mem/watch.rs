@@ -0,0 +1,63 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// Notices when any of a set of files changes, by polling their modification times and sizes.
+pub struct FileWatcher {
+    files: Vec<(PathBuf, Option<(SystemTime, u64)>)>,
+}
+
+impl FileWatcher {
+    pub fn new<P: Into<PathBuf>>(paths: Vec<P>) -> FileWatcher {
+        let files = paths.into_iter()
+            .map(|path| {
+                let path = path.into();
+                let stamp = stamp(&path);
+                (path, stamp)
+            })
+            .collect();
+        FileWatcher { files }
+    }
+
+    /// Whether any of the files changed, appeared or disappeared since the last call.
+    pub fn changed(&mut self) -> bool {
+        let mut changed = false;
+        for (path, last) in self.files.iter_mut() {
+            let now = stamp(path);
+            if now != *last {
+                *last = now;
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+fn stamp(path: &PathBuf) -> Option<(SystemTime, u64)> {
+    fs::metadata(path).ok()
+        .and_then(|metadata| Some((metadata.modified().ok()?, metadata.len())))
+}
+
+#[cfg(test)]
+mod watch_tests {
+    use crate::mem::watch::*;
+
+    #[test]
+    fn notice_changes() {
+        let path = std::env::temp_dir().join(format!("watch_test_{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut watcher = FileWatcher::new(vec![path.clone()]);
+        assert!(!watcher.changed());
+
+        fs::write(&path, "[]").unwrap();
+        assert!(watcher.changed());
+        assert!(!watcher.changed());
+
+        // The size changes even if the modification time doesn't tick over.
+        fs::write(&path, "[{}]").unwrap();
+        assert!(watcher.changed());
+
+        fs::remove_file(&path).unwrap();
+        assert!(watcher.changed());
+    }
+}
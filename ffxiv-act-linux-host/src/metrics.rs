@@ -0,0 +1,195 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io;
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use ffxiv_act_linux_common::http;
+use serde::Deserialize;
+
+/// Where the metrics endpoint listens. It is only started when the config has a `metrics` section.
+#[derive(Deserialize, Clone)]
+pub struct MetricsConfig {
+    pub bind_address: String,
+}
+
+/// How long a reader tick took, in seconds.
+const TICK_SECONDS_BUCKETS: &[f64] = &[0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25];
+/// How many mobs were in the mob array when it was read.
+const MOBS_BUCKETS: &[f64] = &[0.0, 10.0, 25.0, 50.0, 100.0, 200.0, 421.0];
+
+/// A value that only goes up, or that mirrors one that does, such as pcap's statistics.
+#[derive(Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn add(&self, by: u64) {
+        self.0.fetch_add(by, Ordering::Relaxed);
+    }
+
+    pub fn set(&self, value: u64) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Counters told apart by one label, e.g. the client they count bytes for.
+#[derive(Default)]
+pub struct LabeledCounter(Mutex<BTreeMap<String, u64>>);
+
+impl LabeledCounter {
+    pub fn add(&self, label: &str, by: u64) {
+        *self.0.lock().unwrap().entry(label.to_string()).or_insert(0) += by;
+    }
+
+    /// Stops reporting `label`, e.g. once a client has disconnected.
+    pub fn remove(&self, label: &str) {
+        self.0.lock().unwrap().remove(label);
+    }
+}
+
+pub struct Histogram {
+    bounds: &'static [f64],
+    counts: Mutex<HistogramCounts>,
+}
+
+#[derive(Default)]
+struct HistogramCounts {
+    /// Observations per bucket, not cumulative. The last bucket is `+Inf`.
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Histogram {
+        Histogram { bounds, counts: Mutex::new(HistogramCounts { buckets: vec![0; bounds.len() + 1], ..Default::default() }) }
+    }
+
+    pub fn observe(&self, value: f64) {
+        let bucket = self.bounds.iter().position(|bound| value <= *bound).unwrap_or(self.bounds.len());
+        let mut counts = self.counts.lock().unwrap();
+        counts.buckets[bucket] += 1;
+        counts.sum += value;
+        counts.count += 1;
+    }
+
+    pub fn observe_duration(&self, duration: Duration) {
+        self.observe(duration.as_secs_f64());
+    }
+}
+
+/// What the host measures while it runs. One set is shared by the reader, the memory-sync server
+/// and network capture for as long as the host runs, across restarts of the game.
+pub struct Metrics {
+    pub reader_tick_seconds: Histogram,
+    pub reader_mobs: Histogram,
+    /// Reads that failed, by signature.
+    pub reader_failures: LabeledCounter,
+    /// Memory-sync bytes sent, by client.
+    pub sent_bytes: LabeledCounter,
+    /// Memory-sync datagrams that couldn't be sent.
+    pub dropped_sync_packets: Counter,
+    pub pcap_received: Counter,
+    pub pcap_dropped: Counter,
+    pub pcap_if_dropped: Counter,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics {
+            reader_tick_seconds: Histogram::new(TICK_SECONDS_BUCKETS),
+            reader_mobs: Histogram::new(MOBS_BUCKETS),
+            reader_failures: LabeledCounter::default(),
+            sent_bytes: LabeledCounter::default(),
+            dropped_sync_packets: Counter::default(),
+            pcap_received: Counter::default(),
+            pcap_dropped: Counter::default(),
+            pcap_if_dropped: Counter::default(),
+        }
+    }
+
+    /// Writes every metric in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        histogram(&mut out, "ffxiv_host_reader_tick_seconds", "Time taken by each memory reader tick.", &self.reader_tick_seconds);
+        histogram(&mut out, "ffxiv_host_reader_mobs", "Mobs found each time the mob array is read.", &self.reader_mobs);
+        labeled_counter(&mut out, "ffxiv_host_reader_failures_total", "Memory reads that failed.", "signature", &self.reader_failures);
+        labeled_counter(&mut out, "ffxiv_host_sent_bytes_total", "Memory-sync bytes sent to each connected client.", "client", &self.sent_bytes);
+        counter(&mut out, "ffxiv_host_dropped_sync_packets_total", "Memory-sync datagrams that couldn't be sent.", &self.dropped_sync_packets);
+        counter(&mut out, "ffxiv_host_pcap_received_packets_total", "Packets pcap received during the current capture.", &self.pcap_received);
+        counter(&mut out, "ffxiv_host_pcap_dropped_packets_total", "Packets pcap dropped for lack of buffer space during the current capture.", &self.pcap_dropped);
+        counter(&mut out, "ffxiv_host_pcap_if_dropped_packets_total", "Packets the interface dropped during the current capture.", &self.pcap_if_dropped);
+        out
+    }
+}
+
+fn counter(out: &mut String, name: &str, help: &str, counter: &Counter) {
+    let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter\n{} {}", name, help, name, name, counter.get());
+}
+
+fn labeled_counter(out: &mut String, name: &str, help: &str, label: &str, counter: &LabeledCounter) {
+    let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter", name, help, name);
+    for (value, count) in counter.0.lock().unwrap().iter() {
+        let _ = writeln!(out, "{}{{{}=\"{}\"}} {}", name, label, value.replace('\\', "\\\\").replace('"', "\\\""), count);
+    }
+}
+
+fn histogram(out: &mut String, name: &str, help: &str, histogram: &Histogram) {
+    let counts = histogram.counts.lock().unwrap();
+    let _ = writeln!(out, "# HELP {} {}\n# TYPE {} histogram", name, help, name);
+    let mut cumulative = 0;
+    let bounds = histogram.bounds.iter().map(|bound| bound.to_string()).chain(Some("+Inf".to_string()));
+    for (bound, count) in bounds.zip(counts.buckets.iter()) {
+        cumulative += count;
+        let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative);
+    }
+    let _ = writeln!(out, "{}_sum {}\n{}_count {}", name, counts.sum, name, counts.count);
+}
+
+/// Serves `metrics` over HTTP on `address`, at `/metrics`.
+pub fn start(metrics: Arc<Metrics>, address: &str) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    info!(target: "metrics", "Serving metrics on http://{}/metrics", address);
+    let page = http::Page { path: "/metrics", content_type: "text/plain; version=0.0.4", target: "metrics" };
+    thread::spawn(move || http::serve(listener, page, move || Ok(metrics.render())));
+    Ok(())
+}
+
+#[cfg(test)]
+mod metrics_tests {
+    use crate::metrics::*;
+
+    #[test]
+    fn render_metrics() {
+        let metrics = Metrics::new();
+        metrics.reader_tick_seconds.observe_duration(Duration::from_micros(800));
+        metrics.reader_tick_seconds.observe(0.02);
+        metrics.reader_tick_seconds.observe(3.0);
+        metrics.reader_failures.add("mob_array", 2);
+        metrics.sent_bytes.add("192.168.122.40:50321", 1200);
+        metrics.sent_bytes.add("192.168.122.41:50322", 10);
+        metrics.sent_bytes.remove("192.168.122.41:50322");
+        metrics.pcap_dropped.set(7);
+        let text = metrics.render();
+
+        assert!(text.contains("# TYPE ffxiv_host_reader_tick_seconds histogram\n\
+ffxiv_host_reader_tick_seconds_bucket{le=\"0.0005\"} 0\n\
+ffxiv_host_reader_tick_seconds_bucket{le=\"0.001\"} 1\n"));
+        assert!(text.contains("ffxiv_host_reader_tick_seconds_bucket{le=\"0.025\"} 2\n"));
+        assert!(text.contains("ffxiv_host_reader_tick_seconds_bucket{le=\"+Inf\"} 3\n\
+ffxiv_host_reader_tick_seconds_sum 3.0208\n\
+ffxiv_host_reader_tick_seconds_count 3\n"));
+        assert!(text.contains("ffxiv_host_reader_mobs_count 0\n"));
+        assert!(text.contains("ffxiv_host_reader_failures_total{signature=\"mob_array\"} 2\n"));
+        assert!(text.contains("ffxiv_host_sent_bytes_total{client=\"192.168.122.40:50321\"} 1200\n"));
+        assert!(!text.contains("192.168.122.41"));
+        assert!(text.contains("# TYPE ffxiv_host_pcap_dropped_packets_total counter\nffxiv_host_pcap_dropped_packets_total 7\n"));
+    }
+}
@@ -0,0 +1,522 @@
+use std::io::Read;
+use std::sync::Arc;
+
+use byteorder::{ByteOrder, LittleEndian as LE};
+use flate2::read::ZlibDecoder;
+use serde::{Deserialize, Deserializer};
+use serde::de::Error as _;
+
+use crate::mem::Event;
+
+/// The start of every frame the game server sends, other than keep-alives, whose start is zeroed.
+const FRAME_MAGIC: [u8; 16] = [0x52, 0x52, 0xa0, 0x41, 0xff, 0x5d, 0x46, 0xe2, 0x7f, 0x2a, 0x64, 0x4d, 0x7b, 0x99, 0xc4, 0x75];
+const FRAME_HEADER_LEN: usize = 40;
+const SEGMENT_HEADER_LEN: usize = 16;
+const IPC_HEADER_LEN: usize = 16;
+/// Segments of any other type are keep-alives and the like.
+const SEGMENT_IPC: u16 = 3;
+/// Larger frames than this are taken to mean the stream is out of step rather than waited for.
+const MAX_FRAME_LEN: usize = 0x10_0000;
+
+const COMPRESSION_NONE: u8 = 0;
+const COMPRESSION_ZLIB: u8 = 1;
+
+/// Effect types in an action's effect entries.
+const EFFECT_DAMAGE: u8 = 3;
+const EFFECT_HEAL: u8 = 4;
+const EFFECT_BLOCKED_DAMAGE: u8 = 5;
+const EFFECT_PARRIED_DAMAGE: u8 = 6;
+/// Set when the amount is too large for 16 bits.
+const FLAG_LARGE_VALUE: u8 = 0x40;
+/// Set when the effect lands on whoever used the action, e.g. the heal of a drain.
+const FLAG_ON_SOURCE: u8 = 0x80;
+const EFFECTS_PER_TARGET: usize = 8;
+const EFFECT_LEN: usize = 8;
+
+/// The actor control category the server ticks damage and healing over time with.
+const CONTROL_HOT_DOT: u16 = 0x17;
+const TICK_DOT: u32 = 3;
+const TICK_HOT: u32 = 4;
+const MAX_STATUS_ENTRIES: usize = 4;
+
+/// The opcodes of the messages the host decodes. They change with every game patch, so they come
+/// from the config, as hex strings (`"0x01a4"`) or numbers, and messages without one are skipped.
+#[derive(Deserialize, Clone, Default, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Opcodes {
+    #[serde(default, deserialize_with = "opcode")]
+    pub action_effect_1: Option<u16>,
+    #[serde(default, deserialize_with = "opcode")]
+    pub action_effect_8: Option<u16>,
+    #[serde(default, deserialize_with = "opcode")]
+    pub action_effect_16: Option<u16>,
+    #[serde(default, deserialize_with = "opcode")]
+    pub action_effect_24: Option<u16>,
+    #[serde(default, deserialize_with = "opcode")]
+    pub action_effect_32: Option<u16>,
+    #[serde(default, deserialize_with = "opcode")]
+    pub actor_control: Option<u16>,
+    #[serde(default, deserialize_with = "opcode")]
+    pub effect_result: Option<u16>,
+    #[serde(default, deserialize_with = "opcode")]
+    pub chat: Option<u16>,
+}
+
+impl Opcodes {
+    /// How many targets an action effect with `opcode` has room for, if it is one.
+    fn action_effect_targets(&self, opcode: u16) -> Option<usize> {
+        [(self.action_effect_1, 1), (self.action_effect_8, 8), (self.action_effect_16, 16), (self.action_effect_24, 24), (self.action_effect_32, 32)]
+            .iter()
+            .find(|(known, _)| *known == Some(opcode))
+            .map(|(_, targets)| *targets)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawOpcode {
+    Number(u16),
+    Text(String),
+}
+
+fn opcode<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u16>, D::Error> {
+    match RawOpcode::deserialize(deserializer)? {
+        RawOpcode::Number(opcode) => Ok(Some(opcode)),
+        RawOpcode::Text(text) => u16::from_str_radix(text.trim_start_matches("0x"), 16)
+            .map(Some)
+            .map_err(|_| D::Error::custom(format!("\"{}\" isn't an opcode, expected e.g. \"0x01a4\"", text))),
+    }
+}
+
+/// One effect of an action on one target, as the game sends it. `high_byte` holds the top byte of
+/// amounts too large for `value`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Effect {
+    pub kind: u8,
+    pub params: [u8; 3],
+    pub high_byte: u8,
+    pub flags: u8,
+    pub value: u16,
+}
+
+impl Effect {
+    pub fn amount(&self) -> u32 {
+        if self.flags & FLAG_LARGE_VALUE != 0 {
+            (self.high_byte as u32) << 16 | self.value as u32
+        } else {
+            self.value as u32
+        }
+    }
+
+    pub fn is_damage(&self) -> bool {
+        self.kind == EFFECT_DAMAGE || self.kind == EFFECT_BLOCKED_DAMAGE || self.kind == EFFECT_PARRIED_DAMAGE
+    }
+
+    pub fn is_heal(&self) -> bool {
+        self.kind == EFFECT_HEAL
+    }
+
+    pub fn on_source(&self) -> bool {
+        self.flags & FLAG_ON_SOURCE != 0
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ActionTarget {
+    pub id: u32,
+    /// Only the effect slots that are used.
+    pub effects: Vec<Effect>,
+}
+
+/// An ability or spell landing, with what it did to each target.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Action {
+    pub source: u32,
+    pub id: u32,
+    pub sequence: u32,
+    /// Whether the action was sent as an area of effect, however many targets it hit.
+    pub aoe: bool,
+    pub targets: Vec<ActionTarget>,
+}
+
+/// Damage or healing over time from a status effect.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tick {
+    pub target: u32,
+    /// Who applied the status, or 0 when the server doesn't say.
+    pub source: u32,
+    pub status: u32,
+    pub heal: bool,
+    pub amount: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Status {
+    pub id: u16,
+    /// Stacks, or another value depending on the status.
+    pub param: u16,
+    pub duration: f32,
+    pub source: u32,
+}
+
+/// A combatant's HP and the statuses an action put on them, sent after the action lands.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EffectResult {
+    pub target: u32,
+    pub current_hp: u32,
+    pub max_hp: u32,
+    pub statuses: Vec<Status>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Chat {
+    pub code: u16,
+    pub name: String,
+    pub message: String,
+}
+
+/// Reassembles frames from the TCP payloads captured from the game server and turns the messages
+/// in them that `opcodes` names into events. Captured payloads are taken to arrive in order; when
+/// they don't, the decoder drops bytes until it finds the start of a frame again.
+pub struct Decoder {
+    opcodes: Opcodes,
+    buffer: Vec<u8>,
+    warned_compression: bool,
+}
+
+impl Decoder {
+    pub fn new(opcodes: Opcodes) -> Decoder {
+        Decoder { opcodes, buffer: Vec::new(), warned_compression: false }
+    }
+
+    /// Takes the next captured payload, returning the events of every frame it completes.
+    pub fn push(&mut self, payload: &[u8]) -> Vec<Event> {
+        self.buffer.extend_from_slice(payload);
+        let mut events = Vec::new();
+        loop {
+            if !self.align() || self.buffer.len() < FRAME_HEADER_LEN {
+                break;
+            }
+            let size = LE::read_u32(&self.buffer[24..28]) as usize;
+            if !(FRAME_HEADER_LEN..=MAX_FRAME_LEN).contains(&size) {
+                debug!(target: "net", "Skipping a frame claiming to be {} bytes", size);
+                self.buffer.drain(..1);
+                continue;
+            }
+            if self.buffer.len() < size {
+                break;
+            }
+            let frame = self.buffer.drain(..size).collect::<Vec<_>>();
+            self.decode_frame(&frame, &mut events);
+        }
+        events
+    }
+
+    /// Drops bytes before the next frame start, returning whether the buffer might start with one.
+    fn align(&mut self) -> bool {
+        let starts_frame = |window: &[u8]| window == FRAME_MAGIC || window.iter().all(|byte| *byte == 0);
+        match self.buffer.windows(FRAME_MAGIC.len()).position(starts_frame) {
+            Some(0) => true,
+            Some(start) => {
+                self.buffer.drain(..start);
+                true
+            },
+            None => {
+                // The start of a frame may be cut off at the end of what's arrived so far.
+                let keep = self.buffer.len().min(FRAME_MAGIC.len() - 1);
+                let drop = self.buffer.len() - keep;
+                self.buffer.drain(..drop);
+                false
+            },
+        }
+    }
+
+    fn decode_frame(&mut self, frame: &[u8], events: &mut Vec<Event>) {
+        let segments = LE::read_u16(&frame[30..32]);
+        let body = &frame[FRAME_HEADER_LEN..];
+        let inflated;
+        let body = match frame[33] {
+            COMPRESSION_NONE => body,
+            COMPRESSION_ZLIB => {
+                let mut buffer = Vec::new();
+                if let Err(e) = ZlibDecoder::new(body).read_to_end(&mut buffer) {
+                    debug!(target: "net", "Unable to inflate a frame: {}", e);
+                    return;
+                }
+                inflated = buffer;
+                &inflated[..]
+            },
+            other => {
+                if !self.warned_compression {
+                    warn!(target: "net", "The game server compresses frames with a method the host can't decode ({}); no abilities, buffs or chat will be seen.", other);
+                    self.warned_compression = true;
+                }
+                return;
+            },
+        };
+
+        let mut position = 0;
+        for _ in 0..segments {
+            if body.len() < position + SEGMENT_HEADER_LEN {
+                break;
+            }
+            let size = LE::read_u32(&body[position..]) as usize;
+            if size < SEGMENT_HEADER_LEN || body.len() < position + size {
+                break;
+            }
+            let segment = &body[position..position + size];
+            position += size;
+            if LE::read_u16(&segment[12..14]) != SEGMENT_IPC || segment.len() < SEGMENT_HEADER_LEN + IPC_HEADER_LEN {
+                continue;
+            }
+            let source = LE::read_u32(&segment[4..8]);
+            let opcode = LE::read_u16(&segment[SEGMENT_HEADER_LEN + 2..]);
+            if let Some(event) = self.decode_message(opcode, source, &segment[SEGMENT_HEADER_LEN + IPC_HEADER_LEN..]) {
+                events.push(event);
+            }
+        }
+    }
+
+    /// `source` is the actor the segment is about: the one acting, or the one an effect lands on.
+    fn decode_message(&self, opcode: u16, source: u32, data: &[u8]) -> Option<Event> {
+        if let Some(targets) = self.opcodes.action_effect_targets(opcode) {
+            decode_action(source, targets, data).map(|action| Event::Action(Arc::new(action)))
+        } else if Some(opcode) == self.opcodes.actor_control {
+            decode_tick(source, data).map(Event::Tick)
+        } else if Some(opcode) == self.opcodes.effect_result {
+            decode_effect_result(source, data).map(|result| Event::EffectResult(Arc::new(result)))
+        } else if Some(opcode) == self.opcodes.chat {
+            decode_chat(data).map(|chat| Event::Chat(Arc::new(chat)))
+        } else {
+            None
+        }
+    }
+}
+
+/// Action effects have room for `room` targets: their effects follow a 42-byte header, eight
+/// slots each, and their ids come after every target's effects.
+fn decode_action(source: u32, room: usize, data: &[u8]) -> Option<Action> {
+    let ids_start = 48 + EFFECTS_PER_TARGET * EFFECT_LEN * room;
+    if data.len() < ids_start + 8 * room {
+        return None;
+    }
+    let hit = (data[33] as usize).min(room);
+    let targets = (0..hit).map(|index| {
+        let effects = data[42 + index * EFFECTS_PER_TARGET * EFFECT_LEN..].chunks(EFFECT_LEN)
+            .take(EFFECTS_PER_TARGET)
+            .map(|effect| Effect {
+                kind: effect[0],
+                params: [effect[1], effect[2], effect[3]],
+                high_byte: effect[4],
+                flags: effect[5],
+                value: LE::read_u16(&effect[6..8]),
+            })
+            .filter(|effect| effect.kind != 0)
+            .collect();
+        ActionTarget { id: LE::read_u32(&data[ids_start + index * 8..]), effects }
+    }).collect();
+    Some(Action { source, id: LE::read_u32(&data[8..12]), sequence: LE::read_u32(&data[12..16]), aoe: room > 1, targets })
+}
+
+/// Actor controls do many things; only damage and healing over time are decoded.
+fn decode_tick(target: u32, data: &[u8]) -> Option<Tick> {
+    if data.len() < 20 || LE::read_u16(&data[0..2]) != CONTROL_HOT_DOT {
+        return None;
+    }
+    let heal = match LE::read_u32(&data[8..12]) {
+        TICK_DOT => false,
+        TICK_HOT => true,
+        _ => return None,
+    };
+    Some(Tick { target, source: LE::read_u32(&data[16..20]), status: LE::read_u32(&data[4..8]), heal, amount: LE::read_u32(&data[12..16]) })
+}
+
+fn decode_effect_result(source: u32, data: &[u8]) -> Option<EffectResult> {
+    if data.len() < 24 {
+        return None;
+    }
+    let count = (data[21] as usize).min(MAX_STATUS_ENTRIES);
+    let statuses = data[24..].chunks_exact(16).take(count).map(|entry| Status {
+        id: LE::read_u16(&entry[2..4]),
+        param: LE::read_u16(&entry[4..6]),
+        duration: LE::read_f32(&entry[8..12]),
+        source: LE::read_u32(&entry[12..16]),
+    }).collect();
+    Some(EffectResult { target: source, current_hp: LE::read_u32(&data[8..12]), max_hp: LE::read_u32(&data[12..16]), statuses })
+}
+
+fn decode_chat(data: &[u8]) -> Option<Chat> {
+    if data.len() < 48 {
+        return None;
+    }
+    Some(Chat { code: LE::read_u16(&data[14..16]), name: nul_terminated(&data[16..48]), message: nul_terminated(&data[48..]) })
+}
+
+fn nul_terminated(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|byte| *byte == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+#[cfg(test)]
+mod decode_tests {
+    use crate::net::decode::*;
+    use byteorder::WriteBytesExt;
+    use flate2::Compression;
+    use flate2::write::ZlibEncoder;
+    use std::io::Write;
+
+    const ACTION_EFFECT_8: u16 = 0x2a1;
+    const ACTOR_CONTROL: u16 = 0x1b3;
+    const EFFECT_RESULT: u16 = 0x3c4;
+    const CHAT: u16 = 0x0f2;
+
+    fn opcodes() -> Opcodes {
+        serde_json::from_value(serde_json::json!({
+            "action_effect_8": "0x2a1", "actor_control": ACTOR_CONTROL, "effect_result": "3c4", "chat": "0x00f2",
+        })).unwrap()
+    }
+
+    fn segment(source: u32, opcode: u16, data: &[u8]) -> Vec<u8> {
+        let mut segment = Vec::new();
+        segment.write_u32::<LE>((SEGMENT_HEADER_LEN + IPC_HEADER_LEN + data.len()) as u32).unwrap();
+        segment.write_u32::<LE>(source).unwrap();
+        segment.write_u32::<LE>(0x1040_2A3B).unwrap();
+        segment.write_u16::<LE>(SEGMENT_IPC).unwrap();
+        segment.extend_from_slice(&[0; 2]);
+        segment.write_u16::<LE>(0x14).unwrap();
+        segment.write_u16::<LE>(opcode).unwrap();
+        segment.extend_from_slice(&[0; 12]);
+        segment.extend_from_slice(data);
+        segment
+    }
+
+    fn frame(segments: &[Vec<u8>], compression: u8) -> Vec<u8> {
+        let mut body = segments.concat();
+        if compression == COMPRESSION_ZLIB {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&body).unwrap();
+            body = encoder.finish().unwrap();
+        }
+        let mut frame = FRAME_MAGIC.to_vec();
+        frame.write_u64::<LE>(1_760_000_000_123).unwrap();
+        frame.write_u32::<LE>((FRAME_HEADER_LEN + body.len()) as u32).unwrap();
+        frame.write_u16::<LE>(1).unwrap();
+        frame.write_u16::<LE>(segments.len() as u16).unwrap();
+        frame.extend_from_slice(&[0, compression, 0, 0, 0, 0, 0, 0]);
+        frame.extend_from_slice(&body);
+        frame
+    }
+
+    /// An eight-target action effect hitting `targets`, each with one effect of `kind` for `value`.
+    fn action_effect(action: u32, targets: &[(u32, u8, u32)]) -> Vec<u8> {
+        let mut data = vec![0; 48 + 8 * 64 + 8 * 8];
+        LE::write_u32(&mut data[8..12], action);
+        LE::write_u32(&mut data[12..16], 7);
+        data[33] = targets.len() as u8;
+        for (index, (id, kind, value)) in targets.iter().enumerate() {
+            let effect = 42 + index * 64;
+            data[effect] = *kind;
+            if *value > 0xffff {
+                data[effect + 4] = (value >> 16) as u8;
+                data[effect + 5] = 0x40;
+            }
+            LE::write_u16(&mut data[effect + 6..effect + 8], *value as u16);
+            LE::write_u32(&mut data[48 + 8 * 64 + index * 8..], *id);
+        }
+        data
+    }
+
+    fn actor_control(category: u16, params: [u32; 4]) -> Vec<u8> {
+        let mut data = vec![0; 24];
+        LE::write_u16(&mut data[0..2], category);
+        for (index, param) in params.iter().enumerate() {
+            LE::write_u32(&mut data[4 + index * 4..], *param);
+        }
+        data
+    }
+
+    #[test]
+    fn read_opcodes() {
+        assert_eq!(opcodes().action_effect_targets(ACTION_EFFECT_8), Some(8));
+        assert_eq!(opcodes().action_effect_targets(EFFECT_RESULT), None);
+        assert_eq!(opcodes().action_effect_1, None);
+        assert_eq!(opcodes().chat, Some(CHAT));
+        let error = serde_json::from_value::<Opcodes>(serde_json::json!({"chat": "0xzz"})).err().unwrap();
+        assert!(error.to_string().contains("\"0xzz\" isn't an opcode"));
+    }
+
+    #[test]
+    fn decode_actions() {
+        let mut decoder = Decoder::new(opcodes());
+        let data = action_effect(0x1d0c, &[(0x4000_0001, 3, 81_234), (0x4000_0002, 4, 1_200)]);
+        let events = decoder.push(&frame(&[segment(0x1040_2A3B, ACTION_EFFECT_8, &data)], COMPRESSION_ZLIB));
+        assert_eq!(events.len(), 1);
+        match &events[0] {
+            Event::Action(action) => {
+                assert_eq!((action.source, action.id, action.sequence, action.aoe), (0x1040_2A3B, 0x1d0c, 7, true));
+                assert_eq!(action.targets.len(), 2);
+                assert_eq!(action.targets[0].id, 0x4000_0001);
+                assert_eq!(action.targets[0].effects.len(), 1);
+                assert_eq!(action.targets[0].effects, vec![Effect { kind: 3, params: [0; 3], high_byte: 1, flags: 0x40, value: 15_698 }]);
+                assert_eq!(action.targets[1].effects, vec![Effect { kind: 4, params: [0; 3], high_byte: 0, flags: 0, value: 1_200 }]);
+                assert!(action.targets[0].effects[0].is_damage());
+                assert_eq!(action.targets[0].effects[0].amount(), 81_234);
+                assert!(action.targets[1].effects[0].is_heal());
+                assert!(!action.targets[1].effects[0].on_source());
+            },
+            _ => panic!("expected an action"),
+        }
+    }
+
+    #[test]
+    fn decode_ticks_statuses_and_chat() {
+        let mut decoder = Decoder::new(opcodes());
+        let mut effect_result = vec![0; 24 + 4 * 16];
+        LE::write_u32(&mut effect_result[8..12], 51_234);
+        LE::write_u32(&mut effect_result[12..16], 60_000);
+        effect_result[21] = 1;
+        LE::write_u16(&mut effect_result[26..28], 0x74f);
+        LE::write_f32(&mut effect_result[32..36], 30.0);
+        LE::write_u32(&mut effect_result[36..40], 0x1040_2A3B);
+        let mut chat = vec![0; 48];
+        LE::write_u16(&mut chat[14..16], 0x0a);
+        chat[16..25].copy_from_slice(b"Kate Arch");
+        chat.extend_from_slice(b"hello\0");
+        let events = decoder.push(&frame(&[
+            segment(0x4000_0001, ACTOR_CONTROL, &actor_control(CONTROL_HOT_DOT, [0x74f, TICK_DOT, 4_321, 0x1040_2A3B])),
+            segment(0x4000_0001, ACTOR_CONTROL, &actor_control(6, [0x1040_2A3B, 0, 0, 0])),
+            segment(0x4000_0001, EFFECT_RESULT, &effect_result),
+            segment(0x1040_2A3B, CHAT, &chat),
+            segment(0x1040_2A3B, 0x999, &[0; 8]),
+        ], COMPRESSION_NONE));
+        assert_eq!(events.len(), 3);
+        match (&events[0], &events[1], &events[2]) {
+            (Event::Tick(tick), Event::EffectResult(result), Event::Chat(chat)) => {
+                assert_eq!(*tick, Tick { target: 0x4000_0001, source: 0x1040_2A3B, status: 0x74f, heal: false, amount: 4_321 });
+                assert_eq!(**result, EffectResult { target: 0x4000_0001, current_hp: 51_234, max_hp: 60_000,
+                    statuses: vec![Status { id: 0x74f, param: 0, duration: 30.0, source: 0x1040_2A3B }] });
+                assert_eq!(**chat, Chat { code: 0x0a, name: "Kate Arch".to_string(), message: "hello".to_string() });
+            },
+            _ => panic!("expected a tick, an effect result and chat"),
+        }
+    }
+
+    #[test]
+    fn reassemble_frames() {
+        let mut decoder = Decoder::new(opcodes());
+        let tick = actor_control(CONTROL_HOT_DOT, [0x74f, TICK_HOT, 1_000, 0]);
+        let frame = frame(&[segment(0x4000_0001, ACTOR_CONTROL, &tick)], COMPRESSION_NONE);
+        // Split across payloads, after bytes that don't start a frame.
+        let mut stream = vec![0x17, 0x03, 0x52];
+        stream.extend_from_slice(&frame);
+        stream.extend_from_slice(&frame);
+        let (first, rest) = stream.split_at(30);
+        assert!(decoder.push(first).is_empty());
+        assert_eq!(decoder.push(rest).len(), 2);
+
+        let mut oodle = frame.clone();
+        oodle[33] = 2;
+        assert!(decoder.push(&oodle).is_empty());
+        assert_eq!(decoder.push(&frame).len(), 1);
+    }
+}
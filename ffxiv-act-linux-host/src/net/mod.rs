@@ -1,9 +1,14 @@
+pub mod decode;
+mod route;
+
 use std::io::prelude::*;
-use std::net::{TcpListener};
+use std::net::{TcpListener, IpAddr};
 
-use std::sync::mpsc;
+use std::sync::{mpsc, Arc};
+use std::sync::mpsc::Sender;
 
 use std::thread;
+use std::time::{Duration, Instant};
 
 use crate::pcap;
 use pcap::{Device, Capture};
@@ -11,44 +16,88 @@ use pcap::{Device, Capture};
 use etherparse::SlicedPacket;
 
 use crate::NetConfig;
+use crate::mem::Event;
+use crate::metrics::Metrics;
 use std::process::Command;
 
-pub fn start_packet_redirection(net_config: NetConfig, ffxiv: i32) -> bool {
-    let interface = net_config.interface;
-    let host_exclude = net_config.hostname_exclude;
-    let sender_opt = start_incoming_sync_host(net_config.bind_address);
+/// How often pcap's statistics are copied into the metrics.
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Captures the game server's traffic and passes it on to network-passthrough clients. When the
+/// config has opcodes, the traffic is also decoded and what happened is sent to each of `observers`.
+pub fn start_packet_redirection(net_config: NetConfig, ffxiv: i32, metrics: Arc<Metrics>, mut observers: Vec<Sender<Event>>) -> bool {
+    let (client_tx, client_rx) = mpsc::channel();
+    let sender_opt = start_incoming_sync_host(net_config.bind_address, client_tx);
     if let Some(sender) = sender_opt {
         if let Ok(device_list) = Device::list() {
+            let server_opt = get_server_address(ffxiv);
+            if server_opt.is_none() {
+                info!(target: "net", "FFXIV connection gone, stopping network-passthrough.");
+                return true;
+            }
+            let (server_ip, src_port) = server_opt.unwrap();
+
+            let interface_opt = net_config.interface.or_else(|| {
+                let interface = match server_ip {
+                    IpAddr::V4(server_ip) => route::interface_for(server_ip),
+                    IpAddr::V6(_) => None,
+                };
+                if let Some(interface) = interface.as_ref() {
+                    info!(target: "net", "FFXIV's connection to {} goes through {}", server_ip, interface);
+                }
+                interface
+            });
+            let interface = match interface_opt {
+                Some(interface) => interface,
+                None => {
+                    error!(target: "net", "Unable to tell which interface FFXIV's connection to {} uses. Set net_config.interface.", server_ip);
+                    return false;
+                }
+            };
             let device_opt = device_list.into_iter().filter(|d| d.name == interface).next();
             if device_opt.is_none() {
-                eprintln!("[NET] Unable to find device with name \"{}\"", interface);
+                error!(target: "net", "Unable to find device with name \"{}\"", interface);
                 return false;
             }
             let device = device_opt.unwrap();
-            println!("[NET] Attempting to capture on {}", device.name);
+            info!(target: "net", "Attempting to capture on {}", device.name);
 
             let cap = Capture::from_device(device).unwrap();
             let cap_res = cap.open();
             if cap_res.is_err() {
-                eprintln!("[NET] Unable to open device for network capture. Are you root?");
+                error!(target: "net", "Unable to open device for network capture. Are you root?");
                 return false;
             }
             let mut cap = cap_res.unwrap();
 
+            let mut decoder = net_config.opcodes.filter(|_| !observers.is_empty()).map(decode::Decoder::new);
+            let mut excluded = net_config.hostname_exclude.into_iter().collect::<Vec<_>>();
 
+            info!(target: "net", "Identified FFXIV Server port as {}, capturing traffic from that port.", src_port);
+            cap.filter(capture_filter(src_port, &excluded).as_str()).expect("[NET] Unable to apply filters");
+            info!(target: "net", "Setup pcap for network redirection");
+            let mut stats_taken = Instant::now();
+            'capture: loop {
 
-            let src_port_opt = get_src_port(ffxiv);
-            if src_port_opt.is_none() {
-                println!("[NET] FFXIV connection gone, stopping network-passthrough.");
-                return true;
-            }
-            let src_port = src_port_opt.unwrap();
-
+                if stats_taken.elapsed() >= STATS_INTERVAL {
+                    stats_taken = Instant::now();
+                    if let Ok(stats) = cap.stats() {
+                        metrics.pcap_received.set(stats.received as u64);
+                        metrics.pcap_dropped.set(stats.dropped as u64);
+                        metrics.pcap_if_dropped.set(stats.if_dropped as u64);
+                    }
+                }
 
-            println!("[NET] Identified FFXIV Server port as {}, capturing traffic from that port.", src_port);
-            cap.filter(format!("(src port {}) && (src host not {})", src_port, host_exclude).as_str()).expect("[NET] Unable to apply filters");
-            println!("[NET] Setup pcap for network redirection");
-            'capture: loop {
+                // Traffic from the clients we pass packets on to must not be captured again.
+                let new_clients = client_rx.try_iter()
+                    .map(|client: IpAddr| client.to_string())
+                    .filter(|client| !excluded.contains(client))
+                    .collect::<Vec<_>>();
+                if !new_clients.is_empty() {
+                    info!(target: "net", "Excluding {} from capture", new_clients.join(", "));
+                    excluded.extend(new_clients);
+                    cap.filter(capture_filter(src_port, &excluded).as_str()).expect("[NET] Unable to apply filters");
+                }
 
                 if let Ok(p) = cap.next() {
                     let data = p.data.to_vec();
@@ -59,35 +108,52 @@ pub fn start_packet_redirection(net_config: NetConfig, ffxiv: i32) -> bool {
                     if pref.len() == 0 {
                         continue;
                     } else {
+                        if let Some(decoder) = decoder.as_mut() {
+                            for event in decoder.push(pref) {
+                                observers.retain(|observer| observer.send(event.clone()).is_ok());
+                            }
+                        }
                         if let Err(_) = sender.send(pref.to_vec()) {
                             break 'capture;
                         }
                     }
                 } else {
-                    eprintln!("[NET] Unable to get next packet! Something may have gone wrong earlier.");
+                    error!(target: "net", "Unable to get next packet! Something may have gone wrong earlier.");
                     return false;
                 }
             }
             true
         } else {
-            eprintln!("[NET] Unable to lookup devices. Are you root?");
+            error!(target: "net", "Unable to lookup devices. Are you root?");
             false
         }
     } else {
-        eprintln!("[NET] Unable to start network sync host.");
+        error!(target: "net", "Unable to start network sync host.");
         false
     }
 }
 
-fn start_incoming_sync_host(bind_address: String) -> Option<mpsc::Sender<Vec<u8>>> {
+/// Captures packets from the game server's port, except those from `excluded` hosts.
+fn capture_filter(src_port: u16, excluded: &[String]) -> String {
+    let mut filter = format!("(src port {})", src_port);
+    for host in excluded {
+        filter.push_str(&format!(" && (src host not {})", host));
+    }
+    filter
+}
+
+/// Passes captured packets on to network-passthrough clients, sending the address of each client
+/// that connects to `clients`.
+fn start_incoming_sync_host(bind_address: String, clients: mpsc::Sender<IpAddr>) -> Option<mpsc::Sender<Vec<u8>>> {
     let (tx, rx) = mpsc::channel::<Vec<u8>>();
     if let Ok(tcp) = TcpListener::bind(&bind_address) {
-        println!("[NET] TCP network-passthrough socket bound to {}.", bind_address);
+        info!(target: "net", "TCP network-passthrough socket bound to {}.", bind_address);
         thread::spawn(move || {
             loop {
-                println!("[NET] Waiting for TCP client");
+                info!(target: "net", "Waiting for TCP client");
                 let (mut inc, from) = tcp.accept().expect("[NET] Unable to accept connection");
-                println!("[NET] TCP connection from {}", from);
+                info!(target: "net", "TCP connection from {}", from);
+                let _ = clients.send(from.ip());
                 // Clear prior packets
                 let mut iter = rx.try_iter();
                 while let Some(_) = iter.next() {}
@@ -95,7 +161,7 @@ fn start_incoming_sync_host(bind_address: String) -> Option<mpsc::Sender<Vec<u8>
                 // Send packets as received
                 'sync: for data in &rx {
                     if let Err(_) = inc.write(&data[..]) {
-                        println!("[NET] Client connection ending.");
+                        info!(target: "net", "Client connection ending.");
                         break 'sync;
                     }
                 }
@@ -103,25 +169,64 @@ fn start_incoming_sync_host(bind_address: String) -> Option<mpsc::Sender<Vec<u8>
         });
         Some(tx)
     } else {
-        eprintln!("[NET] Unable to bind socket on {}. Is another process using it?", bind_address);
+        error!(target: "net", "Unable to bind socket on {}. Is another process using it?", bind_address);
         None
     }
 
 }
 
+/// The names of the interfaces pcap can capture on, if they can be listed.
+pub fn interface_names() -> Option<Vec<String>> {
+    Device::list().ok().map(|devices| devices.into_iter().map(|device| device.name).collect())
+}
+
 pub fn get_src_port(pid: i32) -> Option<u16> {
-    use regex::Regex;
+    get_server_address(pid).map(|(_, port)| port)
+}
+
+/// The address and port of the game server FFXIV is connected to.
+fn get_server_address(pid: i32) -> Option<(IpAddr, u16)> {
     let output = Command::new("lsof")
         .arg("-i")
+        .arg("-n")
+        .arg("-P")
         .arg("-a")
         .arg("-p")
         .arg(format!("{}", pid))
         .output().expect("Unable to get lsof");
 
     let lsof = String::from_utf8(output.stdout).expect("Couldn't read lsof output");
-    let re = Regex::new(r":(\d+) \(ESTABLISHED\)").unwrap();
+    parse_server_address(&lsof)
+}
+
+fn parse_server_address(lsof: &str) -> Option<(IpAddr, u16)> {
+    use regex::Regex;
+    let re = Regex::new(r"->\[?([0-9a-fA-F.:]+?)\]?:(\d+) \(ESTABLISHED\)").unwrap();
     re
-        .captures_iter(lsof.as_str())
+        .captures_iter(lsof)
         .next()
-        .map(|cap| cap[1].parse::<u16>().expect("Couldn't parse port"))
+        .and_then(|cap| Some((cap[1].parse::<IpAddr>().ok()?, cap[2].parse::<u16>().ok()?)))
+}
+
+#[cfg(test)]
+mod net_tests {
+    use crate::net::*;
+
+    #[test]
+    fn filter_out_clients() {
+        assert_eq!(capture_filter(55006, &[]), "(src port 55006)");
+        assert_eq!(capture_filter(55006, &["kate-arch".to_string(), "192.168.122.40".to_string()]),
+            "(src port 55006) && (src host not kate-arch) && (src host not 192.168.122.40)");
+    }
+
+    #[test]
+    fn read_server_address() {
+        let lsof = "\
+COMMAND     PID USER   FD   TYPE DEVICE SIZE/OFF NODE NAME
+ffxiv_dx1 41235 kate   95u  IPv4 812345      0t0  TCP 192.168.1.20:50642->204.2.229.9:55006 (ESTABLISHED)
+";
+        assert_eq!(parse_server_address(lsof), Some(("204.2.229.9".parse().unwrap(), 55006)));
+        assert_eq!(parse_server_address("TCP [::1]:50642->[2001:db8::1]:55006 (ESTABLISHED)"), Some(("2001:db8::1".parse().unwrap(), 55006)));
+        assert_eq!(parse_server_address("TCP *:50642 (LISTEN)"), None);
+    }
 }
@@ -0,0 +1,56 @@
+use std::fs;
+use std::net::Ipv4Addr;
+
+/// Flag on routes that are in use.
+const RTF_UP: u32 = 0x1;
+
+/// The interface the kernel would send traffic for `address` out of, from the IPv4 routing table.
+pub fn interface_for(address: Ipv4Addr) -> Option<String> {
+    fs::read_to_string("/proc/net/route").ok()
+        .and_then(|table| find_route(&table, address))
+}
+
+/// Picks the most specific route for `address` out of a table in the format of `/proc/net/route`,
+/// preferring the lowest metric between routes that are equally specific.
+fn find_route(table: &str, address: Ipv4Addr) -> Option<String> {
+    let address = u32::from(address);
+    table.lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            // Addresses are written as the hex of their bytes in memory, i.e. little-endian.
+            let hex = |index: usize| fields.get(index).and_then(|field| u32::from_str_radix(field, 16).ok());
+            let (destination, flags, metric, mask) = (hex(1)?.swap_bytes(), hex(3)?, fields.get(6)?.parse::<u32>().ok()?, hex(7)?.swap_bytes());
+            if flags & RTF_UP != 0 && address & mask == destination & mask {
+                Some((fields[0], mask.count_ones(), metric))
+            } else {
+                None
+            }
+        })
+        .max_by(|a, b| a.1.cmp(&b.1).then(b.2.cmp(&a.2)))
+        .map(|(interface, _, _)| interface.to_string())
+}
+
+#[cfg(test)]
+mod route_tests {
+    use crate::net::route::*;
+
+    const TABLE: &str = "\
+Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+enp8s0\t00000000\t0101A8C0\t0003\t0\t0\t100\t00000000\t0\t0\t0
+wlp3s0\t00000000\t0100A8C0\t0003\t0\t0\t600\t00000000\t0\t0\t0
+enp8s0\t0001A8C0\t00000000\t0001\t0\t0\t100\t00FFFFFF\t0\t0\t0
+virbr0\t007AA8C0\t00000000\t0001\t0\t0\t0\t00FFFFFF\t0\t0\t0
+tun0\t0000000A\t00000000\t0000\t0\t0\t0\t000000FF\t0\t0\t0
+";
+
+    #[test]
+    fn most_specific_route() {
+        // Game servers are reached through the default route with the lowest metric.
+        assert_eq!(find_route(TABLE, Ipv4Addr::new(204, 2, 229, 9)), Some("enp8s0".to_string()));
+        assert_eq!(find_route(TABLE, Ipv4Addr::new(192, 168, 122, 40)), Some("virbr0".to_string()));
+        // Routes that aren't up are ignored.
+        assert_eq!(find_route(TABLE, Ipv4Addr::new(10, 0, 0, 1)), Some("enp8s0".to_string()));
+        assert_eq!(find_route("Iface\tDestination\n", Ipv4Addr::new(10, 0, 0, 1)), None);
+    }
+}
@@ -0,0 +1,364 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tungstenite::{Message, WebSocket};
+
+use crate::act;
+use crate::mem::{Combatant, Event};
+use crate::parser::{self, Parser, Reporter, Summary};
+
+/// Where the overlay WebSocket server listens. It is only started when the config has an
+/// `overlay` section.
+#[derive(Deserialize, Clone)]
+pub struct OverlayConfig {
+    pub bind_address: String,
+}
+
+/// How long a connection waits for a call from its overlay before sending it events again.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+/// How often `CombatData` is sent during an encounter, as OverlayPlugin sends it by default.
+const COMBAT_DATA_INTERVAL: Duration = Duration::from_secs(1);
+/// An encounter ends once no one has dealt damage for this long.
+const ENCOUNTER_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The game as overlays are told about it, for answering calls and new subscriptions.
+#[derive(Default)]
+struct State {
+    zone: u32,
+    player: Option<Arc<Combatant>>,
+    combatants: Arc<Vec<Arc<Combatant>>>,
+}
+
+/// An event message for the connections subscribed to `kind`.
+type Broadcast = Arc<(&'static str, String)>;
+
+struct Shared {
+    state: Mutex<State>,
+    connections: Mutex<Vec<Sender<Broadcast>>>,
+}
+
+/// Serves overlays written for OverlayPlugin's WebSocket API on `listener`, sending them the events
+/// given to the returned sender.
+pub fn start(listener: TcpListener) -> io::Result<Sender<Event>> {
+    info!(target: "overlay", "Serving overlays on ws://{}/ws", listener.local_addr()?);
+    let shared = Arc::new(Shared { state: Mutex::new(State::default()), connections: Mutex::new(Vec::new()) });
+    let (events_tx, events) = mpsc::channel();
+
+    let dispatch_shared = shared.clone();
+    thread::spawn(move || dispatch(events, dispatch_shared));
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let shared = shared.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = serve(stream, shared) {
+                            debug!(target: "overlay", "Overlay connection ended: {}", e);
+                        }
+                    });
+                },
+                Err(e) => warn!(target: "overlay", "Unable to accept an overlay connection: {}", e),
+            }
+        }
+    });
+    Ok(events_tx)
+}
+
+/// Keeps the state up to date and passes each event on to every connection, along with the
+/// encounter parsed from them.
+fn dispatch(events: Receiver<Event>, shared: Arc<Shared>) {
+    let mut roster = act::Roster::default();
+    let mut parser = Parser::new(ENCOUNTER_IDLE_TIMEOUT);
+    let mut reporter = Reporter::new(COMBAT_DATA_INTERVAL);
+    for event in events {
+        roster.apply(&event);
+        let now = Instant::now();
+        parser.apply(&event, now);
+        let mut broadcasts = Vec::new();
+        {
+            let mut state = shared.state.lock().unwrap();
+            match &event {
+                Event::ChangeZone(zone) => {
+                    state.zone = *zone;
+                    broadcasts.push(("ChangeZone", change_zone(*zone)));
+                },
+                Event::ChangePrimaryPlayer(player) => {
+                    state.player = Some(player.clone());
+                    broadcasts.push(("ChangePrimaryPlayer", change_primary_player(player)));
+                },
+                Event::Combatants(combatants) => state.combatants = combatants.clone(),
+                _ => {},
+            }
+        }
+        for line in act::log_lines(&event, chrono::Local::now(), &roster) {
+            broadcasts.push(("LogLine", json!({ "type": "LogLine", "rawLine": line.join("|"), "line": line }).to_string()));
+        }
+        if reporter.due(&parser, now) {
+            broadcasts.extend(parser.summary().map(|summary| ("CombatData", combat_data(&summary))));
+        }
+        let mut connections = shared.connections.lock().unwrap();
+        for broadcast in broadcasts {
+            let broadcast = Arc::new(broadcast);
+            connections.retain(|connection| connection.send(broadcast.clone()).is_ok());
+        }
+    }
+}
+
+fn change_zone(zone: u32) -> String {
+    json!({ "type": "ChangeZone", "zoneID": zone }).to_string()
+}
+
+fn change_primary_player(player: &Combatant) -> String {
+    json!({ "type": "ChangePrimaryPlayer", "charID": player.id, "charName": player.name() }).to_string()
+}
+
+/// The encounter as OverlayPlugin sends it, with every number as a string, as ACT formats them.
+/// Only players are listed, with their pets' numbers in theirs, and the zone's ID stands in for
+/// its name, which needs the game's data files. Fields that need more than the
+/// parser keeps, such as max hits and deaths, are left out.
+fn combat_data(summary: &Summary) -> String {
+    let duration = format!("{:02}:{:02}", summary.duration_s as u64 / 60, summary.duration_s as u64 % 60);
+    let percent = |part: u64, whole: u64| if whole == 0 { "0%".to_string() } else { format!("{:.0}%", part as f64 * 100.0 / whole as f64) };
+    let players = summary.combatants.iter().filter(|combatant| combatant.player);
+    let combatants = players.clone().map(|combatant| {
+        let job = parser::job_name(combatant.job);
+        let job = format!("{}{}", &job[..1], job[1..].to_lowercase());
+        let hits = combatant.abilities.iter().map(|ability| ability.hits).sum::<u64>();
+        (combatant.name.clone(), json!({
+            "name": combatant.name,
+            "Job": job,
+            "duration": duration,
+            "DURATION": format!("{:.0}", summary.duration_s),
+            "damage": combatant.damage.to_string(),
+            "damage%": percent(combatant.damage, summary.damage),
+            "dps": format!("{:.0}", combatant.dps),
+            "DPS": format!("{:.2}", combatant.dps),
+            "encdps": format!("{:.0}", combatant.dps),
+            "ENCDPS": format!("{:.2}", combatant.dps),
+            "healed": combatant.healing.to_string(),
+            "healed%": percent(combatant.healing, summary.healing),
+            "enchps": format!("{:.0}", combatant.hps),
+            "ENCHPS": format!("{:.2}", combatant.hps),
+            "damagetaken": combatant.damage_taken.to_string(),
+            "hits": hits.to_string(),
+        }))
+    }).collect::<serde_json::Map<_, _>>();
+    let damage_taken = players.map(|combatant| combatant.damage_taken).sum::<u64>();
+    json!({
+        "type": "CombatData",
+        "Encounter": {
+            "title": "Encounter",
+            "duration": duration,
+            "DURATION": format!("{:.0}", summary.duration_s),
+            "damage": summary.damage.to_string(),
+            "dps": format!("{:.0}", summary.dps),
+            "DPS": format!("{:.2}", summary.dps),
+            "encdps": format!("{:.0}", summary.dps),
+            "ENCDPS": format!("{:.2}", summary.dps),
+            "healed": summary.healing.to_string(),
+            "enchps": format!("{:.0}", summary.hps),
+            "ENCHPS": format!("{:.2}", summary.hps),
+            "damagetaken": damage_taken.to_string(),
+            "CurrentZoneName": format!("{:X}", summary.zone),
+        },
+        "Combatant": combatants,
+        "isActive": summary.active.to_string(),
+    }).to_string()
+}
+
+/// Talks to one overlay until it disconnects, answering its calls and sending it the events it has
+/// subscribed to.
+fn serve(stream: TcpStream, shared: Arc<Shared>) -> Result<(), Box<dyn Error>> {
+    let mut socket: WebSocket<TcpStream> = tungstenite::accept(stream).map_err(|e| e.to_string())?;
+    socket.get_ref().set_read_timeout(Some(POLL_INTERVAL))?;
+    let (broadcasts_tx, broadcasts) = mpsc::channel();
+    shared.connections.lock().unwrap().push(broadcasts_tx);
+    let mut subscriptions = HashSet::new();
+    loop {
+        for broadcast in broadcasts.try_iter() {
+            if subscriptions.contains(broadcast.0) {
+                socket.send(Message::Text(broadcast.1.clone()))?;
+            }
+        }
+        match socket.read() {
+            Ok(Message::Text(text)) => {
+                let replies = {
+                    let state = shared.state.lock().unwrap();
+                    handle_call(&text, &state, &mut subscriptions)
+                };
+                for reply in replies {
+                    socket.send(Message::Text(reply))?;
+                }
+            },
+            Ok(_) => {},
+            Err(tungstenite::Error::Io(ref e)) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {},
+            Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Handles a call from an overlay, `{"call": ..., "rseq": ...}`, returning the messages to send
+/// back. Calls other than subscribing and `getCombatants` aren't supported and are answered
+/// with nothing but their `rseq`, so overlays waiting on them carry on.
+fn handle_call(text: &str, state: &State, subscriptions: &mut HashSet<String>) -> Vec<String> {
+    let call: Value = match serde_json::from_str(text) {
+        Ok(call) => call,
+        Err(e) => {
+            debug!(target: "overlay", "Ignoring a message that isn't JSON: {}", e);
+            return Vec::new();
+        },
+    };
+    let names = |key: &str| call[key].as_array().map(|names| names.iter().filter_map(Value::as_str).map(str::to_string).collect::<Vec<_>>()).unwrap_or_default();
+    let mut replies = Vec::new();
+    match call["call"].as_str() {
+        Some("subscribe") => {
+            for event in names("events") {
+                // Overlays are told where things stand as soon as they ask.
+                match event.as_str() {
+                    "ChangeZone" if state.zone != 0 => replies.push(change_zone(state.zone)),
+                    "ChangePrimaryPlayer" => replies.extend(state.player.as_ref().map(|player| change_primary_player(player))),
+                    _ => {},
+                }
+                subscriptions.insert(event);
+            }
+        },
+        Some("unsubscribe") => {
+            for event in names("events") {
+                subscriptions.remove(&event);
+            }
+        },
+        Some("getCombatants") => {
+            let ids = call["ids"].as_array().map(|ids| ids.iter().filter_map(Value::as_u64).collect::<Vec<_>>()).unwrap_or_default();
+            let names = names("names");
+            let combatants = state.combatants.iter()
+                .filter(|combatant| ids.is_empty() || ids.contains(&(combatant.id as u64)))
+                .filter(|combatant| names.is_empty() || names.contains(&combatant.name()))
+                .map(|combatant| combatant_json(combatant))
+                .collect::<Vec<_>>();
+            replies.push(json!({ "rseq": call["rseq"], "combatants": combatants }).to_string());
+        },
+        other => {
+            debug!(target: "overlay", "Unsupported overlay call {:?}", other);
+            if !call["rseq"].is_null() {
+                replies.push(json!({ "rseq": call["rseq"] }).to_string());
+            }
+        },
+    }
+    replies
+}
+
+/// A combatant as OverlayPlugin describes them.
+fn combatant_json(combatant: &Combatant) -> Value {
+    json!({
+        "ID": combatant.id,
+        "OwnerID": combatant.ownerid,
+        "Type": combatant.tipe,
+        "TargetID": combatant.pctargetid,
+        "Job": combatant.job,
+        "Level": combatant.level,
+        "Name": combatant.name(),
+        "CurrentHP": combatant.current_hp,
+        "MaxHP": combatant.max_hp,
+        "CurrentMP": combatant.current_mp,
+        "MaxMP": combatant.max_mp,
+        "CurrentCP": combatant.current_cp,
+        "MaxCP": combatant.max_cp,
+        "CurrentGP": combatant.current_gp,
+        "MaxGP": combatant.max_gp,
+        "WorldID": combatant.home_world_id,
+        "CurrentWorldID": combatant.current_world_id,
+        "BNpcID": combatant.bnpcid,
+        "BNpcNameID": combatant.bnpcnameid,
+        "PosX": combatant.pos_x,
+        "PosY": combatant.pos_y,
+        "PosZ": combatant.pos_z,
+        "Heading": combatant.heading,
+    })
+}
+
+#[cfg(test)]
+mod overlay_tests {
+    use crate::overlay::*;
+    use crate::net::decode::{Action, ActionTarget, Effect};
+
+    fn combatant(id: u32, name: &str) -> Arc<Combatant> {
+        Arc::new(Combatant::named(id, name))
+    }
+
+    #[test]
+    fn answer_calls() {
+        let player = combatant(0x1040_2A3B, "Kate Arch");
+        let state = State {
+            zone: 132,
+            player: Some(player.clone()),
+            combatants: Arc::new(vec![player, combatant(0x4000_0001, "Striking Dummy")]),
+        };
+        let mut subscriptions = HashSet::new();
+
+        let replies = handle_call(r#"{"call": "subscribe", "events": ["ChangeZone", "ChangePrimaryPlayer", "LogLine"]}"#, &state, &mut subscriptions);
+        assert_eq!(replies, vec![
+            r#"{"type":"ChangeZone","zoneID":132}"#.to_string(),
+            r#"{"charID":272640571,"charName":"Kate Arch","type":"ChangePrimaryPlayer"}"#.to_string(),
+        ]);
+        assert_eq!(subscriptions.len(), 3);
+        assert!(handle_call(r#"{"call": "unsubscribe", "events": ["LogLine"]}"#, &state, &mut subscriptions).is_empty());
+        assert!(!subscriptions.contains("LogLine"));
+
+        let replies = handle_call(r#"{"call": "getCombatants", "names": ["Striking Dummy"], "rseq": 4}"#, &state, &mut subscriptions);
+        let reply: Value = serde_json::from_str(&replies[0]).unwrap();
+        assert_eq!(reply["rseq"], 4);
+        assert_eq!(reply["combatants"].as_array().unwrap().len(), 1);
+        assert_eq!(reply["combatants"][0]["ID"], 0x4000_0001);
+        let reply: Value = serde_json::from_str(&handle_call(r#"{"call": "getCombatants", "rseq": 5}"#, &state, &mut subscriptions)[0]).unwrap();
+        assert_eq!(reply["combatants"].as_array().unwrap().len(), 2);
+
+        assert_eq!(handle_call(r#"{"call": "saveData", "rseq": 6}"#, &state, &mut subscriptions), vec![r#"{"rseq":6}"#.to_string()]);
+        assert!(handle_call("not json", &state, &mut subscriptions).is_empty());
+    }
+
+    #[test]
+    fn send_subscribed_events() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let events = start(listener).unwrap();
+
+        let (mut socket, _) = tungstenite::connect(format!("ws://{}/ws", address)).unwrap();
+        socket.send(Message::Text(r#"{"call": "subscribe", "events": ["ChangeZone", "CombatData"]}"#.to_string())).unwrap();
+        // Calls are answered in order, so once this one is the subscription has been made.
+        let _ = socket.send(Message::Text(r#"{"call": "getCombatants", "rseq": 1}"#.to_string()));
+        assert_eq!(socket.read().unwrap(), Message::Text(r#"{"combatants":[],"rseq":1}"#.to_string()));
+
+        events.send(Event::AddCombatant(combatant(1, "Ifrit"))).unwrap();
+        events.send(Event::ChangeZone(0x84)).unwrap();
+        assert_eq!(socket.read().unwrap(), Message::Text(r#"{"type":"ChangeZone","zoneID":132}"#.to_string()));
+
+        let mut player = Combatant::named(0x1040_2A3B, "Kate Arch");
+        player.tipe = 1;
+        player.job = 28;
+        events.send(Event::Combatants(Arc::new(vec![Arc::new(player), combatant(0x4000_0001, "Striking Dummy")]))).unwrap();
+        let hit = Effect { kind: 3, params: [0; 3], high_byte: 0, flags: 0, value: 12_000 };
+        let targets = vec![ActionTarget { id: 0x4000_0001, effects: vec![hit] }];
+        events.send(Event::Action(Arc::new(Action { source: 0x1040_2A3B, id: 0x4099, sequence: 1, aoe: false, targets }))).unwrap();
+        let combat_data: Value = match socket.read().unwrap() {
+            Message::Text(text) => serde_json::from_str(&text).unwrap(),
+            other => panic!("expected CombatData, got {:?}", other),
+        };
+        assert_eq!(combat_data["type"], "CombatData");
+        assert_eq!(combat_data["isActive"], "true");
+        assert_eq!(combat_data["Encounter"]["damage"], "12000");
+        assert_eq!(combat_data["Encounter"]["ENCDPS"], "12000.00");
+        assert_eq!(combat_data["Encounter"]["CurrentZoneName"], "84");
+        let kate = &combat_data["Combatant"]["Kate Arch"];
+        assert_eq!((kate["Job"].as_str(), kate["damage"].as_str(), kate["damage%"].as_str(), kate["hits"].as_str()), (Some("Sch"), Some("12000"), Some("100%"), Some("1")));
+        assert!(combat_data["Combatant"]["Striking Dummy"].is_null());
+    }
+}
@@ -0,0 +1,515 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::act::Roster;
+use crate::mem::Event;
+use crate::net::decode::{Action, Tick};
+
+/// How the encounter parser reports. It only runs when the config has a `parser` section.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct ParserConfig {
+    /// An encounter ends once no one has dealt damage for this long.
+    pub idle_timeout_s: u64,
+    /// Print the encounter as a table every `interval_s` while it goes on, and once when it ends.
+    pub table: bool,
+    /// A file to keep the encounter's summary in as JSON, rewritten as often as the table is
+    /// printed.
+    pub json_file: Option<PathBuf>,
+    pub interval_s: u64,
+}
+
+impl Default for ParserConfig {
+    fn default() -> ParserConfig {
+        ParserConfig { idle_timeout_s: 10, table: true, json_file: None, interval_s: 5 }
+    }
+}
+
+/// The game's type for player characters.
+const PLAYER_TYPE: u8 = 1;
+
+/// The abbreviation ACT shows for each of the game's class and job IDs.
+const JOBS: [&str; 43] = [
+    "ADV", "GLA", "PGL", "MRD", "LNC", "ARC", "CNJ", "THM", "CRP", "BSM", "ARM", "GSM", "LTW", "WVR", "ALC", "CUL", "MIN",
+    "BTN", "FSH", "PLD", "MNK", "WAR", "DRG", "BRD", "WHM", "BLM", "ACN", "SMN", "SCH", "ROG", "NIN", "MCH", "DRK", "AST",
+    "SAM", "RDM", "BLU", "GNB", "DNC", "RPR", "SGE", "VPR", "PCT",
+];
+
+/// A job's abbreviation, or its ID if it is newer than the host.
+pub fn job_name(job: u8) -> String {
+    JOBS.get(job as usize).map(|name| name.to_string()).unwrap_or_else(|| job.to_string())
+}
+
+/// What damage or healing came from: an action, or a status ticking over time. The game's data
+/// files would name them, and the host doesn't read those, so they are told apart by ID as ACT
+/// does for abilities it doesn't know.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Ability {
+    Action(u32),
+    DamageOverTime(u32),
+    HealingOverTime(u32),
+}
+
+impl Ability {
+    fn label(&self) -> String {
+        match self {
+            Ability::Action(id) => format!("{:X}", id),
+            Ability::DamageOverTime(status) => format!("DoT {:X}", status),
+            Ability::HealingOverTime(status) => format!("HoT {:X}", status),
+        }
+    }
+}
+
+/// Finds encounters in the actions and ticks decoded from the network stream and adds up who
+/// dealt and took what damage and healing, with which ability. Pets' numbers go to their owner,
+/// as ACT shows them by default, and damage over time without a source in the tick is credited to
+/// whoever last put that status on the target.
+pub struct Parser {
+    idle_timeout: Duration,
+    roster: Roster,
+    zone: u32,
+    /// Who applied each status each combatant has, by target and status.
+    statuses: HashMap<(u32, u32), u32>,
+    encounter: Option<Encounter>,
+}
+
+struct Encounter {
+    zone: u32,
+    started: Instant,
+    last_damage: Instant,
+    active: bool,
+    actors: BTreeMap<u32, Actor>,
+}
+
+#[derive(Default)]
+struct Actor {
+    name: String,
+    job: u8,
+    player: bool,
+    damage: u64,
+    healing: u64,
+    damage_taken: u64,
+    abilities: BTreeMap<Ability, AbilityTotals>,
+}
+
+#[derive(Default)]
+struct AbilityTotals {
+    hits: u64,
+    damage: u64,
+    healing: u64,
+}
+
+/// What an encounter came to, as written to the JSON file.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Summary {
+    pub zone: u32,
+    pub active: bool,
+    pub duration_s: f64,
+    /// Damage dealt by players.
+    pub damage: u64,
+    pub dps: f64,
+    /// Healing done by players.
+    pub healing: u64,
+    pub hps: f64,
+    pub combatants: Vec<CombatantSummary>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct CombatantSummary {
+    pub id: u32,
+    pub name: String,
+    pub job: u8,
+    pub player: bool,
+    pub damage: u64,
+    pub dps: f64,
+    pub healing: u64,
+    pub hps: f64,
+    pub damage_taken: u64,
+    pub dtps: f64,
+    /// Most damage first, then most healing.
+    pub abilities: Vec<AbilitySummary>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct AbilitySummary {
+    /// The action's ID in hex, or `DoT`/`HoT` and the status's ID for ticks.
+    pub ability: String,
+    pub hits: u64,
+    pub damage: u64,
+    pub healing: u64,
+}
+
+impl Parser {
+    pub fn new(idle_timeout: Duration) -> Parser {
+        Parser { idle_timeout, roster: Roster::default(), zone: 0, statuses: HashMap::new(), encounter: None }
+    }
+
+    pub fn apply(&mut self, event: &Event, now: Instant) {
+        self.roster.apply(event);
+        match event {
+            Event::ChangeZone(zone) => {
+                self.zone = *zone;
+                self.statuses.clear();
+                self.end();
+            },
+            Event::Action(action) => self.action(action, now),
+            Event::Tick(tick) => self.tick(tick, now),
+            Event::EffectResult(result) => {
+                for status in result.statuses.iter().filter(|status| status.source != 0) {
+                    self.statuses.insert((result.target, status.id as u32), status.source);
+                }
+            },
+            _ => {},
+        }
+        let idle = self.encounter.as_ref().map(|encounter| encounter.active && now.duration_since(encounter.last_damage) >= self.idle_timeout);
+        if idle == Some(true) {
+            self.end();
+        }
+    }
+
+    fn action(&mut self, action: &Action, now: Instant) {
+        for target in &action.targets {
+            for effect in &target.effects {
+                let receiver = if effect.on_source() { action.source } else { target.id };
+                if effect.is_damage() {
+                    self.damage(action.source, receiver, Ability::Action(action.id), effect.amount(), now);
+                } else if effect.is_heal() {
+                    self.heal(action.source, receiver, Ability::Action(action.id), effect.amount());
+                }
+            }
+        }
+    }
+
+    fn tick(&mut self, tick: &Tick, now: Instant) {
+        let source = match tick.source {
+            0 => self.statuses.get(&(tick.target, tick.status)).copied().unwrap_or(0),
+            source => source,
+        };
+        if tick.heal {
+            self.heal(source, tick.target, Ability::HealingOverTime(tick.status), tick.amount);
+        } else {
+            self.damage(source, tick.target, Ability::DamageOverTime(tick.status), tick.amount, now);
+        }
+    }
+
+    fn damage(&mut self, source: u32, target: u32, ability: Ability, amount: u32, now: Instant) {
+        let zone = self.zone;
+        let encounter = match self.encounter.as_mut() {
+            Some(encounter) if encounter.active => encounter,
+            _ => {
+                info!(target: "parser", "Encounter started");
+                self.encounter.insert(Encounter { zone, started: now, last_damage: now, active: true, actors: BTreeMap::new() })
+            },
+        };
+        encounter.last_damage = now;
+        encounter.actor(&self.roster, target).damage_taken += amount as u64;
+        if source != 0 {
+            let actor = encounter.actor(&self.roster, owner(&self.roster, source));
+            actor.damage += amount as u64;
+            let totals = actor.abilities.entry(ability).or_default();
+            totals.hits += 1;
+            totals.damage += amount as u64;
+        }
+    }
+
+    /// Healing outside an encounter, e.g. topping up after a pull, isn't counted.
+    fn heal(&mut self, source: u32, target: u32, ability: Ability, amount: u32) {
+        let encounter = match self.encounter.as_mut() {
+            Some(encounter) if encounter.active && source != 0 => encounter,
+            _ => return,
+        };
+        encounter.actor(&self.roster, target);
+        let actor = encounter.actor(&self.roster, owner(&self.roster, source));
+        actor.healing += amount as u64;
+        let totals = actor.abilities.entry(ability).or_default();
+        totals.hits += 1;
+        totals.healing += amount as u64;
+    }
+
+    fn end(&mut self) {
+        if let Some(encounter) = self.encounter.as_mut().filter(|encounter| encounter.active) {
+            encounter.active = false;
+            info!(target: "parser", "Encounter ended after {:.0} s", encounter.duration().as_secs_f64());
+        }
+    }
+
+    /// The current encounter, or the last one once it has ended.
+    pub fn summary(&self) -> Option<Summary> {
+        self.encounter.as_ref().map(Encounter::summary)
+    }
+
+    fn active(&self) -> bool {
+        self.encounter.as_ref().is_some_and(|encounter| encounter.active)
+    }
+}
+
+/// The combatant whose numbers `id`'s go to: its owner if it is a pet, otherwise itself.
+fn owner(roster: &Roster, id: u32) -> u32 {
+    match roster.get(id) {
+        Some(combatant) if combatant.ownerid != 0 && roster.get(combatant.ownerid).is_some() => combatant.ownerid,
+        _ => id,
+    }
+}
+
+/// Says when to report on the encounter: every `interval` while it goes on, and once more as it
+/// ends.
+pub struct Reporter {
+    interval: Duration,
+    reported: Option<Instant>,
+    was_active: bool,
+}
+
+impl Reporter {
+    pub fn new(interval: Duration) -> Reporter {
+        Reporter { interval, reported: None, was_active: false }
+    }
+
+    pub fn due(&mut self, parser: &Parser, now: Instant) -> bool {
+        let active = parser.active();
+        let ended = self.was_active && !active;
+        self.was_active = active;
+        let interval_passed = match self.reported {
+            Some(reported) => now.duration_since(reported) >= self.interval,
+            None => true,
+        };
+        let due = ended || (active && interval_passed);
+        if due {
+            self.reported = Some(now);
+        }
+        due
+    }
+}
+
+impl Encounter {
+    /// The actor for combatant `id`, named from `roster` when it is first seen, as it may have
+    /// left by the time the encounter is summed up.
+    fn actor(&mut self, roster: &Roster, id: u32) -> &mut Actor {
+        self.actors.entry(id).or_insert_with(|| match roster.get(id) {
+            Some(combatant) => Actor { name: combatant.name(), job: combatant.job, player: combatant.tipe == PLAYER_TYPE, ..Default::default() },
+            None => Actor { name: format!("{:08X}", id), ..Default::default() },
+        })
+    }
+
+    /// From the first damage to the last, as ACT counts it, and at least a second.
+    fn duration(&self) -> Duration {
+        self.last_damage.duration_since(self.started).max(Duration::from_secs(1))
+    }
+
+    fn summary(&self) -> Summary {
+        let seconds = self.duration().as_secs_f64();
+        let players = self.actors.values().filter(|actor| actor.player);
+        let damage = players.clone().map(|actor| actor.damage).sum::<u64>();
+        let healing = players.map(|actor| actor.healing).sum::<u64>();
+        let mut combatants = self.actors.iter()
+            .map(|(id, actor)| {
+                let mut abilities = actor.abilities.iter()
+                    .map(|(ability, totals)| AbilitySummary { ability: ability.label(), hits: totals.hits, damage: totals.damage, healing: totals.healing })
+                    .collect::<Vec<_>>();
+                abilities.sort_by_key(|ability| (std::cmp::Reverse(ability.damage), std::cmp::Reverse(ability.healing)));
+                CombatantSummary {
+                    id: *id,
+                    name: actor.name.clone(),
+                    job: actor.job,
+                    player: actor.player,
+                    damage: actor.damage,
+                    dps: actor.damage as f64 / seconds,
+                    healing: actor.healing,
+                    hps: actor.healing as f64 / seconds,
+                    damage_taken: actor.damage_taken,
+                    dtps: actor.damage_taken as f64 / seconds,
+                    abilities,
+                }
+            })
+            .collect::<Vec<_>>();
+        // Players first, then whoever dealt the most.
+        combatants.sort_by_key(|combatant| (!combatant.player, std::cmp::Reverse(combatant.damage), std::cmp::Reverse(combatant.damage_taken)));
+        Summary {
+            zone: self.zone,
+            active: self.active,
+            duration_s: seconds,
+            damage,
+            dps: damage as f64 / seconds,
+            healing,
+            hps: healing as f64 / seconds,
+            combatants,
+        }
+    }
+}
+
+impl Summary {
+    /// The summary as a table for the terminal, with each player's abilities under them.
+    pub fn table(&self) -> String {
+        let seconds = self.duration_s as u64;
+        let mut table = format!("Encounter in zone {:X}, {:02}:{:02}{}: {} damage ({:.1} DPS), {} healing ({:.1} HPS)\n",
+            self.zone, seconds / 60, seconds % 60, if self.active { "" } else { " (ended)" }, self.damage, self.dps, self.healing, self.hps);
+        table.push_str(&format!("{:<24} {:>4} {:>10} {:>10} {:>10} {:>10} {:>12}\n", "Name", "Job", "Damage", "DPS", "Healing", "HPS", "Damage taken"));
+        for combatant in &self.combatants {
+            let job = if combatant.player { job_name(combatant.job) } else { String::new() };
+            table.push_str(&format!("{:<24} {:>4} {:>10} {:>10.1} {:>10} {:>10.1} {:>12}\n",
+                combatant.name, job, combatant.damage, combatant.dps, combatant.healing, combatant.hps, combatant.damage_taken));
+            if !combatant.player {
+                continue;
+            }
+            for ability in &combatant.abilities {
+                let name = format!("  {} ({} {})", ability.ability, ability.hits, if ability.hits == 1 { "hit" } else { "hits" });
+                table.push_str(&format!("{:<24} {:>4} {:>10} {:>10.1} {:>10} {:>10.1}\n",
+                    name, "", ability.damage, ability.damage as f64 / self.duration_s, ability.healing, ability.healing as f64 / self.duration_s));
+            }
+        }
+        table
+    }
+}
+
+/// Runs a parser on the events given to the returned sender, reporting as `config` says.
+pub fn start(config: ParserConfig) -> Sender<Event> {
+    let (events_tx, events) = mpsc::channel();
+    thread::spawn(move || {
+        let mut parser = Parser::new(Duration::from_secs(config.idle_timeout_s));
+        let mut reporter = Reporter::new(Duration::from_secs(config.interval_s));
+        for event in events {
+            let now = Instant::now();
+            parser.apply(&event, now);
+            if !reporter.due(&parser, now) {
+                continue;
+            }
+            if let Some(summary) = parser.summary() {
+                if config.table {
+                    println!("{}", summary.table());
+                }
+                if let Some(json_file) = config.json_file.as_ref() {
+                    if let Err(e) = write_json(json_file, &summary) {
+                        error!(target: "parser", "Unable to write {}: {}", json_file.display(), e);
+                    }
+                }
+            }
+        }
+    });
+    events_tx
+}
+
+/// Checks that the JSON file can be written, without writing it.
+pub fn check_json_file(path: &Path) -> Result<(), String> {
+    let directory = path.parent().filter(|directory| !directory.as_os_str().is_empty()).unwrap_or_else(|| Path::new("."));
+    match fs::metadata(directory) {
+        Ok(metadata) if !metadata.is_dir() => Err(format!("{} isn't a directory", directory.display())),
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("{}: {}", directory.display(), e)),
+    }
+}
+
+/// Replaces the file in one step, so nothing reading it sees half a summary.
+fn write_json(path: &Path, summary: &Summary) -> io::Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    fs::write(&temporary, serde_json::to_vec_pretty(summary)?)?;
+    fs::rename(&temporary, path)
+}
+
+#[cfg(test)]
+mod parser_tests {
+    use crate::parser::*;
+    use crate::mem::Combatant;
+    use crate::net::decode::{ActionTarget, Effect, EffectResult, Status};
+    use std::sync::Arc;
+
+    const KATE: u32 = 0x1040_2A3B;
+    const FAIRY: u32 = 0x4000_0010;
+    const DUMMY: u32 = 0x4000_0001;
+
+    fn combatants() -> Event {
+        let mut kate = Combatant::named(KATE, "Kate Arch");
+        kate.tipe = PLAYER_TYPE;
+        kate.job = 28;
+        let mut fairy = Combatant::named(FAIRY, "Eos");
+        fairy.ownerid = KATE;
+        let dummy = Combatant::named(DUMMY, "Striking Dummy");
+        Event::Combatants(Arc::new(vec![Arc::new(kate), Arc::new(fairy), Arc::new(dummy)]))
+    }
+
+    fn action(source: u32, id: u32, targets: &[(u32, u8, u32)]) -> Event {
+        let targets = targets.iter().map(|(target, kind, amount)| ActionTarget {
+            id: *target,
+            effects: vec![Effect { kind: *kind, params: [0; 3], high_byte: 0, flags: 0, value: *amount as u16 }],
+        }).collect();
+        Event::Action(Arc::new(Action { source, id, sequence: 1, aoe: false, targets }))
+    }
+
+    #[test]
+    fn parse_encounter() {
+        let start = Instant::now();
+        let at = |seconds: u64| start + Duration::from_secs(seconds);
+        let mut parser = Parser::new(Duration::from_secs(10));
+        parser.apply(&Event::ChangeZone(0x84), start);
+        parser.apply(&combatants(), start);
+        // Healing before anyone is hit doesn't start an encounter.
+        parser.apply(&action(KATE, 0xb9, &[(KATE, 4, 5_000)]), at(0));
+        assert_eq!(parser.summary(), None);
+
+        parser.apply(&action(KATE, 0x4099, &[(DUMMY, 3, 10_000)]), at(1));
+        parser.apply(&Event::EffectResult(Arc::new(EffectResult { target: DUMMY, current_hp: 0, max_hp: 0,
+            statuses: vec![Status { id: 0x767, param: 0, duration: 30.0, source: KATE }] })), at(1));
+        parser.apply(&action(DUMMY, 0x7, &[(KATE, 3, 4_000)]), at(2));
+        parser.apply(&action(FAIRY, 0x4086, &[(KATE, 4, 3_000)]), at(3));
+        // The tick doesn't say who the DoT is from; the status does.
+        parser.apply(&Event::Tick(Tick { target: DUMMY, source: 0, status: 0x767, heal: false, amount: 2_000 }), at(4));
+        parser.apply(&action(KATE, 0x4099, &[(DUMMY, 3, 8_000)]), at(5));
+        let summary = parser.summary().unwrap();
+        assert!(summary.active);
+        assert_eq!((summary.duration_s, summary.damage, summary.dps, summary.healing, summary.hps), (4.0, 20_000, 5_000.0, 3_000, 750.0));
+        assert_eq!(summary.combatants.len(), 2);
+        assert_eq!(summary.combatants[0], CombatantSummary {
+            id: KATE, name: "Kate Arch".to_string(), job: 28, player: true,
+            damage: 20_000, dps: 5_000.0, healing: 3_000, hps: 750.0, damage_taken: 4_000, dtps: 1_000.0,
+            abilities: vec![
+                AbilitySummary { ability: "4099".to_string(), hits: 2, damage: 18_000, healing: 0 },
+                AbilitySummary { ability: "DoT 767".to_string(), hits: 1, damage: 2_000, healing: 0 },
+                AbilitySummary { ability: "4086".to_string(), hits: 1, damage: 0, healing: 3_000 },
+            ],
+        });
+        assert_eq!((summary.combatants[1].name.as_str(), summary.combatants[1].damage, summary.combatants[1].damage_taken), ("Striking Dummy", 4_000, 20_000));
+
+        parser.apply(&Event::Combatants(Arc::new(Vec::new())), at(14));
+        assert!(parser.summary().unwrap().active);
+        parser.apply(&Event::Combatants(Arc::new(Vec::new())), at(15));
+        let summary = parser.summary().unwrap();
+        assert!(!summary.active);
+        let table = summary.table();
+        assert!(table.starts_with("Encounter in zone 84, 00:04 (ended): 20000 damage (5000.0 DPS), 3000 healing (750.0 HPS)\n"));
+        assert!(table.contains("\nKate Arch                 SCH      20000     5000.0       3000      750.0         4000\n"));
+        assert!(table.contains("\n  4099 (2 hits)                    18000     4500.0          0        0.0\n  DoT 767 (1 hit)                   2000      500.0          0        0.0\n"));
+
+        // The next damage starts a new encounter, naming those who have gone by their ID.
+        parser.apply(&action(0x1040_0001, 0x4099, &[(DUMMY, 3, 1_000)]), at(20));
+        let summary = parser.summary().unwrap();
+        assert!(summary.active);
+        assert_eq!((summary.damage, summary.combatants.len()), (0, 2));
+        assert_eq!(summary.combatants[0].name, "10400001");
+
+        assert_eq!((job_name(24), job_name(200)), ("WHM".to_string(), "200".to_string()));
+        assert_eq!(check_json_file(Path::new("encounter.json")), Ok(()));
+        assert_eq!(check_json_file(Path::new("/proc/version/encounter.json")), Err("/proc/version isn't a directory".to_string()));
+    }
+
+    #[test]
+    fn report_during_and_after_encounters() {
+        let start = Instant::now();
+        let at = |seconds: u64| start + Duration::from_secs(seconds);
+        let mut parser = Parser::new(Duration::from_secs(10));
+        let mut reporter = Reporter::new(Duration::from_secs(5));
+        parser.apply(&combatants(), at(0));
+        assert!(!reporter.due(&parser, at(0)));
+        parser.apply(&action(KATE, 0x4099, &[(DUMMY, 3, 1_000)]), at(1));
+        let due = (1..=12).map(|second| {
+            parser.apply(&combatants(), at(second));
+            reporter.due(&parser, at(second))
+        });
+        assert_eq!(due.collect::<Vec<_>>(), vec![true, false, false, false, false, true, false, false, false, false, true, false]);
+    }
+}
@@ -45,4 +45,12 @@ pub fn find_ffxiv() -> Option<i32> {
         None
     }
 
-}
\ No newline at end of file
+}
+
+/// Finds FFXIV, or checks that the process given with `--pid` is still running.
+pub fn find_ffxiv_or(pid: Option<i32>) -> Option<i32> {
+    match pid {
+        Some(pid) => Some(pid).filter(|pid| std::path::Path::new(&format!("/proc/{}", pid)).exists()),
+        None => find_ffxiv(),
+    }
+}
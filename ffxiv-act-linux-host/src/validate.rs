@@ -0,0 +1,195 @@
+use std::fs::File;
+use std::net::{TcpListener, ToSocketAddrs, UdpSocket};
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::{Config, NetConfig, MemConfig};
+use ffxiv_act_linux_common::logging::LogConfig;
+use crate::metrics::MetricsConfig;
+use crate::overlay::OverlayConfig;
+use crate::act::network_log::{self, NetworkLogConfig};
+use crate::parser::{self, ParserConfig};
+
+/// Reads the config file, reporting every section that doesn't parse rather than just the first.
+pub fn read_config(path: &Path) -> Result<Config, Vec<String>> {
+    let file = File::open(path).map_err(|e| vec![format!("Couldn't open {}: {}", path.display(), e)])?;
+    let config: Value = serde_json::from_reader(file)
+        .map_err(|e| vec![format!("{} isn't valid JSON: {}", path.display(), e)])?;
+    let log = match config.get("log") {
+        Some(_) => section::<LogConfig>(&config, "log"),
+        None => Ok(LogConfig::default()),
+    };
+    let metrics = optional_section::<MetricsConfig>(&config, "metrics");
+    let overlay = optional_section::<OverlayConfig>(&config, "overlay");
+    let network_log = optional_section::<NetworkLogConfig>(&config, "network_log");
+    let parser = optional_section::<ParserConfig>(&config, "parser");
+    match (section::<NetConfig>(&config, "net_config"), section::<MemConfig>(&config, "mem_config"), log, metrics, overlay, network_log, parser) {
+        (Ok(net_config), Ok(mem_config), Ok(log), Ok(metrics), Ok(overlay), Ok(network_log), Ok(parser)) =>
+            Ok(Config { net_config, mem_config, log, metrics, overlay, network_log, parser }),
+        (net_config, mem_config, log, metrics, overlay, network_log, parser) => Err(net_config.err().into_iter().chain(mem_config.err()).chain(log.err())
+            .chain(metrics.err()).chain(overlay.err()).chain(network_log.err()).chain(parser.err()).collect()),
+    }
+}
+
+/// A section that turns something on when it is there.
+fn optional_section<T: DeserializeOwned>(config: &Value, name: &str) -> Result<Option<T>, String> {
+    match config.get(name) {
+        Some(_) => section(config, name).map(Some),
+        None => Ok(None),
+    }
+}
+
+fn section<T: DeserializeOwned>(config: &Value, name: &str) -> Result<T, String> {
+    match config.get(name) {
+        Some(section) => T::deserialize(section).map_err(|e| format!("{}: {}", name, e)),
+        None => Err(format!("{} is missing", name)),
+    }
+}
+
+/// Checks that the config can actually be used: that the capture interface, if set, is one of
+/// `interfaces` (unless they couldn't be listed), that the bind addresses, including those of the
+/// metrics endpoint and overlay server, are free, that the excluded host resolves, that network
+/// logs can be written under a usable plugin version, that the parser's JSON file can be written,
+/// and that no interval is 0. Returns every problem found.
+pub fn validate(config: &Config, interfaces: Option<Vec<String>>) -> Vec<String> {
+    let mut problems = Vec::new();
+    let net_config = &config.net_config;
+    let mem_config = &config.mem_config;
+
+    match interfaces {
+        Some(interfaces) => {
+            if let Some(interface) = net_config.interface.as_ref().filter(|interface| !interfaces.contains(interface)) {
+                problems.push(format!("net_config.interface \"{}\" doesn't exist. Capture interfaces are: {}", interface, interfaces.join(", ")));
+            }
+        },
+        None => problems.push("Unable to list capture interfaces. Are you root?".to_string()),
+    }
+
+    if let Err(e) = resolve(&net_config.bind_address).and_then(|_| TcpListener::bind(&net_config.bind_address).map_err(|e| e.to_string())) {
+        problems.push(format!("net_config.bind_address \"{}\" can't be bound: {}", net_config.bind_address, e));
+    }
+    if let Err(e) = resolve(&mem_config.bind_address).and_then(|_| UdpSocket::bind(&mem_config.bind_address).map_err(|e| e.to_string())) {
+        problems.push(format!("mem_config.bind_address \"{}\" can't be bound: {}", mem_config.bind_address, e));
+    }
+    let servers = [("metrics", config.metrics.as_ref().map(|metrics| &metrics.bind_address)),
+        ("overlay", config.overlay.as_ref().map(|overlay| &overlay.bind_address))];
+    for (name, bind_address) in servers.iter().filter_map(|(name, address)| address.map(|address| (name, address))) {
+        if let Err(e) = resolve(bind_address).and_then(|_| TcpListener::bind(bind_address).map_err(|e| e.to_string())) {
+            problems.push(format!("{}.bind_address \"{}\" can't be bound: {}", name, bind_address, e));
+        }
+    }
+    if let Some(hostname_exclude) = net_config.hostname_exclude.as_ref() {
+        if let Err(e) = (hostname_exclude.as_str(), 0).to_socket_addrs() {
+            problems.push(format!("net_config.hostname_exclude \"{}\" doesn't resolve: {}", hostname_exclude, e));
+        }
+    }
+
+    if let Some(network_log) = config.network_log.as_ref() {
+        if let Err(e) = network_log::check_directory(&network_log.directory) {
+            problems.push(format!("network_log.directory \"{}\" can't hold logs: {}", network_log.directory.display(), e));
+        }
+        if let Err(e) = network_log::check_plugin_version(&network_log.plugin_version) {
+            problems.push(format!("network_log.plugin_version \"{}\" isn't usable: {}", network_log.plugin_version, e));
+        }
+    }
+    if let Some(parser) = config.parser.as_ref() {
+        if let Some(json_file) = parser.json_file.as_ref() {
+            if let Err(e) = parser::check_json_file(json_file) {
+                problems.push(format!("parser.json_file \"{}\" can't be written: {}", json_file.display(), e));
+            }
+        }
+        for (name, seconds) in &[("idle_timeout_s", parser.idle_timeout_s), ("interval_s", parser.interval_s)] {
+            if *seconds == 0 {
+                problems.push(format!("parser.{} must be at least 1", name));
+            }
+        }
+    }
+
+    let rates = &mem_config.tick_rates;
+    for (name, ms) in &[("server_time_ms", rates.server_time_ms), ("target_ms", rates.target_ms), ("mobs_ms", rates.mobs_ms),
+            ("party_list_ms", rates.party_list_ms), ("zone_ms", rates.zone_ms)] {
+        if *ms == 0 {
+            problems.push(format!("mem_config.tick_rates.{} must be at least 1", name));
+        }
+    }
+    if rates.adaptive && rates.idle_multiplier == 0 {
+        problems.push("mem_config.tick_rates.idle_multiplier must be at least 1".to_string());
+    }
+    problems
+}
+
+/// Checks that `address` is an address and port before trying to bind it, for a clearer message.
+fn resolve(address: &str) -> Result<(), String> {
+    match address.to_socket_addrs() {
+        Ok(mut addresses) => addresses.next().map(|_| ()).ok_or_else(|| "it resolves to nothing".to_string()),
+        Err(_) => Err("expected an address and port, e.g. 0.0.0.0:54992".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod validate_tests {
+    use crate::validate::*;
+
+    fn config(value: Value) -> Config {
+        Config {
+            net_config: serde_json::from_value(value["net_config"].clone()).unwrap(),
+            mem_config: serde_json::from_value(value["mem_config"].clone()).unwrap(),
+            log: LogConfig::default(),
+            metrics: serde_json::from_value(value["metrics"].clone()).unwrap(),
+            overlay: serde_json::from_value(value["overlay"].clone()).unwrap(),
+            network_log: serde_json::from_value(value["network_log"].clone()).unwrap(),
+            parser: serde_json::from_value(value["parser"].clone()).unwrap(),
+        }
+    }
+
+    #[test]
+    fn report_every_section() {
+        let path = std::env::temp_dir().join(format!("validate_test_{}.json", std::process::id()));
+        std::fs::write(&path, r#"{"net_config": {"interface": "eth0"}, "mem_config": {"bind_address": "0.0.0.0:7262", "compression": ["brotli"]}}"#).unwrap();
+        let problems = read_config(&path).err().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].starts_with("net_config: missing field"));
+        assert!(problems[1].starts_with("mem_config: unknown variant `brotli`"));
+    }
+
+    #[test]
+    fn find_unusable_settings() {
+        let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+        let taken = taken.local_addr().unwrap().to_string();
+        let config = config(serde_json::json!({
+            "net_config": {"interface": "enp8s0", "hostname_exclude": "localhost", "bind_address": taken},
+            "mem_config": {"bind_address": "7262", "tick_rates": {"mobs_ms": 0}},
+            "metrics": {"bind_address": "localhost"},
+            "overlay": {"bind_address": taken},
+            "network_log": {"directory": "/proc/version", "plugin_version": "latest"},
+            "parser": {"idle_timeout_s": 0, "json_file": "/proc/version/encounter.json"},
+        }));
+        let problems = validate(&config, Some(vec!["lo".to_string(), "eth0".to_string()]));
+        assert_eq!(problems, vec![
+            "net_config.interface \"enp8s0\" doesn't exist. Capture interfaces are: lo, eth0".to_string(),
+            format!("net_config.bind_address \"{}\" can't be bound: Address already in use (os error 98)", taken),
+            "mem_config.bind_address \"7262\" can't be bound: expected an address and port, e.g. 0.0.0.0:54992".to_string(),
+            "metrics.bind_address \"localhost\" can't be bound: expected an address and port, e.g. 0.0.0.0:54992".to_string(),
+            format!("overlay.bind_address \"{}\" can't be bound: Address already in use (os error 98)", taken),
+            "network_log.directory \"/proc/version\" can't hold logs: it isn't a directory".to_string(),
+            "network_log.plugin_version \"latest\" isn't usable: expected the FFXIV_ACT_Plugin's version, e.g. 2.7.4.0".to_string(),
+            "parser.json_file \"/proc/version/encounter.json\" can't be written: /proc/version isn't a directory".to_string(),
+            "parser.idle_timeout_s must be at least 1".to_string(),
+            "mem_config.tick_rates.mobs_ms must be at least 1".to_string(),
+        ]);
+
+        let config = Config {
+            net_config: NetConfig { interface: None, bind_address: "127.0.0.1:0".to_string(), ..config.net_config },
+            mem_config: MemConfig { bind_address: "127.0.0.1:0".to_string(), tick_rates: Default::default(), ..config.mem_config },
+            log: config.log,
+            metrics: None,
+            overlay: None,
+            network_log: None,
+            parser: Some(ParserConfig::default()),
+        };
+        assert_eq!(validate(&config, Some(vec!["eth0".to_string()])), Vec::<String>::new());
+    }
+}